@@ -0,0 +1,26 @@
+use fda_calendar_scraper::fda_scraper::{self, ScrapePredicates};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 200;
+
+//cargo bench, compares the streaming row parser with building the whole document first. Both read the page from a
+//string in memory, so this is their time per parse, not how much memory they use
+fn main() {
+    let contents = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+
+    let dom = time_parse(|| fda_scraper::parse_html_dom(&contents, &ScrapePredicates::new()).unwrap());
+    let streaming = time_parse(|| fda_scraper::parse_html(&contents, &ScrapePredicates::new()).unwrap());
+
+    println!("dom parse:       {} micros/iteration", (dom / ITERATIONS).as_micros());
+    println!("streaming parse: {} micros/iteration", (streaming / ITERATIONS).as_micros());
+}
+
+fn time_parse<T, F: Fn() -> T>(parse: F) -> Duration {
+    parse(); //warm up
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        parse();
+    }
+    start.elapsed()
+}
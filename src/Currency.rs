@@ -1,7 +1,9 @@
 use std::{fmt, error};
+use std::ops::{Mul, Sub};
+use std::convert::TryFrom;
 use crate::currency::USDParseError::{NoDollarSign, InvalidStructure, DecimalWithInsufficientCents};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct USD {
     is_positive: bool,
     cents: u64,
@@ -71,6 +73,57 @@ impl USD {
             Err(InvalidStructure(usd_original.to_string()))
         }
     }
+
+    //Amounts like cash positions and market caps are shown shortened, e.g. $48.2M or $1.05B
+    pub fn from_abbreviated(usd_original: &str) -> Result<USD, USDParseError> {
+        let zeros = match usd_original.chars().last() {
+            Some('K') | Some('k') => 3,
+            Some('M') | Some('m') => 6,
+            Some('B') | Some('b') => 9,
+            _ => return USD::new(usd_original)
+        };
+        let usd = &usd_original[..usd_original.len() - 1];
+
+        let mut split = usd.splitn(2, ".");
+        let dollars = split.next().unwrap_or("");
+        let fraction = split.next().unwrap_or("");
+        if fraction.len() > zeros + 2 {
+            return Err(InvalidStructure(usd_original.to_string()))
+        }
+
+        //Move the decimal point over by the abbreviation's zeros, whatever's left over becomes cents
+        let padded_fraction = format!("{:0<width$}", fraction, width = zeros + 2);
+        let (shifted, cents) = padded_fraction.split_at(zeros);
+        USD::new(&format!("{}{}.{}", dollars, shifted, cents))
+            .map_err(|_| InvalidStructure(usd_original.to_string()))
+    }
+}
+
+//Arithmetic goes through signed cents so a result can cross zero, and is None when the result doesn't fit
+impl USD {
+    pub(crate) fn signed_cents(&self) -> i128 {
+        if self.is_positive { i128::from(self.cents) } else { -i128::from(self.cents) }
+    }
+
+    fn from_signed_cents(cents: i128) -> Option<USD> {
+        Some(USD { is_positive: cents >= 0, cents: u64::try_from(cents.unsigned_abs()).ok()? })
+    }
+}
+
+impl<'a> Mul<u64> for &'a USD {
+    type Output = Option<USD>;
+
+    fn mul(self, quantity: u64) -> Option<USD> {
+        USD::from_signed_cents(self.signed_cents().checked_mul(i128::from(quantity))?)
+    }
+}
+
+impl<'a> Sub for &'a USD {
+    type Output = Option<USD>;
+
+    fn sub(self, other: &USD) -> Option<USD> {
+        USD::from_signed_cents(self.signed_cents() - other.signed_cents())
+    }
 }
 
 impl error::Error for USDParseError {
@@ -211,6 +264,29 @@ use super::*;
         assert_eq!(USD::new("$1.00.33").err().unwrap(), USDParseError::InvalidStructure("$1.00.33".to_string()));
     }
 
+    #[test]
+    fn abbreviated() {
+        assert_eq!(USD::from_abbreviated("$48.2M").unwrap(), USD::new("$48200000").unwrap());
+        assert_eq!(USD::from_abbreviated("$1.05B").unwrap(), USD::new("$1050000000").unwrap());
+        assert_eq!(USD::from_abbreviated("-$750K").unwrap(), USD::new("-$750000").unwrap());
+        assert_eq!(USD::from_abbreviated("$1.23456789M").unwrap(), USD::new("$1234567.89").unwrap());
+        assert_eq!(USD::from_abbreviated("$3.50").unwrap(), USD::new("$3.50").unwrap());
+        assert_eq!(USD::from_abbreviated("$1.234567891M").err().unwrap(), USDParseError::InvalidStructure("$1.234567891M".to_string()));
+        assert_eq!(USD::from_abbreviated("48.2M").err().unwrap(), USDParseError::InvalidStructure("48.2M".to_string()));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let price = USD::new("$1.26").unwrap();
+        assert_eq!(&price * 1000, Some(USD::new("$1260").unwrap()));
+        assert_eq!(&price - &USD::new("$1.50").unwrap(), Some(USD::new("-$.24").unwrap()));
+        assert_eq!(&USD::new("-$.24").unwrap() - &USD::new("-$1.00").unwrap(), Some(USD::new("$.76").unwrap()));
+        assert_eq!(&price - &price, Some(USD::new("$0").unwrap()));
+        //too many cents for a u64, rather than wrapping around
+        assert_eq!(&price * u64::MAX, None);
+        assert_eq!(&USD::new("-$100000000000000000").unwrap() - &USD::new("$100000000000000000").unwrap(), None);
+    }
+
     #[test]
     fn comparison() {
         let negative = USD::new("-$.05").unwrap();
@@ -0,0 +1,342 @@
+use chrono::{DateTime, Utc, NaiveDate, Duration};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Sha256, Digest};
+use log::info;
+use std::collections::HashSet;
+use std::fmt;
+use std::error;
+use std::io;
+use std::io::{Read, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use super::fetcher::FetchedPage;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    IoError(PathBuf, io::Error),
+    MalformedIndexLine(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArchiveError::IoError(ref path, ref e) => write!(f, "Archive {:?}: {}", path, e),
+            ArchiveError::MalformedIndexLine(ref line) => write!(f, "Malformed archive index line {:?}", line),
+        }
+    }
+}
+
+impl error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ArchiveError::IoError(_, ref e) => Some(e),
+            ArchiveError::MalformedIndexLine(_) => None,
+        }
+    }
+}
+
+//Every download gets an index line, identical pages share one compressed blob named after their sha256
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ArchiveEntry {
+    pub fetched_at: DateTime<Utc>,
+    pub hash: String,
+    pub url: String,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArchivedPage {
+    pub entry: ArchiveEntry,
+    //true when the previous download of the same url had the same content
+    pub unchanged: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    max_age: Option<Duration>,
+    max_entries: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy { max_age: Option::None, max_entries: Option::None }
+    }
+
+    pub fn set_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Option::Some(max_age);
+        self
+    }
+
+    pub fn set_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Option::Some(max_entries);
+        self
+    }
+}
+
+pub struct PageArchive {
+    archive_dir: PathBuf,
+    retention: RetentionPolicy,
+    //Downloads stored while read only, with their bodies. Listed and loaded along with the archived ones but never written
+    unwritten: Option<Mutex<Vec<(ArchiveEntry, String)>>>,
+}
+
+const INDEX_FILE_NAME: &str = "index.tsv";
+
+impl PageArchive {
+    pub fn new(archive_dir: &Path, retention: RetentionPolicy) -> PageArchive {
+        PageArchive { archive_dir: archive_dir.to_owned(), retention, unwritten: Option::None }
+    }
+
+    //For a dry run, which has to leave the archive as it found it. The run still sees its own download, as
+    //storing keeps it in memory, and nothing is pruned
+    pub fn set_read_only(mut self) -> Self {
+        self.unwritten = Option::Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.unwritten.is_some()
+    }
+
+    //For anything derived from the downloads that's kept alongside them
+    pub fn archive_dir(&self) -> &Path {
+        &self.archive_dir
+    }
+
+    pub fn store(&self, page: &FetchedPage) -> Result<ArchivedPage, ArchiveError> {
+        if let Some(unwritten) = &self.unwritten {
+            let entry = ArchiveEntry { fetched_at: page.fetched_at, hash: content_hash(&page.body), url: page.url.clone() };
+            let unchanged = self.is_unchanged(&entry)?;
+            unwritten.lock().unwrap_or_else(|x| x.into_inner()).push((entry.clone(), page.body.clone()));
+            return Ok(ArchivedPage { entry, unchanged });
+        }
+        fs::create_dir_all(&self.archive_dir).map_err(|x| ArchiveError::IoError(self.archive_dir.clone(), x))?;
+
+        let hash = content_hash(&page.body);
+        let blob_path = self.blob_path(&hash);
+        if !blob_path.exists() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(page.body.as_bytes())
+                .and_then(|_| encoder.finish())
+                .and_then(|compressed| fs::write(&blob_path, compressed))
+                .map_err(|x| ArchiveError::IoError(blob_path.clone(), x))?;
+        }
+
+        let entry = ArchiveEntry { fetched_at: page.fetched_at, hash, url: page.url.clone() };
+        let unchanged = self.is_unchanged(&entry)?;
+        let index_path = self.archive_dir.join(INDEX_FILE_NAME);
+        fs::OpenOptions::new().create(true).append(true).open(&index_path)
+            .and_then(|mut index| index.write_all(index_line(&entry).as_bytes()))
+            .map_err(|x| ArchiveError::IoError(index_path.clone(), x))?;
+
+        //retention is measured back from the newest download
+        self.prune(page.fetched_at)?;
+        Ok(ArchivedPage { entry, unchanged })
+    }
+
+    //Whether the previous download of the same url had the same content
+    fn is_unchanged(&self, entry: &ArchiveEntry) -> Result<bool, ArchiveError> {
+        Ok(self.entries()?.iter().rev()
+            .find(|x| x.url == entry.url)
+            .map_or(false, |previous| previous.hash == entry.hash))
+    }
+
+    pub fn load(&self, entry: &ArchiveEntry) -> Result<String, ArchiveError> {
+        if let Some(unwritten) = &self.unwritten {
+            if let Some((_, body)) = unwritten.lock().unwrap_or_else(|x| x.into_inner()).iter().find(|(x, _)| x.hash == entry.hash) {
+                return Ok(body.clone());
+            }
+        }
+        let blob_path = self.blob_path(&entry.hash);
+        let mut body = String::new();
+        fs::File::open(&blob_path)
+            .and_then(|blob| GzDecoder::new(blob).read_to_string(&mut body))
+            .map_err(|x| ArchiveError::IoError(blob_path, x))?;
+        Ok(body)
+    }
+
+    //Oldest first, as they were downloaded
+    pub fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        let index_path = self.archive_dir.join(INDEX_FILE_NAME);
+        let index = match fs::read_to_string(&index_path) {
+            Ok(index) => index,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ArchiveError::IoError(index_path, e)),
+        };
+        let mut entries = index.lines().filter(|x| !x.is_empty()).map(parse_index_line).collect::<Result<Vec<ArchiveEntry>, ArchiveError>>()?;
+        if let Some(unwritten) = &self.unwritten {
+            entries.extend(unwritten.lock().unwrap_or_else(|x| x.into_inner()).iter().map(|(x, _)| x.clone()));
+        }
+        Ok(entries)
+    }
+
+    pub fn entries_since(&self, since: NaiveDate) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        Ok(self.entries()?.into_iter().filter(|x| x.fetched_at.naive_utc().date() >= since).collect())
+    }
+
+    //Drops index lines outside the retention policy, then any blob no remaining line refers to
+    pub fn prune(&self, now: DateTime<Utc>) -> Result<usize, ArchiveError> {
+        if self.is_read_only() {
+            return Ok(0);
+        }
+        let entries = self.entries()?;
+        let mut kept: Vec<&ArchiveEntry> = entries.iter()
+            .filter(|x| self.retention.max_age.map_or(true, |max_age| now.signed_duration_since(x.fetched_at) <= max_age))
+            .collect();
+        if let Some(max_entries) = self.retention.max_entries {
+            let excess = kept.len().saturating_sub(max_entries);
+            kept.drain(..excess);
+        }
+
+        let pruned = entries.len() - kept.len();
+        if pruned == 0 {
+            return Ok(0);
+        }
+
+        let index_path = self.archive_dir.join(INDEX_FILE_NAME);
+        let index: String = kept.iter().map(|x| index_line(x)).collect();
+        fs::write(&index_path, index).map_err(|x| ArchiveError::IoError(index_path, x))?;
+
+        let kept_hashes: HashSet<&str> = kept.iter().map(|x| x.hash.as_str()).collect();
+        for removed in entries.iter().filter(|x| !kept_hashes.contains(x.hash.as_str())) {
+            let blob_path = self.blob_path(&removed.hash);
+            if blob_path.exists() {
+                fs::remove_file(&blob_path).map_err(|x| ArchiveError::IoError(blob_path, x))?;
+            }
+        }
+        info!("Pruned {} archive entries", pruned);
+        Ok(pruned)
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.archive_dir.join(format!("{}.html.gz", hash))
+    }
+}
+
+pub fn content_hash(body: &str) -> String {
+    Sha256::digest(body.as_bytes()).iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn index_line(entry: &ArchiveEntry) -> String {
+    format!("{}\t{}\t{}\n", entry.fetched_at.to_rfc3339(), entry.hash, entry.url)
+}
+
+fn parse_index_line(line: &str) -> Result<ArchiveEntry, ArchiveError> {
+    let malformed = || ArchiveError::MalformedIndexLine(line.to_owned());
+    let mut fields = line.splitn(3, '\t');
+    let fetched_at = fields.next()
+        .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+        .ok_or_else(malformed)?
+        .with_timezone(&Utc);
+    let hash = fields.next().ok_or_else(malformed)?.to_owned();
+    let url = fields.next().ok_or_else(malformed)?.to_owned();
+    Ok(ArchiveEntry { fetched_at, hash, url })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(body: &str, fetched_at: &str) -> FetchedPage {
+        FetchedPage {
+            url: "https://www.biopharmcatalyst.com/calendars/fda-calendar".to_string(),
+            status: 200,
+            headers: Vec::new(),
+            fetched_at: DateTime::parse_from_rfc3339(fetched_at).unwrap().with_timezone(&Utc),
+            body: body.to_string(),
+        }
+    }
+
+    fn blob_count(archive_dir: &Path) -> usize {
+        fs::read_dir(archive_dir).unwrap().filter(|x| x.as_ref().unwrap().path().to_string_lossy().ends_with(".html.gz")).count()
+    }
+
+    #[test]
+    fn store_and_load() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+
+        let archived = archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        assert!(!archived.unchanged);
+        assert_eq!(archived.entry.hash, content_hash("<html>first</html>"));
+        assert_eq!(archive.load(&archived.entry).unwrap(), "<html>first</html>");
+        assert_eq!(archive.entries().unwrap(), vec![archived.entry]);
+    }
+
+    #[test]
+    fn read_only_archive_unwritten() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+        let first = archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        let index = fs::read_to_string(archive_dir.path().join(INDEX_FILE_NAME)).unwrap();
+
+        let read_only = PageArchive::new(archive_dir.path(), RetentionPolicy::new().set_max_entries(1)).set_read_only();
+        let second = read_only.store(&page("<html>second</html>", "2019-05-02T12:00:00Z")).unwrap();
+        assert!(!second.unchanged);
+        assert!(read_only.store(&page("<html>second</html>", "2019-05-03T12:00:00Z")).unwrap().unchanged);
+        assert_eq!(read_only.entries().unwrap().len(), 3);
+        assert_eq!(read_only.load(&second.entry).unwrap(), "<html>second</html>");
+        assert_eq!(read_only.load(&first.entry).unwrap(), "<html>first</html>");
+        assert_eq!(read_only.prune(Utc::now()).unwrap(), 0);
+
+        assert_eq!(fs::read_to_string(archive_dir.path().join(INDEX_FILE_NAME)).unwrap(), index);
+        assert_eq!(blob_count(archive_dir.path()), 1);
+        assert_eq!(archive.entries().unwrap(), vec![first.entry]);
+    }
+
+    #[test]
+    fn identical_pages_share_a_blob() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+
+        archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        assert!(archive.store(&page("<html>first</html>", "2019-05-02T12:00:00Z")).unwrap().unchanged);
+        assert!(!archive.store(&page("<html>second</html>", "2019-05-03T12:00:00Z")).unwrap().unchanged);
+        assert!(!archive.store(&page("<html>first</html>", "2019-05-04T12:00:00Z")).unwrap().unchanged);
+
+        assert_eq!(archive.entries().unwrap().len(), 4);
+        assert_eq!(blob_count(archive_dir.path()), 2);
+    }
+
+    #[test]
+    fn entries_since() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+
+        archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        let second = archive.store(&page("<html>second</html>", "2019-05-02T00:30:00Z")).unwrap();
+
+        assert_eq!(archive.entries_since(NaiveDate::from_ymd(2019, 5, 2)).unwrap(), vec![second.entry]);
+    }
+
+    #[test]
+    fn prune_by_count_removes_unreferenced_blobs() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new().set_max_entries(2));
+
+        archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        archive.store(&page("<html>second</html>", "2019-05-02T12:00:00Z")).unwrap();
+        archive.store(&page("<html>second</html>", "2019-05-03T12:00:00Z")).unwrap();
+
+        let remaining = archive.entries().unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.iter().all(|x| x.hash == content_hash("<html>second</html>")));
+        assert_eq!(blob_count(archive_dir.path()), 1);
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new().set_max_age(Duration::days(30)));
+
+        archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        archive.store(&page("<html>second</html>", "2019-05-20T12:00:00Z")).unwrap();
+
+        let now = DateTime::parse_from_rfc3339("2019-06-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(archive.prune(now).unwrap(), 1);
+        assert_eq!(archive.entries().unwrap().len(), 1);
+        assert_eq!(blob_count(archive_dir.path()), 1);
+    }
+}
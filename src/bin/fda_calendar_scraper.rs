@@ -1,71 +1,454 @@
-use lettre::{SmtpClient, Transport, ClientSecurity, ClientTlsParameters, smtp};
-use lettre_email::Email;
-use native_tls::TlsConnector;
-use lettre::smtp::authentication::{Mechanism, Credentials};
 use std::error::Error;
-use log::{info, error, LevelFilter};
+use log::{info, Level};
+use serde_json::{json, Value};
 use std::env;
-use chrono::Utc;
-use fda_calendar_scraper::{currency, fda_scraper, fda_scraper::ScrapedCatalysts};
-use askama::Template;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::net::{TcpListener, SocketAddr, ToSocketAddrs};
+use std::thread;
+use std::process;
+use std::panic::{self, AssertUnwindSafe};
+use chrono::{Utc, Local, NaiveDate};
+use fda_calendar_scraper::{currency, fda_scraper};
+use fda_calendar_scraper::fda_scraper::{ScrapePredicates, Grouping};
+use fda_calendar_scraper::scoring::DefaultScorer;
+use fda_calendar_scraper::taxonomy::Taxonomy;
+use fda_calendar_scraper::theme::Theme;
+use fda_calendar_scraper::fetcher::{Fetcher, FetchError, RecordingFetcher, ReplayFetcher};
+use fda_calendar_scraper::polite_fetcher::{PoliteFetcher, CrawlPolicy};
+use fda_calendar_scraper::session_fetcher::{SessionFetcher, SessionConfig};
+use fda_calendar_scraper::company::{Enricher, CompanyCache};
+use fda_calendar_scraper::archive::{PageArchive, RetentionPolicy};
+use fda_calendar_scraper::notifier::{Notifier, EmailNotifier, Preview};
+use fda_calendar_scraper::ledger::{LedgeredNotifier, SentLedger};
+use fda_calendar_scraper::watchlist::Watchlist;
+use fda_calendar_scraper::price_history::PriceHistory;
+use fda_calendar_scraper::digest::{self, DigestMode, Schedule};
+use fda_calendar_scraper::report::Report;
+use fda_calendar_scraper::user_template::UserTemplate;
+use fda_calendar_scraper::fda_scraper::ScrapeError;
+use fda_calendar_scraper::error_report;
+use fda_calendar_scraper::metrics;
+use fda_calendar_scraper::logging::{self, LogFormat};
 use std::time::SystemTime;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
 use currency::USD;
 use time::Duration;
 
 fn main() {
+    //LOG_FORMAT is text (the default) or json, for one JSON object per line
+    logging::init(setting("LOG_FORMAT", "text or json", LogFormat::from_name).unwrap_or(LogFormat::Text));
+
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("reparse") => reparse(&args[2..]),
+        Some("daemon") => daemon(&Settings::from_env()),
+        _ => {
+            run(&Settings::from_env());
+            //METRICS_TEXTFILE is where a one-shot run leaves its metrics, for node_exporter's textfile collector
+            if let Ok(textfile) = env::var("METRICS_TEXTFILE") {
+                if let Err(err) = metrics::registry().write_textfile(&PathBuf::from(textfile)) {
+                    log_failure("metrics_write_failed", &err, &[])
+                }
+            }
+        }
+    }
+}
+
+//An error event, with the error and its cause as fields alongside the details
+fn log_failure(name: &str, err: &dyn Error, details: &[(&str, Value)]) {
+    let mut fields = details.to_vec();
+    fields.push(("error", json!(err.to_string())));
+    fields.push(("cause", json!(err.source().map(|x| x.to_string()))));
+    logging::event(module_path!(), Level::Error, name, &fields);
+}
+
+//Settings are checked before anything is fetched, so a typo stops the run with a message rather than a panic partway through
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2)
+}
+
+//None when the variable isn't set, exits naming it when it's set to something that can't be used
+fn setting<T>(name: &str, accepted: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match parse(&value) {
+        Some(parsed) => Some(parsed),
+        None => exit_with(&format!("{} is {:?}, it should be {}", name, value, accepted)),
+    }
+}
+
+//What every run is configured with, read once at startup
+struct Settings {
+    digest_mode: DigestMode,
+    //When a mode only sends in its hour, None for every run
+    schedule: Option<Schedule>,
+    grouping: Grouping,
+    theme: Theme,
+    html_template: Option<Arc<UserTemplate>>,
+    price_limit: USD,
+    metrics_address: SocketAddr,
+    interval_minutes: u64,
+    ledger_max_age: Duration,
+    archive_retention: RetentionPolicy,
+    user_agent: String,
+    crawl_contact: Option<String>,
+    requests_per_second: f64,
+    crawl_burst: u32,
+    //When logging in to fetch as a member
+    session: Option<SessionConfig>,
+    enrich_workers: Option<usize>,
+    watchlist: Option<Watchlist>,
+    taxonomy: Option<Taxonomy>,
+}
+
+impl Settings {
+    //DIGEST_MODE is one of standard (the default), weekly, daily or tomorrow, DIGEST_SCHEDULE changes when it sends, e.g. "mon@7".
+    //The daily digest compares against the previous download, so needs ARCHIVE_DIR.
+    //REPORT_GROUPING is one of phase (the default), date, symbol, indication, area, price, score or flat.
+    //REPORT_ORDER=score is how ranking by score was first turned on, and is still read when there's no REPORT_GROUPING.
+    //REPORT_THEME sets the report's colors, font and columns, REPORT_TEMPLATE is a handlebars template replacing the report itself,
+    //see resources/example_report.hbs
+    fn from_env() -> Settings {
+        let digest_mode = setting("DIGEST_MODE", "standard, weekly, daily or tomorrow", DigestMode::from_name).unwrap_or(DigestMode::Standard);
+        if digest_mode == DigestMode::DailyDelta && env::var("ARCHIVE_DIR").is_err() {
+            exit_with("ARCHIVE_DIR must be set for the daily digest");
+        }
+        let grouping_names = "phase, date, symbol, indication, area, price, score or flat";
+        Settings {
+            digest_mode,
+            schedule: setting("DIGEST_SCHEDULE", "a weekday or daily, optionally followed by @ and an hour, e.g. mon@7", Schedule::parse)
+                .or_else(|| digest_mode.default_schedule()),
+            grouping: setting("REPORT_GROUPING", grouping_names, Grouping::from_name)
+                .or_else(|| setting("REPORT_ORDER", grouping_names, Grouping::from_name))
+                .unwrap_or(Grouping::ByPhaseThenDate),
+            theme: env::var("REPORT_THEME").ok()
+                .map(|x| Theme::load(&PathBuf::from(x)).unwrap_or_else(|err| exit_with(&format!("REPORT_THEME: {}", err))))
+                .unwrap_or_default(),
+            html_template: env::var("REPORT_TEMPLATE").ok()
+                .map(|x| Arc::new(UserTemplate::load(&PathBuf::from(x)).unwrap_or_else(|err| exit_with(&format!("REPORT_TEMPLATE: {}", err))))),
+            price_limit: setting("PRICE_LIMIT", "a dollar amount, e.g. $10", |x| USD::new(x).ok())
+                .unwrap_or_else(|| exit_with("PRICE_LIMIT must be set")),
+            metrics_address: setting("METRICS_ADDRESS", "a host and port, e.g. 127.0.0.1:9898", |x| x.to_socket_addrs().ok()?.next())
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 9898))),
+            interval_minutes: setting("DAEMON_INTERVAL_MINUTES", "a whole number of minutes above 0", |x| x.parse().ok().filter(|&x| x > 0))
+                .unwrap_or(60),
+            ledger_max_age: Duration::days(setting("LEDGER_MAX_AGE_DAYS", "a whole number of days", |x| x.parse::<u32>().ok()).map_or(90, i64::from)),
+            archive_retention: archive_retention(),
+            user_agent: setting("USER_AGENT", "printable text", header_value)
+                .unwrap_or_else(|| format!("fda_calendar_scraper/{}", env!("CARGO_PKG_VERSION"))),
+            crawl_contact: setting("CRAWL_CONTACT", "printable text, e.g. an email address", header_value),
+            requests_per_second: setting("CRAWL_REQUESTS_PER_SECOND", "a number above 0, e.g. 0.5",
+                                         |x| x.parse().ok().filter(|x: &f64| x.is_finite() && *x > 0.0)).unwrap_or(1.0),
+            crawl_burst: setting("CRAWL_BURST", "a whole number above 0", |x| x.parse().ok().filter(|&x| x > 0)).unwrap_or(1),
+            session: session_config(),
+            enrich_workers: setting("ENRICH_WORKERS", "a whole number above 0", |x| x.parse().ok().filter(|&x| x > 0)),
+            //WATCHLIST_FILE lists symbols to always report on, along with any position held in them
+            watchlist: env::var("WATCHLIST_FILE").ok()
+                .map(|x| Watchlist::load(&PathBuf::from(x)).unwrap_or_else(|err| exit_with(&format!("WATCHLIST_FILE: {}", err)))),
+            //THERAPEUTIC_AREAS_FILE replaces the built in taxonomy
+            taxonomy: env::var("THERAPEUTIC_AREAS_FILE").ok()
+                .map(|x| Taxonomy::load(&PathBuf::from(x)).unwrap_or_else(|err| exit_with(&format!("THERAPEUTIC_AREAS_FILE: {}", err)))),
+        }
+    }
+}
+
+//BPC_EMAIL and BPC_PASSWORD fetch as a logged in member, whose session is kept in BPC_COOKIE_JAR between runs.
+//The jar is as good as the password, so where it's kept has to be chosen
+fn session_config() -> Option<SessionConfig> {
+    let email = env::var("BPC_EMAIL").ok()?;
+    let login_url = setting("BPC_LOGIN_URL", "a url, e.g. https://www.biopharmcatalyst.com/login",
+                            |x| Url::parse(x).ok().filter(|x| x.has_host()).map(|_| x.to_string()))
+        .unwrap_or_else(|| "https://www.biopharmcatalyst.com/login".to_string());
+    let password = env::var("BPC_PASSWORD").unwrap_or_else(|_| exit_with("BPC_PASSWORD must be set to log in"));
+    let cookie_jar = env::var("BPC_COOKIE_JAR").unwrap_or_else(|_| exit_with("BPC_COOKIE_JAR must be set to log in"));
+    Some(SessionConfig::new(&login_url, &email, &password, &PathBuf::from(cookie_jar)))
+}
+
+//Only what can be sent in a request header
+fn header_value(value: &str) -> Option<String> {
+    HeaderValue::from_str(value).ok().map(|_| value.to_string())
+}
+
+//fda_calendar_scraper daemon runs every DAEMON_INTERVAL_MINUTES, 60 by default, serving metrics at /metrics on METRICS_ADDRESS.
+//DIGEST_SCHEDULE still decides when it's due to send, and LEDGER_FILE keeps the runs after that from sending it again.
+//A run that panics is counted as a "run" error and the next one goes ahead as usual
+fn daemon(settings: &Settings) {
+    let listener = TcpListener::bind(settings.metrics_address)
+        .unwrap_or_else(|err| exit_with(&format!("METRICS_ADDRESS {} can't be listened on: {}", settings.metrics_address, err)));
+    thread::spawn(move || metrics::serve(listener, metrics::registry()));
+    info!("Serving metrics on http://{}/metrics", settings.metrics_address);
+
+    let interval_minutes = settings.interval_minutes;
+    loop {
+        //The settings are only read, so a panic partway through a run can't leave them half changed
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| run(settings))) {
+            //panic! with a message leaves a &str or a String, anything else is logged without one
+            let message = payload.downcast_ref::<&str>().map(|x| x.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned());
+            logging::event(module_path!(), Level::Error, "run_failed", &[("interval_minutes", json!(interval_minutes)), ("panic", json!(message))]);
+            metrics::registry().record_error("run");
+        }
+        thread::sleep(std::time::Duration::from_secs(interval_minutes * 60));
+    }
+}
+
+//Everything logged during the run carries its id
+fn run(settings: &Settings) {
     let overall_start_time = SystemTime::now();
+    let run_id = logging::start_run();
+    metrics::registry().start_run();
+    logging::event(module_path!(), Level::Info, "run_started", &[("run_id", json!(run_id))]);
+    scrape_and_send(settings);
+    if let Ok(overall_duration) = overall_start_time.elapsed() {
+        logging::event(module_path!(), Level::Info, "run_finished", &[("millis", json!(overall_duration.as_millis() as u64))]);
+        metrics::registry().observe_stage("overall", overall_duration);
+    }
+}
+
+fn scrape_and_send(settings: &Settings) {
+    let digest_mode = settings.digest_mode;
+    if !settings.schedule.map_or(true, |x| x.is_due(Local::now().naive_local())) {
+        info!("{:?} isn't due to send yet", digest_mode);
+        return;
+    }
 
-    env_logger::builder()
-        .filter_level(LevelFilter::Info) //defaults to error otherwise
-//        .filter_module("lettre", LevelFilter::Debug)
-//        .filter_module("lettre_email", LevelFilter::Debug)
-        .init();
+    let watchlist = &settings.watchlist;
 
-    let price_limit = USD::new(&env::var("PRICE_LIMIT").unwrap()[..]).unwrap();
+    let mut predicates = digest_mode.predicates(ScrapePredicates::new().set_price_limit(settings.price_limit.clone()), Utc::today().naive_utc());
+    if let Some(watchlist) = watchlist {
+        predicates = predicates.set_always_included(watchlist.symbols());
+    }
+    if let Some(taxonomy) = &settings.taxonomy {
+        predicates = predicates.set_taxonomy(taxonomy.clone());
+    }
+    //THERAPEUTIC_AREAS is a comma separated list of the areas to report on
+    if let Ok(therapeutic_areas) = env::var("THERAPEUTIC_AREAS") {
+        predicates = predicates.set_therapeutic_areas(therapeutic_areas.split(',').map(|x| x.trim().to_string()).collect());
+    }
 
-    let date_limit = (Utc::today() + Duration::days(7)).naive_utc();
+    let address_to_scrape = "https://www.biopharmcatalyst.com/calendars/fda-calendar";
+    let fetcher = match build_fetcher(settings) {
+        Ok(fetcher) => fetcher,
+        Err(err) => return scrape_failed(&ScrapeError::from(err), address_to_scrape),
+    };
+    let archive = build_archive(settings.archive_retention.clone());
 
-    let scrape_results = fda_scraper::do_scraping("https://www.biopharmcatalyst.com/calendars/fda-calendar", price_limit, date_limit);
+    let scrape_results = fda_scraper::do_scraping(fetcher.as_ref(), archive.as_ref(), address_to_scrape, &predicates);
 
+    if scrape_results.is_ok() {
+        metrics::registry().record_success(SystemTime::now());
+    }
     match scrape_results {
-        Ok(scrape_result) => send_email(&scrape_result),
-        Err(err) => error!("Scraping Failed {}. Cause: {:?}", err, err.source())
+        Ok(mut scrape_result) => {
+            if let Some(enricher) = build_enricher(settings, &fetcher) {
+                enricher.enrich(&mut scrape_result, Utc::today().naive_utc());
+            }
+            scrape_result.score_with(&DefaultScorer, Utc::today().naive_utc());
+            let mut report = Report::new(&scrape_result).set_grouping(settings.grouping).set_theme(settings.theme.clone());
+            if let Some(watchlist) = watchlist {
+                report = report.set_holdings(watchlist);
+            }
+            //The archive, which now includes this run's download, gives each row a sparkline of its last 30 days of prices
+            if let Some(archive) = &archive {
+                match PriceHistory::from_archive(archive, Utc::today().naive_utc()) {
+                    Ok(history) => report = report.set_price_history(&history),
+                    Err(err) => log_failure("price_history_failed", &err, &[])
+                }
+            }
+            send_report(settings, &report, archive.as_ref(), &predicates);
+        },
+        Err(err) => scrape_failed(&err, address_to_scrape)
     }
+}
 
-    if let Ok(overall_duration) = overall_start_time.elapsed() {
-        info!("Overall took {} millis", overall_duration.as_millis());
+fn scrape_failed(err: &ScrapeError, address_to_scrape: &str) {
+    log_failure("scrape_failed", err, &[("kind", json!(err.kind()))]);
+    metrics::registry().record_error(err.kind());
+    send_error_report(err, address_to_scrape);
+}
+
+fn send_report(settings: &Settings, report: &Report, archive: Option<&PageArchive>, predicates: &ScrapePredicates) {
+    let digest_mode = settings.digest_mode;
+    let scrape_result = report.catalysts();
+    let notifier = build_notifier(settings);
+    let today = Utc::today().naive_utc();
+    let digests = match digest_mode {
+        DigestMode::Standard => {
+            if let Err(err) = notifier.notify(report) {
+                log_failure("notify_failed", &err, &[]);
+                metrics::registry().record_error("notify");
+            }
+            return;
+        },
+        DigestMode::WeeklyLookahead => digest::weekly_lookahead(scrape_result, today).map(|x| vec![x]),
+        DigestMode::DailyDelta => {
+            let archive = archive.expect("ARCHIVE_DIR is checked at startup for the daily digest");
+            //The same page as last time has no changes, whatever the predicates
+            match digest::unchanged_download(archive) {
+                Ok(true) => {
+                    info!("Calendar unchanged since the previous download, no daily digest to send");
+                    return;
+                },
+                Ok(false) => (),
+                Err(err) => {
+                    log_failure("archive_read_failed", &err, &[]);
+                    return;
+                }
+            }
+            let previous = match digest::previous_download(archive, predicates) {
+                Ok(previous) => previous,
+                Err(err) => {
+                    log_failure("previous_download_failed", &err, &[("kind", json!(err.kind()))]);
+                    return;
+                }
+            };
+            digest::daily_delta(scrape_result, previous.as_ref(), today).map(|x| x.into_iter().collect())
+        },
+        DigestMode::TomorrowReminder => digest::tomorrow_reminders(scrape_result, today),
+    };
+    match digests {
+        Ok(digests) => for a_digest in digests {
+            if let Err(err) = notifier.send_digest(&a_digest) {
+                log_failure("digest_send_failed", &err, &[("subject", json!(a_digest.subject))]);
+                metrics::registry().record_error("notify");
+            }
+        },
+        Err(err) => log_failure("digest_render_failed", &err, &[("mode", json!(format!("{:?}", digest_mode)))])
     }
 }
 
-fn send_email(catalysts: &ScrapedCatalysts) {
-    let email_start_time = SystemTime::now();
+//MAINTAINER_ADDRESS is sent why a scrape failed, so a change to the page's layout is noticed before the next report is missed
+fn send_error_report(scrape_error: &ScrapeError, address_to_scrape: &str) {
+    let maintainer_address = match env::var("MAINTAINER_ADDRESS") {
+        Ok(maintainer_address) => maintainer_address,
+        Err(_) => return,
+    };
+    let notifier = build_email_notifier().set_to_address(&maintainer_address);
+    match error_report::error_report(scrape_error, address_to_scrape, Utc::now()) {
+        Ok(report) => if let Err(err) = notifier.send_digest(&report) {
+            log_failure("error_report_send_failed", &err, &[])
+        },
+        Err(err) => log_failure("error_report_render_failed", &err, &[])
+    }
+}
 
-    let to_address = &env::var("TO_ADDRESS").unwrap()[..];
-    let smtp_server = "smtp.gmail.com";
-    //Can't move these out otherwise someone else looking at env::var wouldn't see them
-    let smtp_username = &env::var("GMAIL_USERNAME").unwrap()[..];
-    let smtp_password = &env::var("GMAIL_PASSWORD").unwrap()[..];
+//LEDGER_FILE keeps track of the alerts already sent, so a rerun doesn't send them again. Alerts sent more than
+//LEDGER_MAX_AGE_DAYS ago, 90 by default, are dropped from it. A dry run isn't kept track of, as nothing was sent
+fn build_notifier(settings: &Settings) -> Box<dyn Notifier> {
+    let mut email_notifier = build_email_notifier();
+    if let Some(html_template) = &settings.html_template {
+        email_notifier = email_notifier.set_html_template(Arc::clone(html_template));
+    }
+    let notifier = Box::new(email_notifier);
+    match env::var("LEDGER_FILE") {
+        Ok(ledger_file) if !is_dry_run() => {
+            let ledger = SentLedger::new(&PathBuf::from(ledger_file)).set_max_age(settings.ledger_max_age);
+            Box::new(LedgeredNotifier::new(notifier, ledger, Utc::today().naive_utc()))
+        },
+        _ => notifier,
+    }
+}
 
-    let subject_date = Utc::now().format("%b %d %Y").to_string();
+//DRY_RUN skips SMTP, writing each email to stdout when it's "-", otherwise as .eml and .html files in that directory.
+//A dry run leaves everything as it found it: the archive, caches and cookie jar are read but not written
+fn is_dry_run() -> bool {
+    env::var("DRY_RUN").is_ok()
+}
 
-    let email = Email::builder()
-        .to(to_address)
-        .from(smtp_username)
-        .subject(format!("Catalyst Update {}", subject_date))
-        .html(catalysts.render().unwrap())
-        .build()
-        .unwrap();
+fn build_email_notifier() -> EmailNotifier {
+    match env::var("DRY_RUN") {
+        Ok(target) => EmailNotifier::preview_from_env(Preview::from_target(&target)),
+        Err(_) => EmailNotifier::from_env(),
+    }
+}
 
-    let security = ClientSecurity::Wrapper(ClientTlsParameters::new(smtp_server.to_string(), TlsConnector::new().unwrap()));
-    let mut mailer = SmtpClient::new((smtp_server, smtp::SUBMISSIONS_PORT), security).unwrap()
-        .authentication_mechanism(Mechanism::Login).credentials(Credentials::new(smtp_username.to_string(), smtp_password.to_string())).transport();
+//FETCH_MODE=record saves every fetched page under FIXTURES_DIR, FETCH_MODE=replay serves them back without the network
+fn build_fetcher(settings: &Settings) -> Result<Arc<dyn Fetcher + Send + Sync>, FetchError> {
+    let fixtures_dir = PathBuf::from(env::var("FIXTURES_DIR").unwrap_or_else(|_| "fixtures".to_string()));
+    Ok(match env::var("FETCH_MODE").unwrap_or_default().as_str() {
+        "record" => Arc::new(RecordingFetcher::new(build_live_fetcher(settings)?, &fixtures_dir)),
+        "replay" => Arc::new(ReplayFetcher::new(&fixtures_dir)),
+        _ => build_live_fetcher(settings)?,
+    })
+}
 
-    // Send the email
-    match mailer.send(email.into()) {
-        Ok(_) => info!("Email Sent"),
-        Err(err) => error!("failed to send email alert: {}. Cause: {:?}", err, err.source())
+//Reading the cookie jar can still fail here, which fails the run like any other fetch
+fn build_live_fetcher(settings: &Settings) -> Result<Arc<dyn Fetcher + Send + Sync>, FetchError> {
+    let polite_fetcher = PoliteFetcher::new(build_crawl_policy(settings))?;
+    let mut config = match &settings.session {
+        Some(config) => config.clone(),
+        None => return Ok(Arc::new(polite_fetcher)),
+    };
+    if is_dry_run() {
+        config = config.set_jar_read_only();
     }
-    if let Ok(email_send_duration) = email_start_time.elapsed() {
-        info!("Sending email took {} millis", email_send_duration.as_millis());
+    Ok(Arc::new(SessionFetcher::new(polite_fetcher, config)?))
+}
+
+//USER_AGENT and CRAWL_CONTACT identify us, CRAWL_REQUESTS_PER_SECOND and CRAWL_BURST limit each host,
+//HTTP_CACHE_DIR keeps pages for conditional requests
+fn build_crawl_policy(settings: &Settings) -> CrawlPolicy {
+    let mut policy = CrawlPolicy::new(&settings.user_agent).set_rate_limit(settings.requests_per_second, settings.crawl_burst);
+    if let Some(contact) = &settings.crawl_contact {
+        policy = policy.set_contact(contact);
     }
-}
\ No newline at end of file
+    if let Ok(cache_dir) = env::var("HTTP_CACHE_DIR") {
+        policy = policy.set_cache_dir(&PathBuf::from(cache_dir));
+        if is_dry_run() {
+            policy = policy.set_cache_read_only();
+        }
+    }
+    policy
+}
+
+//ENRICH_WORKERS turns on fetching each company's page, COMPANY_CACHE_DIR keeps the pages so a company is fetched at most once a day.
+//Requests to the same host are spaced out by the shared fetcher's rate limit
+fn build_enricher(settings: &Settings, fetcher: &Arc<dyn Fetcher + Send + Sync>) -> Option<Enricher> {
+    let mut enricher = Enricher::new(Arc::clone(fetcher)).set_workers(settings.enrich_workers?);
+    if let Ok(cache_dir) = env::var("COMPANY_CACHE_DIR") {
+        let cache = CompanyCache::new(&PathBuf::from(cache_dir));
+        enricher = enricher.set_cache(if is_dry_run() { cache.set_read_only() } else { cache });
+    }
+    Some(enricher)
+}
+
+//ARCHIVE_DIR turns on keeping every downloaded page, ARCHIVE_MAX_AGE_DAYS and ARCHIVE_MAX_ENTRIES bound how many are kept
+fn build_archive(retention: RetentionPolicy) -> Option<PageArchive> {
+    let archive_dir = env::var("ARCHIVE_DIR").ok()?;
+    let archive = PageArchive::new(&PathBuf::from(archive_dir), retention);
+    Some(if is_dry_run() { archive.set_read_only() } else { archive })
+}
+
+fn archive_retention() -> RetentionPolicy {
+    let mut retention = RetentionPolicy::new();
+    if let Some(max_age_days) = setting("ARCHIVE_MAX_AGE_DAYS", "a whole number of days", |x| x.parse::<u32>().ok()) {
+        retention = retention.set_max_age(Duration::days(i64::from(max_age_days)));
+    }
+    if let Some(max_entries) = setting("ARCHIVE_MAX_ENTRIES", "a whole number of downloads", |x| x.parse().ok()) {
+        retention = retention.set_max_entries(max_entries);
+    }
+    retention
+}
+
+//fda_calendar_scraper reparse --since 2019-05-01
+fn reparse(args: &[String]) {
+    let usage = "Usage: fda_calendar_scraper reparse --since <yyyy-mm-dd>";
+    let since = match args {
+        [flag, since] if flag == "--since" => NaiveDate::parse_from_str(since, "%Y-%m-%d")
+            .unwrap_or_else(|_| exit_with(&format!("{:?} isn't a yyyy-mm-dd date. {}", since, usage))),
+        _ => exit_with(usage),
+    };
+    let archive = build_archive(archive_retention()).unwrap_or_else(|| exit_with("ARCHIVE_DIR must be set to reparse"));
+
+    match fda_scraper::reparse_archive(&archive, since) {
+        Ok(reparsed) => for (entry, parsed) in reparsed {
+            match parsed {
+                Ok(catalysts) => info!("Reparsed {} from {}: {} catalysts", entry.hash, entry.fetched_at, catalysts.len()),
+                Err(err) => log_failure("reparse_failed", &err, &[("hash", json!(entry.hash)), ("fetched_at", json!(entry.fetched_at.to_rfc3339()))])
+            }
+        },
+        Err(err) => log_failure("archive_read_failed", &err, &[])
+    }
+}
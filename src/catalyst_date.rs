@@ -0,0 +1,249 @@
+use chrono::{NaiveDate, Datelike};
+use chrono::format::{self, Parsed, StrftimeItems};
+use regex::Regex;
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::fmt;
+use super::note_events;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DatePrecision {
+    Day,
+    Month,
+    Quarter,
+    Half,
+    //early, mid or late in the year
+    PartOfYear,
+    Year,
+}
+
+//The calendar gives exact dates for most rows but "Q3 2019", "2H 2019", "mid-2019" or "TBD" for the rest.
+//Known dates are inclusive ranges, an exact date is a range of a single day.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CatalystDate {
+    Known { start: NaiveDate, end: NaiveDate, precision: DatePrecision },
+    Tbd,
+}
+
+lazy_static! {
+    static ref QUARTER: Regex = Regex::new(r"(?i)^(?:q([1-4])|([1-4])q)[\s-]*(\d{2}|\d{4})$").unwrap();
+    static ref HALF: Regex = Regex::new(r"(?i)^(?:h([12])|([12])h)[\s-]*(\d{2}|\d{4})$").unwrap();
+    static ref PART_OF_YEAR: Regex = Regex::new(r"(?i)^(early|mid|late)[\s-]*(\d{4})$").unwrap();
+    static ref MONTH: Regex = Regex::new(r"(?i-u)^([a-z]{3,9})\.?,?\s+(\d{4})$").unwrap();
+    static ref YEAR: Regex = Regex::new(r"^(\d{4})$").unwrap();
+}
+
+impl CatalystDate {
+    //Anything that isn't one of the fuzzy forms has to be an exact %m/%d/%Y date, which is where the error comes from
+    pub fn new(catalyst_date: &str) -> Result<CatalystDate, chrono::format::ParseError> {
+        let catalyst_date = catalyst_date.trim();
+
+        match catalyst_date.to_uppercase().as_str() {
+            "" | "TBD" | "TBA" | "N/A" => return Ok(CatalystDate::Tbd),
+            _ => ()
+        }
+
+        if let Some(captures) = QUARTER.captures(catalyst_date) {
+            let quarter: u32 = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str().parse().unwrap();
+            let first_month = (quarter - 1) * 3 + 1;
+            return Ok(CatalystDate::months(full_year(&captures[3])?, first_month, first_month + 2, DatePrecision::Quarter));
+        }
+
+        if let Some(captures) = HALF.captures(catalyst_date) {
+            let half: u32 = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str().parse().unwrap();
+            let first_month = (half - 1) * 6 + 1;
+            return Ok(CatalystDate::months(full_year(&captures[3])?, first_month, first_month + 5, DatePrecision::Half));
+        }
+
+        if let Some(captures) = PART_OF_YEAR.captures(catalyst_date) {
+            let (first_month, last_month) = match captures[1].to_lowercase().as_str() {
+                "early" => (1, 4),
+                "mid" => (5, 8),
+                _ => (9, 12),
+            };
+            return Ok(CatalystDate::months(full_year(&captures[2])?, first_month, last_month, DatePrecision::PartOfYear));
+        }
+
+        if let Some(captures) = MONTH.captures(catalyst_date) {
+            if let Some(month) = note_events::month_number(&captures[1]) {
+                return Ok(CatalystDate::months(full_year(&captures[2])?, month, month, DatePrecision::Month));
+            }
+        }
+
+        if let Some(captures) = YEAR.captures(catalyst_date) {
+            return Ok(CatalystDate::months(full_year(&captures[1])?, 1, 12, DatePrecision::Year));
+        }
+
+        NaiveDate::parse_from_str(catalyst_date, "%m/%d/%Y").map(CatalystDate::exact)
+    }
+
+    pub fn exact(date: NaiveDate) -> CatalystDate {
+        CatalystDate::Known { start: date, end: date, precision: DatePrecision::Day }
+    }
+
+    fn months(year: i32, first_month: u32, last_month: u32, precision: DatePrecision) -> CatalystDate {
+        let start = NaiveDate::from_ymd(year, first_month, 1);
+        let end = if last_month == 12 {
+            NaiveDate::from_ymd(year, 12, 31)
+        } else {
+            NaiveDate::from_ymd(year, last_month + 1, 1).pred()
+        };
+        CatalystDate::Known { start, end, precision }
+    }
+
+    //None when the date is still to be determined
+    pub fn start(&self) -> Option<NaiveDate> {
+        match *self {
+            CatalystDate::Known { start, .. } => Some(start),
+            CatalystDate::Tbd => None,
+        }
+    }
+
+    pub fn end(&self) -> Option<NaiveDate> {
+        match *self {
+            CatalystDate::Known { end, .. } => Some(end),
+            CatalystDate::Tbd => None,
+        }
+    }
+}
+
+//The patterns' \d also matches digits from other scripts, which chrono's %Y turns down with a ParseError
+fn full_year(year: &str) -> Result<i32, chrono::format::ParseError> {
+    let mut parsed = Parsed::new();
+    format::parse(&mut parsed, year, StrftimeItems::new("%Y"))?;
+    let year = parsed.year.unwrap_or_default();
+    Ok(if year < 100 { 2000 + year } else { year })
+}
+
+//Earliest possible day first, then the narrower range, with TBD after everything that has a date
+impl Ord for CatalystDate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (CatalystDate::Tbd, CatalystDate::Tbd) => Ordering::Equal,
+            (CatalystDate::Tbd, _) => Ordering::Greater,
+            (_, CatalystDate::Tbd) => Ordering::Less,
+            (CatalystDate::Known { start, end, .. }, CatalystDate::Known { start: other_start, end: other_end, .. }) =>
+                start.cmp(other_start).then_with(|| end.cmp(other_end)),
+        }
+    }
+}
+
+impl PartialOrd for CatalystDate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for CatalystDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CatalystDate::Tbd => write!(f, "TBD"),
+            CatalystDate::Known { start, precision, .. } => match precision {
+                DatePrecision::Day => write!(f, "{}", start),
+                DatePrecision::Month => write!(f, "{}", start.format("%b %Y")),
+                DatePrecision::Quarter => write!(f, "Q{} {}", (start.month() - 1) / 3 + 1, start.year()),
+                DatePrecision::Half => write!(f, "{}H {}", (start.month() - 1) / 6 + 1, start.year()),
+                DatePrecision::PartOfYear => {
+                    let part = match start.month() {
+                        1 => "early",
+                        5 => "mid",
+                        _ => "late",
+                    };
+                    write!(f, "{}-{}", part, start.year())
+                },
+                DatePrecision::Year => write!(f, "{}", start.year()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(ymd: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap()
+    }
+
+    fn known(start: &str, end: &str, precision: DatePrecision) -> CatalystDate {
+        CatalystDate::Known { start: date(start), end: date(end), precision }
+    }
+
+    #[test]
+    fn exact_date() {
+        assert_eq!(CatalystDate::new("05/02/2019").unwrap(), CatalystDate::exact(date("2019-05-02")));
+    }
+
+    #[test]
+    fn malformed_exact_date() {
+        assert!(CatalystDate::new("05_02_2019").is_err());
+    }
+
+    #[test]
+    fn quarters() {
+        assert_eq!(CatalystDate::new("Q3 2019").unwrap(), known("2019-07-01", "2019-09-30", DatePrecision::Quarter));
+        assert_eq!(CatalystDate::new("4Q19").unwrap(), known("2019-10-01", "2019-12-31", DatePrecision::Quarter));
+    }
+
+    #[test]
+    fn halves() {
+        assert_eq!(CatalystDate::new("2H 2019").unwrap(), known("2019-07-01", "2019-12-31", DatePrecision::Half));
+        assert_eq!(CatalystDate::new("H1 2020").unwrap(), known("2020-01-01", "2020-06-30", DatePrecision::Half));
+    }
+
+    #[test]
+    fn parts_of_year() {
+        assert_eq!(CatalystDate::new("mid-2019").unwrap(), known("2019-05-01", "2019-08-31", DatePrecision::PartOfYear));
+        assert_eq!(CatalystDate::new("Early 2019").unwrap(), known("2019-01-01", "2019-04-30", DatePrecision::PartOfYear));
+        assert_eq!(CatalystDate::new("late 2019").unwrap(), known("2019-09-01", "2019-12-31", DatePrecision::PartOfYear));
+    }
+
+    #[test]
+    fn months_and_years() {
+        assert_eq!(CatalystDate::new("February 2020").unwrap(), known("2020-02-01", "2020-02-29", DatePrecision::Month));
+        assert_eq!(CatalystDate::new("2019").unwrap(), known("2019-01-01", "2019-12-31", DatePrecision::Year));
+    }
+
+    #[test]
+    fn years_in_other_digits() {
+        assert!(CatalystDate::new("Q3 \u{0662}\u{0660}\u{0661}\u{0669}").is_err());
+        assert!(CatalystDate::new("\u{0968}\u{0966}\u{0967}\u{096F}").is_err());
+    }
+
+    #[test]
+    fn months_in_other_letters() {
+        assert!(CatalystDate::new("Ma\u{017F} 2019").is_err());
+        assert!(CatalystDate::new("\u{212A}an 2019").is_err());
+    }
+
+    #[test]
+    fn to_be_determined() {
+        assert_eq!(CatalystDate::new("TBD").unwrap(), CatalystDate::Tbd);
+        assert_eq!(CatalystDate::new("  ").unwrap(), CatalystDate::Tbd);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for original in &["2019-05-02", "May 2019", "Q3 2019", "2H 2019", "mid-2019", "2019", "TBD"] {
+            let parsed = if original.contains('-') && !original.starts_with("mid") {
+                CatalystDate::exact(date(original))
+            } else {
+                CatalystDate::new(original).unwrap()
+            };
+            assert_eq!(&parsed.to_string(), original);
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        let exact = CatalystDate::exact(date("2019-07-01"));
+        let quarter = CatalystDate::new("Q3 2019").unwrap();
+        let half = CatalystDate::new("2H 2019").unwrap();
+        let earlier = CatalystDate::exact(date("2019-06-30"));
+
+        assert!(earlier < exact);
+        assert!(exact < quarter);
+        assert!(quarter < half);
+        assert!(half < CatalystDate::Tbd);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use super::fda_scraper::ParsedRow;
+use super::text;
+
+//What makes a catalyst the same one from run to run: the company, the drug and what it's for.
+//The date and note aren't part of it as they're what changes. Text is normalized and lowercased,
+//so "OpRegen " and "Opregen" are the same drug
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+pub struct CatalystId {
+    pub symbol: String,
+    pub drug: String,
+    pub indication: String,
+}
+
+impl CatalystId {
+    pub fn new(symbol: &str, drug: &str, indication: &str) -> CatalystId {
+        CatalystId { symbol: symbol.trim().to_uppercase(), drug: text::normalize(drug).to_lowercase(), indication: text::normalize(indication).to_lowercase() }
+    }
+
+    pub fn of(row: &ParsedRow) -> CatalystId {
+        CatalystId::new(&row.symbol, &row.drug_name, &row.drug_indication)
+    }
+
+    //The id with only the letters and digits of the drug and indication, so "GWPCARE-2" and "GWPCARE 2" key the same
+    //but "GWPCARE1" and "GWPCARE2", being different trials, don't. The ledger keys alerts on it
+    pub fn key(&self) -> String {
+        let alphanumeric = |x: &str| x.chars().filter(|x| x.is_alphanumeric()).collect::<String>();
+        format!("{}/{}/{}", self.symbol, alphanumeric(&self.drug), alphanumeric(&self.indication))
+    }
+
+    //The same company, drug and indication, differing only in whitespace and punctuation
+    pub fn is_similar(&self, other: &CatalystId) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl fmt::Display for CatalystId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.symbol, self.drug, self.indication)
+    }
+}
+
+//Rows from one run linked to the rows of another
+#[derive(Debug, Eq, PartialEq)]
+pub struct MatchedRows<'a> {
+    //(previous, current)
+    pub matched: Vec<(&'a ParsedRow, &'a ParsedRow)>,
+    //In the current run only
+    pub added: Vec<&'a ParsedRow>,
+    //In the previous run only
+    pub dropped: Vec<&'a ParsedRow>,
+}
+
+type IdComparison = dyn Fn(&CatalystId, &CatalystId) -> bool;
+
+//Each row is matched at most once, first to a row with the same id, then to a similar one.
+//Rows keep the order they're given in
+pub fn match_rows<'a>(previous: &[&'a ParsedRow], current: &[&'a ParsedRow]) -> MatchedRows<'a> {
+    let previous_ids: Vec<CatalystId> = previous.iter().map(|x| CatalystId::of(x)).collect();
+    let mut unmatched: Vec<bool> = vec![true; previous.len()];
+    let mut matches: Vec<Option<usize>> = vec![None; current.len()];
+
+    let passes: [&IdComparison; 2] = [&|a, b| a == b, &|a, b| a.is_similar(b)];
+    for same in passes.iter() {
+        for (index, a_row) in current.iter().enumerate() {
+            if matches[index].is_some() {
+                continue;
+            }
+            let id = CatalystId::of(a_row);
+            if let Some(found) = (0..previous.len()).find(|x| unmatched[*x] && same(&previous_ids[*x], &id)) {
+                unmatched[found] = false;
+                matches[index] = Some(found);
+            }
+        }
+    }
+
+    MatchedRows {
+        matched: current.iter().zip(&matches).filter_map(|(row, found)| found.map(|x| (previous[x], *row))).collect(),
+        added: current.iter().zip(&matches).filter(|(_, found)| found.is_none()).map(|(row, _)| *row).collect(),
+        dropped: previous.iter().zip(&unmatched).filter(|(_, unmatched)| **unmatched).map(|(row, _)| *row).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use std::path::Path;
+
+    #[test]
+    fn normalized_ids() {
+        assert_eq!(CatalystId::new(" btx", "OpRegen ", "Dry age-related macular  degeneration (AMD)"),
+                   CatalystId::new("BTX", "opregen", "dry age-related macular degeneration (amd)"));
+        assert_eq!(CatalystId::new("btx", "OpRegen", "AMD").to_string(), "BTX/opregen/amd");
+        assert_eq!(CatalystId::new("btx", "Op&Regen", "Dry AMD").key(), "BTX/opregen/dryamd");
+        assert_ne!(CatalystId::new("BTX", "OpRegen", "AMD"), CatalystId::new("BTX", "OpRegen", "Glaucoma"));
+    }
+
+    #[test]
+    fn similar_ids() {
+        let id = CatalystId::new("GWPH", "Epidiolex GWPCARE2", "Dravet Syndrome");
+        assert!(id.is_similar(&CatalystId::new("GWPH", "Epidiolex GWPCARE-2", "Dravet syndrome")));
+        assert!(id.is_similar(&CatalystId::new("GWPH", "Epidiolex  GWPCARE 2", "Dravet-Syndrome")));
+        //A different trial, or a different word, is a different catalyst however close the spelling
+        assert!(!id.is_similar(&CatalystId::new("GWPH", "Epidiolex GWPCARE1", "Dravet Syndrome")));
+        assert!(!id.is_similar(&CatalystId::new("GWPH", "Epidiolex GWPCARE2", "Dravet Syndrom")));
+        assert!(!id.is_similar(&CatalystId::new("GWPHX", "Epidiolex GWPCARE2", "Dravet Syndrome")));
+    }
+
+    #[test]
+    fn rows_matched_across_runs() {
+        let previous = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html"), ScrapePredicates::new()).unwrap();
+        let html = std::fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap()
+            .replace("05/02/2019", "05/09/2019")
+            .replace("OpRegen", "OpRegen ")
+            .replace("MicroStat", "Micro-Stat");
+        let current = fda_scraper::parse_html(&html, &ScrapePredicates::new()).unwrap();
+        let previous_rows: Vec<&ParsedRow> = previous.rows().collect();
+        let current_rows: Vec<&ParsedRow> = current.rows().collect();
+
+        let matched = match_rows(&previous_rows, &current_rows);
+        let symbols = |rows: Vec<&ParsedRow>| rows.iter().map(|x| format!("{} {}", x.symbol, x.drug_name)).collect::<Vec<String>>();
+        assert_eq!(symbols(matched.matched.iter().map(|x| x.1).collect()), vec!["BTX OpRegen", "GWPH Epidiolex GWPCARE2", "EYEN Micro-Stat"]);
+        assert!(matched.matched.iter().all(|(was, is)| CatalystId::of(was).is_similar(&CatalystId::of(is))));
+        assert!(matched.added.is_empty());
+        assert_eq!(symbols(matched.dropped), vec!["GWPH Sativex"]);
+    }
+}
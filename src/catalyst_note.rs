@@ -0,0 +1,174 @@
+use chrono::NaiveDate;
+use regex::Regex;
+use lazy_static::lazy_static;
+use std::fmt;
+use super::note_events;
+
+//Declaration order doubles as the order event types are listed in a NoteClassification
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub enum EventType {
+    Pdufa,
+    AdCom,
+    ToplineData,
+    EnrollmentComplete,
+    ConferencePresentation,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Outcome {
+    Met,
+    Missed,
+    Approved,
+    Crl,
+}
+
+//Structured facts pulled out of the free text catalyst note
+#[derive(Debug, Eq, PartialEq, Default)]
+pub struct NoteClassification {
+    pub event_types: Vec<EventType>,
+    pub outcome: Option<Outcome>,
+    pub conferences: Vec<String>,
+    pub embedded_dates: Vec<NaiveDate>,
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match *self {
+            EventType::Pdufa => "PDUFA",
+            EventType::AdCom => "AdCom",
+            EventType::ToplineData => "Topline Data",
+            EventType::EnrollmentComplete => "Enrollment Complete",
+            EventType::ConferencePresentation => "Conference Presentation",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match *self {
+            Outcome::Met => "Met",
+            Outcome::Missed => "Missed",
+            Outcome::Approved => "Approved",
+            Outcome::Crl => "CRL",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+//Conference acronyms are matched case sensitively so words like "ash" or "ada" in prose don't count
+const KNOWN_CONFERENCES: &[&str] = &[
+    "AACR", "AAN", "AASLD", "ACC", "ACR", "ADA", "AHA", "ARVO", "ASCO", "ASCRS", "ASGCT", "ASH", "ATS",
+    "CROI", "EASL", "ECTRIMS", "EHA", "ENDO", "ERS", "ESC", "ESMO", "EULAR", "IDWeek", "SABCS", "WCLC",
+];
+
+lazy_static! {
+    static ref EVENT_TYPE_RULES: Vec<(EventType, Regex)> = vec![
+        (EventType::Pdufa, Regex::new(r"(?i)\bpdufa\b").unwrap()),
+        (EventType::AdCom, Regex::new(r"(?i)\badcomm?\b|\badvisory committee\b").unwrap()),
+        (EventType::ToplineData, Regex::new(r"(?i)\btop-?line\b|\bdata (?:is |are )?(?:due|expected)\b|\bread-?out\b|\bresults (?:due|expected)\b").unwrap()),
+        (EventType::EnrollmentComplete, Regex::new(r"(?i)\benrol(?:l)?ment\b[^.]*\bcomplet(?:e|ed|ion)\b").unwrap()),
+        (EventType::ConferencePresentation, Regex::new(r"(?i)\bpresent(?:ed|ation|ing)?\b").unwrap()),
+    ];
+
+    static ref OUTCOME_RULES: Vec<(Outcome, Regex)> = vec![
+        (Outcome::Met, Regex::new(r"(?i)\bmet\b").unwrap()),
+        (Outcome::Missed, Regex::new(r"(?i)\bmissed\b|\b(?:did not|failed to) meet\b").unwrap()),
+        (Outcome::Approved, Regex::new(r"(?i)\bapproved\b|\bapproval (?:granted|received)\b").unwrap()),
+        (Outcome::Crl, Regex::new(r"(?i)\bcrl\b|\bcomplete response letter\b").unwrap()),
+    ];
+
+    static ref CONFERENCE: Regex = Regex::new(&format!(r"\b(?:{})\b", KNOWN_CONFERENCES.join("|"))).unwrap();
+}
+
+pub fn classify_note(note: &str) -> NoteClassification {
+    let mut event_types: Vec<EventType> = EVENT_TYPE_RULES.iter()
+        .filter(|(_, rule)| rule.is_match(note))
+        .map(|(event_type, _)| *event_type)
+        .collect();
+
+    let conferences = find_conferences(note);
+    if !conferences.is_empty() && !event_types.contains(&EventType::ConferencePresentation) {
+        event_types.push(EventType::ConferencePresentation);
+    }
+    event_types.sort();
+
+    NoteClassification {
+        event_types,
+        outcome: find_outcome(note),
+        conferences,
+        embedded_dates: note_events::extract_note_events(note).iter().map(|x| x.date).collect(),
+    }
+}
+
+//Notes read chronologically, so the last mentioned outcome is the most recent one
+fn find_outcome(note: &str) -> Option<Outcome> {
+    OUTCOME_RULES.iter()
+        .filter_map(|(outcome, rule)| rule.find_iter(note).last().map(|found| (found.start(), *outcome)))
+        .max_by_key(|(position, _)| *position)
+        .map(|(_, outcome)| outcome)
+}
+
+fn find_conferences(note: &str) -> Vec<String> {
+    let mut conferences: Vec<String> = Vec::new();
+    for found in CONFERENCE.find_iter(note) {
+        let conference = found.as_str().to_owned();
+        if !conferences.contains(&conference) {
+            conferences.push(conference);
+        }
+    }
+    conferences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(ymd: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn enrollment_and_conference() {
+        let actual = classify_note("Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.");
+        assert_eq!(actual, NoteClassification {
+            event_types: vec![EventType::ToplineData, EventType::EnrollmentComplete, EventType::ConferencePresentation],
+            outcome: None,
+            conferences: vec!["ARVO".to_string()],
+            embedded_dates: vec![date("2019-05-02")],
+        });
+    }
+
+    #[test]
+    fn met_endpoint_with_date_range() {
+        let actual = classify_note("Phase 3 trial met primary endpoint - January 31, 2019. Data from second trial also met primary endpoint - February 25, 2019. Detailed data due at (ASCRS) meeting May 3-7, 2019.");
+        assert_eq!(actual.outcome, Some(Outcome::Met));
+        assert_eq!(actual.conferences, vec!["ASCRS".to_string()]);
+        assert_eq!(actual.embedded_dates, vec![date("2019-01-31"), date("2019-02-25"), date("2019-05-03")]);
+    }
+
+    #[test]
+    fn pdufa_and_adcom() {
+        let actual = classify_note("PDUFA date of June 12, 2019. Advisory committee meeting not planned.");
+        assert_eq!(actual.event_types, vec![EventType::Pdufa, EventType::AdCom]);
+        assert_eq!(actual.embedded_dates, vec![date("2019-06-12")]);
+    }
+
+    #[test]
+    fn latest_outcome_wins() {
+        assert_eq!(classify_note("Phase 2 met primary endpoint. CRL issued March 1, 2019.").outcome, Some(Outcome::Crl));
+        assert_eq!(classify_note("Complete response letter received. Resubmission approved.").outcome, Some(Outcome::Approved));
+        assert_eq!(classify_note("Trial did not meet its primary endpoint.").outcome, Some(Outcome::Missed));
+    }
+
+    #[test]
+    fn conferences_are_case_sensitive_and_deduplicated() {
+        let actual = classify_note("Data at ASCO and again at ASCO. Results turned to ash.");
+        assert_eq!(actual.conferences, vec!["ASCO".to_string()]);
+    }
+
+    #[test]
+    fn empty_note() {
+        assert_eq!(classify_note(""), NoteClassification::default());
+    }
+}
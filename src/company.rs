@@ -0,0 +1,457 @@
+use scraper::{Html, Selector, ElementRef};
+use chrono::NaiveDate;
+use log::{info, error};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::SystemTime;
+use super::currency;
+use super::catalyst_date::CatalystDate;
+use super::fetcher::{Fetcher, FetchError};
+use super::fda_scraper::{ScrapedCatalysts, ScrapeError, build_selector_for};
+use super::text;
+
+#[derive(Debug)]
+pub enum EnrichError {
+    FetchFailure(FetchError),
+    CacheIoError(PathBuf, io::Error),
+    CurrencyParseError(currency::USDParseError),
+    DateParseFailure(chrono::format::ParseError),
+    ScrapeFailure(ScrapeError),
+    //Symbols name the cached page, so anything but A-Z, 0-9, '.' and '-' is turned down
+    InvalidSymbol(String),
+}
+
+impl From<FetchError> for EnrichError {
+    fn from(e: FetchError) -> Self {
+        EnrichError::FetchFailure(e)
+    }
+}
+
+impl From<currency::USDParseError> for EnrichError {
+    fn from(e: currency::USDParseError) -> Self {
+        EnrichError::CurrencyParseError(e)
+    }
+}
+
+impl From<chrono::format::ParseError> for EnrichError {
+    fn from(e: chrono::format::ParseError) -> Self {
+        EnrichError::DateParseFailure(e)
+    }
+}
+
+impl From<ScrapeError> for EnrichError {
+    fn from(e: ScrapeError) -> Self {
+        EnrichError::ScrapeFailure(e)
+    }
+}
+
+impl fmt::Display for EnrichError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnrichError::FetchFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            EnrichError::CacheIoError(ref path, ref e) => write!(f, "Company cache {:?}: {}", path, e),
+            EnrichError::CurrencyParseError(ref e) => std::fmt::Display::fmt(&e, f),
+            EnrichError::DateParseFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            EnrichError::ScrapeFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            EnrichError::InvalidSymbol(ref symbol) => write!(f, "Symbol {:?} can't name a cached page", symbol),
+        }
+    }
+}
+
+impl error::Error for EnrichError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            EnrichError::FetchFailure(ref e) => Some(e),
+            EnrichError::CacheIoError(_, ref e) => Some(e),
+            EnrichError::CurrencyParseError(ref e) => Some(e),
+            EnrichError::DateParseFailure(ref e) => Some(e),
+            EnrichError::ScrapeFailure(ref e) => Some(e),
+            EnrichError::InvalidSymbol(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PipelineEntry {
+    pub drug_name: String,
+    pub indication: String,
+    pub phase: String,
+}
+
+//What the company page adds on top of a calendar row
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CompanyProfile {
+    pub pipeline: Vec<PipelineEntry>,
+    pub cash: Option<currency::USD>,
+    //How long the company says its cash lasts, e.g. "2H 2021"
+    pub cash_runway: Option<CatalystDate>,
+}
+
+fn first_text_from(an_element_ref: &ElementRef, selector: &Selector) -> Option<String> {
+    an_element_ref.select(selector).next()
+        .map(|x| text::element_text(&x))
+        .filter(|x| !x.is_empty())
+}
+
+impl CompanyProfile {
+    //Cash and runway are missing for some companies, so only values that are there but malformed are errors
+    pub fn parse(contents: &str) -> Result<CompanyProfile, EnrichError> {
+        let document = Html::parse_document(contents);
+        let root = document.root_element();
+
+        let pipeline_row = build_selector_for("tr.pipeline-row")?;
+        let drug_name = build_selector_for("td.pipeline-drug")?;
+        let indication = build_selector_for("td.pipeline-indication")?;
+        let phase = build_selector_for("td.pipeline-stage")?;
+
+        let pipeline = root.select(&pipeline_row)
+            .map(|a_row| PipelineEntry {
+                drug_name: first_text_from(&a_row, &drug_name).unwrap_or_default(),
+                indication: first_text_from(&a_row, &indication).unwrap_or_default(),
+                phase: first_text_from(&a_row, &phase).unwrap_or_default(),
+            })
+            .collect();
+
+        let cash = match first_text_from(&root, &build_selector_for("div.cash-position")?) {
+            Some(cash) => Some(currency::USD::from_abbreviated(&cash)?),
+            None => None
+        };
+        let cash_runway = match first_text_from(&root, &build_selector_for("div.cash-runway")?) {
+            Some(cash_runway) => Some(CatalystDate::new(&cash_runway)?),
+            None => None
+        };
+
+        Ok(CompanyProfile { pipeline, cash, cash_runway })
+    }
+}
+
+//Company pages are kept for the day they were fetched on, so a company is fetched at most once a day
+pub struct CompanyCache {
+    cache_dir: PathBuf,
+    //Pages already cached are used but nothing is stored or pruned, for a dry run
+    read_only: bool,
+}
+
+impl CompanyCache {
+    pub fn new(cache_dir: &Path) -> CompanyCache {
+        CompanyCache { cache_dir: cache_dir.to_owned(), read_only: false }
+    }
+
+    pub fn set_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    fn day_dir(&self, day: NaiveDate) -> PathBuf {
+        self.cache_dir.join(day.format("%Y-%m-%d").to_string())
+    }
+
+    fn page_path(&self, symbol: &str, day: NaiveDate) -> Result<PathBuf, EnrichError> {
+        if symbol.is_empty() || !symbol.chars().all(|x| x.is_ascii_uppercase() || x.is_ascii_digit() || x == '.' || x == '-') {
+            return Err(EnrichError::InvalidSymbol(symbol.to_string()));
+        }
+        Ok(self.day_dir(day).join(format!("{}.html", symbol)))
+    }
+
+    pub fn load(&self, symbol: &str, day: NaiveDate) -> Option<String> {
+        fs::read_to_string(self.page_path(symbol, day).ok()?).ok()
+    }
+
+    pub fn store(&self, symbol: &str, day: NaiveDate, contents: &str) -> Result<(), EnrichError> {
+        let day_dir = self.day_dir(day);
+        let page_path = self.page_path(symbol, day)?;
+        if self.read_only {
+            return Ok(());
+        }
+        fs::create_dir_all(&day_dir)
+            .and_then(|_| fs::write(&page_path, contents))
+            .map_err(|x| EnrichError::CacheIoError(page_path.clone(), x))
+    }
+
+    //Removes the pages of every day before the given one
+    pub fn prune_before(&self, day: NaiveDate) -> Result<(), EnrichError> {
+        if self.read_only {
+            return Ok(());
+        }
+        let day_dirs = match fs::read_dir(&self.cache_dir) {
+            Ok(day_dirs) => day_dirs,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(EnrichError::CacheIoError(self.cache_dir.clone(), e)),
+        };
+        for a_day_dir in day_dirs {
+            let a_day_dir = a_day_dir.map_err(|x| EnrichError::CacheIoError(self.cache_dir.clone(), x))?.path();
+            let is_older = a_day_dir.file_name()
+                .and_then(|x| NaiveDate::parse_from_str(&x.to_string_lossy(), "%Y-%m-%d").ok())
+                .map_or(false, |x| x < day);
+            if is_older {
+                fs::remove_dir_all(&a_day_dir).map_err(|x| EnrichError::CacheIoError(a_day_dir.clone(), x))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type EnrichedProfile = (String, Result<CompanyProfile, EnrichError>);
+
+//Fetches company pages on a fixed number of worker threads, rate limiting is left to the fetcher given
+pub struct Enricher {
+    fetcher: Arc<dyn Fetcher + Send + Sync>,
+    cache: Option<Arc<CompanyCache>>,
+    workers: usize,
+}
+
+impl Enricher {
+    pub fn new(fetcher: Arc<dyn Fetcher + Send + Sync>) -> Enricher {
+        Enricher { fetcher, cache: Option::None, workers: 4 }
+    }
+
+    pub fn set_cache(mut self, cache: CompanyCache) -> Self {
+        self.cache = Option::Some(Arc::new(cache));
+        self
+    }
+
+    pub fn set_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    //Takes (symbol, company page url) pairs, results come back in no particular order
+    pub fn fetch_profiles(&self, companies: Vec<(String, String)>, today: NaiveDate) -> Vec<EnrichedProfile> {
+        if let Some(cache) = &self.cache {
+            if let Err(err) = cache.prune_before(today) {
+                error!("Pruning the company cache failed {}", err);
+            }
+        }
+
+        let company_count = companies.len();
+        let queue = Arc::new(Mutex::new(companies.into_iter().collect::<VecDeque<_>>()));
+        let (sender, receiver) = mpsc::channel();
+
+        let workers: Vec<_> = (0..self.workers.min(company_count)).map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let fetcher = Arc::clone(&self.fetcher);
+            let cache = self.cache.clone();
+            thread::spawn(move || {
+                loop {
+                    //Taken in its own statement so the lock isn't held while fetching
+                    let next_company = queue.lock().unwrap().pop_front();
+                    let (symbol, url) = match next_company {
+                        Some(next_company) => next_company,
+                        None => break
+                    };
+                    let profile = fetch_profile(fetcher.as_ref(), cache.as_ref().map(|x| x.as_ref()), &symbol, &url, today);
+                    if sender.send((symbol, profile)).is_err() {
+                        break;
+                    }
+                }
+            })
+        }).collect();
+        drop(sender);
+
+        let profiles: Vec<EnrichedProfile> = receiver.iter().collect();
+        for a_worker in workers {
+            if a_worker.join().is_err() {
+                error!("A company page worker panicked");
+            }
+        }
+        info!("Fetched {} of {} company profiles", profiles.len(), company_count);
+        profiles
+    }
+
+    //Attaches a profile to every row of each company that could be fetched, failures are logged and returned
+    pub fn enrich(&self, catalysts: &mut ScrapedCatalysts, today: NaiveDate) -> Vec<(String, EnrichError)> {
+        let enrich_start_time = SystemTime::now();
+
+        let companies: BTreeMap<String, String> = catalysts.rows()
+            .map(|x| (x.symbol.clone(), x.url.clone()))
+            .collect();
+
+        let mut profiles = BTreeMap::new();
+        let mut failures = Vec::new();
+        for (symbol, profile) in self.fetch_profiles(companies.into_iter().collect(), today) {
+            match profile {
+                Ok(profile) => { profiles.insert(symbol, profile); },
+                Err(err) => {
+                    error!("Enriching {} failed {}", symbol, err);
+                    failures.push((symbol, err));
+                }
+            }
+        }
+
+        for a_row in catalysts.rows_mut() {
+            a_row.company = profiles.get(&a_row.symbol).cloned();
+        }
+
+        if let Ok(enrich_duration) = enrich_start_time.elapsed() {
+            info!("Enrichment took {} millis", enrich_duration.as_millis());
+        }
+        failures
+    }
+}
+
+fn fetch_profile(fetcher: &dyn Fetcher, cache: Option<&CompanyCache>, symbol: &str, url: &str, today: NaiveDate) -> Result<CompanyProfile, EnrichError> {
+    if let Some(contents) = cache.and_then(|x| x.load(symbol, today)) {
+        return CompanyProfile::parse(&contents);
+    }
+
+    let page = fetcher.fetch(url)?;
+    if let Some(cache) = cache {
+        cache.store(symbol, today, &page.body)?;
+    }
+    CompanyProfile::parse(&page.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::{FetchedPage, ReplayFetcher};
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use crate::report::Report;
+    use askama::Template;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn date(ymd: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap()
+    }
+
+    fn btx_profile() -> CompanyProfile {
+        CompanyProfile {
+            pipeline: vec![
+                PipelineEntry { drug_name: "OpRegen".to_string(), indication: "Dry age-related macular degeneration (AMD)".to_string(), phase: "Phase 1/2".to_string() },
+                PipelineEntry { drug_name: "OPC1".to_string(), indication: "Spinal cord injury".to_string(), phase: "Phase 1/2".to_string() },
+                PipelineEntry { drug_name: "VAC2".to_string(), indication: "Non-small cell lung cancer".to_string(), phase: "Phase 1".to_string() },
+            ],
+            cash: Some(currency::USD::new("$48200000").unwrap()),
+            cash_runway: Some(CatalystDate::new("2H 2021").unwrap()),
+        }
+    }
+
+    #[test]
+    fn parse_company_page() {
+        let recording = FetchedPage::from_recording(&fs::read_to_string(
+            "test-resources/recordings/www_biopharmcatalyst_com_company_BTX_6efa798ee304.http").unwrap()).unwrap();
+        assert_eq!(CompanyProfile::parse(&recording.body).unwrap(), btx_profile());
+    }
+
+    #[test]
+    fn parse_company_page_without_cash() {
+        assert_eq!(CompanyProfile::parse("<html><body><table></table></body></html>").unwrap(),
+                   CompanyProfile { pipeline: Vec::new(), cash: None, cash_runway: None });
+    }
+
+    //Counts fetches and how many were in flight at once
+    struct CountingFetcher {
+        inner: ReplayFetcher,
+        fetches: AtomicUsize,
+        //(in flight now, most ever in flight)
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    impl CountingFetcher {
+        fn new() -> CountingFetcher {
+            CountingFetcher { inner: ReplayFetcher::new(Path::new("test-resources/recordings")), fetches: AtomicUsize::new(0), in_flight: Mutex::new((0, 0)) }
+        }
+    }
+
+    impl Fetcher for CountingFetcher {
+        fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            //long enough for the other workers to start their fetches
+            thread::sleep(Duration::from_millis(50));
+            //every symbol is served the BTX page
+            let page = self.inner.fetch(&url.replace(|x: char| x.is_ascii_digit(), ""));
+            self.in_flight.lock().unwrap().0 -= 1;
+            page
+        }
+    }
+
+    fn companies(count: usize) -> Vec<(String, String)> {
+        (0..count).map(|x| (format!("BTX{}", x), format!("https://www.biopharmcatalyst.com/company/BTX{}", x))).collect()
+    }
+
+    #[test]
+    fn bounded_worker_pool() {
+        let fetcher = Arc::new(CountingFetcher::new());
+        let profiles = Enricher::new(fetcher.clone()).set_workers(2).fetch_profiles(companies(6), date("2019-05-01"));
+
+        assert_eq!(profiles.len(), 6);
+        assert!(profiles.iter().all(|(_, x)| x.as_ref().unwrap() == &btx_profile()));
+        assert_eq!(fetcher.fetches.load(Ordering::SeqCst), 6);
+        assert_eq!(fetcher.in_flight.lock().unwrap().1, 2);
+    }
+
+    #[test]
+    fn fetched_at_most_once_per_day() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let fetcher = Arc::new(CountingFetcher::new());
+        let enricher = Enricher::new(fetcher.clone()).set_cache(CompanyCache::new(cache_dir.path()));
+
+        enricher.fetch_profiles(companies(2), date("2019-05-01"));
+        let cached = enricher.fetch_profiles(companies(2), date("2019-05-01"));
+        assert!(cached.iter().all(|(_, x)| x.as_ref().unwrap() == &btx_profile()));
+        assert_eq!(fetcher.fetches.load(Ordering::SeqCst), 2);
+
+        enricher.fetch_profiles(companies(2), date("2019-05-02"));
+        assert_eq!(fetcher.fetches.load(Ordering::SeqCst), 4);
+        assert!(!cache_dir.path().join("2019-05-01").exists());
+    }
+
+    #[test]
+    fn cached_symbols_checked() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = CompanyCache::new(&cache_dir.path().join("cache"));
+        cache.store("BRK.B", date("2019-05-01"), "<html></html>").unwrap();
+        assert_eq!(cache.load("BRK.B", date("2019-05-01")), Some("<html></html>".to_string()));
+
+        for symbol in &["../../escaped", "BTX/..", "btx", ""] {
+            match cache.store(symbol, date("2019-05-01"), "<html></html>") {
+                Err(EnrichError::InvalidSymbol(_)) => (),
+                other => panic!("{:?} for {:?}", other, symbol),
+            }
+            assert_eq!(cache.load(symbol, date("2019-05-01")), None);
+        }
+        assert!(!cache_dir.path().join("escaped.html").exists());
+    }
+
+    #[test]
+    fn read_only_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        CompanyCache::new(cache_dir.path()).store("BTX", date("2019-04-30"), "<html></html>").unwrap();
+        let cache = CompanyCache::new(cache_dir.path()).set_read_only();
+        cache.store("GWPH", date("2019-05-01"), "<html></html>").unwrap();
+        cache.prune_before(date("2019-05-01")).unwrap();
+        assert_eq!(cache.load("BTX", date("2019-04-30")), Some("<html></html>".to_string()));
+        assert_eq!(cache.load("GWPH", date("2019-05-01")), None);
+    }
+
+    #[test]
+    fn enrich_replayed_calendar() {
+        let fetcher = Arc::new(ReplayFetcher::new(Path::new("test-resources/recordings")));
+        let predicates = ScrapePredicates::new().set_price_limit(currency::USD::new("$200").unwrap()).set_date_limit(date("2019-05-04"));
+        let mut scraped = fda_scraper::do_scraping(fetcher.as_ref(), None, "https://www.biopharmcatalyst.com/calendars/fda-calendar", &predicates).unwrap();
+
+        //only BTX has a recorded company page
+        let mut failed: Vec<String> = Enricher::new(fetcher).enrich(&mut scraped, date("2019-05-01")).into_iter().map(|x| x.0).collect();
+        failed.sort();
+        assert_eq!(failed, vec!["EYEN".to_string(), "GWPH".to_string()]);
+
+        for a_row in scraped.rows() {
+            let expected = if a_row.symbol == "BTX" { Some(btx_profile()) } else { None };
+            assert_eq!(a_row.company, expected);
+        }
+        assert!(Report::new(&scraped).render().unwrap().contains("Cash $48200000.00, runway into 2H 2021, 3 pipeline programs"));
+    }
+}
@@ -0,0 +1,351 @@
+use askama::Template;
+use chrono::{NaiveDate, NaiveDateTime, Duration, Weekday, Datelike, Timelike};
+use super::fda_scraper::{self, ScrapedCatalysts, ScrapePredicates, ScrapeError, GroupedRow, ParsedRow};
+use super::archive::{PageArchive, ArchiveError};
+use super::ledger::Alert;
+use super::catalyst_id;
+
+//Which email a run sends
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DigestMode {
+    //The full report on the next 7 days, whenever it's run
+    Standard,
+    //The next 30 days split at the two week mark, on Monday mornings
+    WeeklyLookahead,
+    //Rows added, changed or dropped in the next 30 days since the previous download
+    DailyDelta,
+    //An email for each catalyst due tomorrow, in the evening
+    TomorrowReminder,
+}
+
+impl DigestMode {
+    pub fn from_name(name: &str) -> Option<DigestMode> {
+        match name.trim().to_lowercase().as_str() {
+            "standard" => Some(DigestMode::Standard),
+            "weekly" => Some(DigestMode::WeeklyLookahead),
+            "daily" => Some(DigestMode::DailyDelta),
+            "tomorrow" => Some(DigestMode::TomorrowReminder),
+            _ => None
+        }
+    }
+
+    //Rows are kept up to, but not including, this many days from today
+    pub fn horizon_days(self) -> i64 {
+        match self {
+            DigestMode::Standard => 7,
+            DigestMode::WeeklyLookahead | DigestMode::DailyDelta => 30,
+            DigestMode::TomorrowReminder => 2,
+        }
+    }
+
+    pub fn predicates(self, predicates: ScrapePredicates, today: NaiveDate) -> ScrapePredicates {
+        predicates.set_date_limit(today + Duration::days(self.horizon_days()))
+    }
+
+    //The standard report and the delta send whenever they're run, the delta only when the page has changed
+    pub fn default_schedule(self) -> Option<Schedule> {
+        match self {
+            DigestMode::Standard | DigestMode::DailyDelta => None,
+            DigestMode::WeeklyLookahead => Some(Schedule { weekday: Some(Weekday::Mon), from_hour: 7 }),
+            DigestMode::TomorrowReminder => Some(Schedule { weekday: None, from_hour: 18 }),
+        }
+    }
+}
+
+//When a mode sends, "mon@7" for Mondays from 7am or "daily@18" for every day from 6pm, in local time.
+//Runs outside the schedule send nothing, so a single frequent cron entry can drive every mode, with the ledger
+//keeping the runs after the first from sending the same alerts again
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Schedule {
+    weekday: Option<Weekday>,
+    from_hour: u32,
+}
+
+impl Schedule {
+    pub fn parse(schedule: &str) -> Option<Schedule> {
+        let mut split = schedule.trim().splitn(2, '@');
+        let weekday = match split.next()?.trim() {
+            day if day.eq_ignore_ascii_case("daily") => None,
+            day => Some(day.parse::<Weekday>().ok()?),
+        };
+        let from_hour = match split.next() {
+            Some(hour) => hour.trim().parse().ok().filter(|x| *x < 24)?,
+            None => 0
+        };
+        Some(Schedule { weekday, from_hour })
+    }
+
+    pub fn is_due(&self, now: NaiveDateTime) -> bool {
+        self.weekday.map_or(true, |x| x == now.weekday()) && now.hour() >= self.from_hour
+    }
+}
+
+//A rendered email, sent by a Notifier's send_digest
+#[derive(Debug)]
+pub struct Digest {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    //What it tells the recipient about, so reruns don't send it again
+    pub alerts: Vec<Alert>,
+}
+
+pub struct WeeklyLookahead<'a> {
+    //Two weeks from today
+    pub fortnight: NaiveDate,
+    //Due before the fortnight
+    pub soon: Vec<GroupedRow<'a>>,
+    //Due from the fortnight on, or only known to within a range starting before it
+    pub later: Vec<GroupedRow<'a>>,
+}
+
+#[derive(Template)]
+#[template(path = "digest_weekly.html")]
+struct WeeklyLookaheadHtml<'a> {
+    digest: &'a WeeklyLookahead<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "digest_weekly.txt")]
+struct WeeklyLookaheadText<'a> {
+    digest: &'a WeeklyLookahead<'a>,
+}
+
+pub fn weekly_lookahead(catalysts: &ScrapedCatalysts, today: NaiveDate) -> Result<Digest, askama::Error> {
+    let fortnight = today + Duration::days(14);
+    let (soon, later) = catalysts.dated_rows().into_iter()
+        .partition(|x| x.row.catalyst_date.end().map_or(false, |end| end < fortnight));
+    let digest = WeeklyLookahead { fortnight, soon, later };
+    let kind = format!("weekly {}", today.format("%G-W%V"));
+    Ok(Digest {
+        subject: format!("Catalyst Lookahead {}: {} catalysts in the next 30 days", today.format("%b %d %Y"), catalysts.len()),
+        html: WeeklyLookaheadHtml { digest: &digest }.render()?,
+        text: WeeklyLookaheadText { digest: &digest }.render()?,
+        alerts: catalysts.rows().map(|x| Alert::new(x, &kind)).collect(),
+    })
+}
+
+//A row that's new, dropped, or whose date, stage or note has changed.
+//Prices move every day, so they don't count as a change
+#[derive(Debug, Eq, PartialEq)]
+pub struct RowChange<'a> {
+    pub row: &'a ParsedRow,
+    //"new", "changed" or "dropped"
+    pub kind: &'static str,
+    //e.g. "date 2019-05-02 was 2019-05-09", empty unless changed
+    pub details: Vec<String>,
+}
+
+impl<'a> RowChange<'a> {
+    fn between(row: &'a ParsedRow, was: &ParsedRow) -> Option<RowChange<'a>> {
+        let mut details = Vec::new();
+        if row.catalyst_date != was.catalyst_date {
+            details.push(format!("date {} was {}", row.catalyst_date, was.catalyst_date));
+        }
+        if row.phase != was.phase {
+            details.push(format!("stage {} was {}", row.phase, was.phase));
+        }
+        if row.catalyst_note != was.catalyst_note {
+            details.push(format!("note was: {}", was.catalyst_note));
+        }
+        if details.is_empty() { None } else { Some(RowChange { row, kind: "changed", details }) }
+    }
+}
+
+//In catalyst date order, with dropped rows last
+pub fn row_changes<'a>(catalysts: &'a ScrapedCatalysts, previous: &'a ScrapedCatalysts) -> Vec<RowChange<'a>> {
+    let current_rows: Vec<&ParsedRow> = catalysts.dated_rows().into_iter().map(|x| x.row).collect();
+    let previous_rows: Vec<&ParsedRow> = previous.dated_rows().into_iter().map(|x| x.row).collect();
+    let matched = catalyst_id::match_rows(&previous_rows, &current_rows);
+
+    let mut changes = Vec::new();
+    for a_row in current_rows {
+        match matched.matched.iter().find(|(_, is)| std::ptr::eq(*is, a_row)) {
+            Some((was, _)) => changes.extend(RowChange::between(a_row, was)),
+            None => changes.push(RowChange { row: a_row, kind: "new", details: Vec::new() }),
+        }
+    }
+    changes.extend(matched.dropped.into_iter().map(|x| RowChange { row: x, kind: "dropped", details: Vec::new() }));
+    changes
+}
+
+#[derive(Template)]
+#[template(path = "digest_daily.html")]
+struct DailyDeltaHtml<'a> {
+    changes: &'a [RowChange<'a>],
+}
+
+#[derive(Template)]
+#[template(path = "digest_daily.txt")]
+struct DailyDeltaText<'a> {
+    changes: &'a [RowChange<'a>],
+}
+
+//None when nothing's changed, or there's no previous download to compare with
+pub fn daily_delta(catalysts: &ScrapedCatalysts, previous: Option<&ScrapedCatalysts>, today: NaiveDate) -> Result<Option<Digest>, askama::Error> {
+    let changes = match previous {
+        Some(previous) => row_changes(catalysts, previous),
+        None => return Ok(None),
+    };
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let count = |kind: &str| changes.iter().filter(|x| x.kind == kind).count();
+    Ok(Some(Digest {
+        subject: format!("Catalyst Changes {}: {} new, {} changed, {} dropped", today.format("%b %d %Y"), count("new"), count("changed"), count("dropped")),
+        html: DailyDeltaHtml { changes: &changes }.render()?,
+        text: DailyDeltaText { changes: &changes }.render()?,
+        //A catalyst changing again is a new alert, as its details are part of the kind
+        alerts: changes.iter().map(|x| Alert::new(x.row, format!("daily {} {}", x.kind, x.details.join("; ")).trim_end())).collect(),
+    }))
+}
+
+//Whether the latest download is the same page as the one before it, in which case there's no delta to send
+pub fn unchanged_download(archive: &PageArchive) -> Result<bool, ArchiveError> {
+    let entries = archive.entries()?;
+    Ok(entries.len() >= 2 && entries[entries.len() - 1].hash == entries[entries.len() - 2].hash)
+}
+
+//The download before the latest one, parsed with the same predicates, for comparing against
+pub fn previous_download(archive: &PageArchive, predicates: &ScrapePredicates) -> Result<Option<ScrapedCatalysts>, ScrapeError> {
+    let entries = archive.entries()?;
+    if entries.len() < 2 {
+        return Ok(None);
+    }
+    let contents = archive.load(&entries[entries.len() - 2])?;
+    fda_scraper::parse_html(&contents, predicates).map(Some)
+}
+
+#[derive(Template)]
+#[template(path = "digest_tomorrow.html")]
+struct TomorrowReminderHtml<'a> {
+    row: &'a ParsedRow,
+}
+
+#[derive(Template)]
+#[template(path = "digest_tomorrow.txt")]
+struct TomorrowReminderText<'a> {
+    row: &'a ParsedRow,
+}
+
+//One for each catalyst dated exactly tomorrow, ranges like "Q2 2019" never are
+pub fn tomorrow_reminders(catalysts: &ScrapedCatalysts, today: NaiveDate) -> Result<Vec<Digest>, askama::Error> {
+    let tomorrow = today + Duration::days(1);
+    catalysts.dated_rows().into_iter()
+        .map(|x| x.row)
+        .filter(|x| x.catalyst_date.start() == Some(tomorrow) && x.catalyst_date.end() == Some(tomorrow))
+        .map(|row| Ok(Digest {
+            subject: format!("Tomorrow: {} {} ({})", row.symbol, row.drug_name, row.phase),
+            html: TomorrowReminderHtml { row }.render()?,
+            text: TomorrowReminderText { row }.render()?,
+            //Dated, so a catalyst that's put back gets a reminder again the evening before its new date
+            alerts: vec![Alert::new(row, &format!("tomorrow {}", tomorrow))],
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    fn clustered() -> ScrapedCatalysts {
+        fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html"), ScrapePredicates::new()).unwrap()
+    }
+
+    #[test]
+    fn schedules() {
+        let monday_morning = date("2019-04-29").and_hms(7, 30, 0);
+        let monday_evening = date("2019-04-29").and_hms(18, 15, 0);
+        let tuesday = date("2019-04-30").and_hms(7, 30, 0);
+        let weekly = DigestMode::WeeklyLookahead.default_schedule().unwrap();
+        let tomorrow = DigestMode::TomorrowReminder.default_schedule().unwrap();
+        assert!(weekly.is_due(monday_morning));
+        assert!(!weekly.is_due(tuesday));
+        assert!(!tomorrow.is_due(monday_morning));
+        assert!(tomorrow.is_due(monday_evening));
+        //The rest of the day is due too, in case the first run in it was missed
+        assert!(weekly.is_due(date("2019-04-29").and_hms(8, 0, 0)));
+        assert!(tomorrow.is_due(date("2019-04-29").and_hms(23, 0, 0)));
+        assert!(!weekly.is_due(date("2019-04-29").and_hms(6, 59, 0)));
+        assert_eq!(DigestMode::Standard.default_schedule(), None);
+
+        let schedule = Schedule::parse("tue@7").unwrap();
+        assert!(schedule.is_due(tuesday));
+        assert!(!schedule.is_due(monday_morning));
+        assert_eq!(Schedule::parse("daily"), Some(Schedule { weekday: None, from_hour: 0 }));
+        assert_eq!(Schedule::parse("Daily@18"), Some(Schedule { weekday: None, from_hour: 18 }));
+        assert_eq!(Schedule::parse("someday@7"), None);
+        assert_eq!(Schedule::parse("mon@24"), None);
+    }
+
+    #[test]
+    fn unchanged_downloads() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), crate::archive::RetentionPolicy::new());
+        let page = |body: &str, fetched_at: &str| crate::fetcher::FetchedPage {
+            url: "https://www.biopharmcatalyst.com/calendars/fda-calendar".to_string(),
+            status: 200,
+            headers: Vec::new(),
+            fetched_at: chrono::DateTime::parse_from_rfc3339(fetched_at).unwrap().with_timezone(&chrono::Utc),
+            body: body.to_string(),
+        };
+        archive.store(&page("<html>first</html>", "2019-05-01T12:00:00Z")).unwrap();
+        assert!(!unchanged_download(&archive).unwrap());
+        archive.store(&page("<html>first</html>", "2019-05-02T12:00:00Z")).unwrap();
+        assert!(unchanged_download(&archive).unwrap());
+        archive.store(&page("<html>second</html>", "2019-05-03T12:00:00Z")).unwrap();
+        assert!(!unchanged_download(&archive).unwrap());
+    }
+
+    #[test]
+    fn weekly_lookahead_splits_at_two_weeks() {
+        let digest = weekly_lookahead(&clustered(), date("2019-04-22")).unwrap();
+        assert_eq!(digest.subject, "Catalyst Lookahead Apr 22 2019: 4 catalysts in the next 30 days");
+        let later = digest.text.find("FROM 2019-05-06").unwrap();
+        assert!(digest.text.find("BTX").unwrap() < later);
+        assert!(digest.text.find("Sativex").unwrap() > later);
+        assert!(digest.html.contains("<td>MicroStat</td>"));
+    }
+
+    #[test]
+    fn daily_delta_lists_changes() {
+        let previous = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let today = date("2019-05-01");
+        assert!(daily_delta(&previous, Some(&previous), today).unwrap().is_none());
+        assert!(daily_delta(&previous, None, today).unwrap().is_none());
+
+        let digest = daily_delta(&clustered(), Some(&previous), today).unwrap().unwrap();
+        assert_eq!(digest.subject, "Catalyst Changes May 01 2019: 1 new, 0 changed, 0 dropped");
+        assert!(digest.text.contains("NEW  GWPH  Sativex"), "{}", digest.text);
+        let digest = daily_delta(&previous, Some(&clustered()), today).unwrap().unwrap();
+        assert_eq!(digest.subject, "Catalyst Changes May 01 2019: 0 new, 0 changed, 1 dropped");
+        assert!(digest.html.contains("<td>dropped</td>"), "{}", digest.html);
+    }
+
+    #[test]
+    fn detailed_changes() {
+        let previous = clustered();
+        let mut html = std::fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html").unwrap();
+        //A reworded drug name is still the same catalyst
+        html = html.replace("05/06/2019", "05/13/2019").replace("Epidiolex GWPCARE2", "Epidiolex GWPCARE-2");
+        let current = fda_scraper::parse_html(&html, &ScrapePredicates::new()).unwrap();
+        let changes = row_changes(&current, &previous);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].row.drug_name, "Sativex");
+        assert_eq!(changes[0].details, vec!["date 2019-05-13 was 2019-05-06".to_string()]);
+    }
+
+    #[test]
+    fn reminders_for_tomorrow() {
+        let reminders = tomorrow_reminders(&clustered(), date("2019-05-01")).unwrap();
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].subject, "Tomorrow: BTX OpRegen (Phase 1/2)");
+        assert!(reminders[0].text.contains("Updated data due"));
+        assert!(tomorrow_reminders(&clustered(), date("2019-05-03")).unwrap().is_empty());
+    }
+}
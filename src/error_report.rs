@@ -0,0 +1,88 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use std::error::Error;
+use super::digest::Digest;
+use super::fda_scraper::{ScrapeError, FieldNotFound};
+
+#[derive(Template)]
+#[template(path = "error_report.html")]
+struct ErrorReportHtml<'a> {
+    address: &'a str,
+    failed_at: String,
+    error: String,
+    causes: &'a [String],
+    missing: Option<&'a FieldNotFound>,
+}
+
+#[derive(Template)]
+#[template(path = "error_report.txt")]
+struct ErrorReportText<'a> {
+    address: &'a str,
+    failed_at: String,
+    error: String,
+    causes: &'a [String],
+    missing: Option<&'a FieldNotFound>,
+}
+
+//Each error under the top one, outermost first
+fn causes(error: &ScrapeError) -> Vec<String> {
+    let mut causes = Vec::new();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    causes
+}
+
+//An email for whoever runs the scraper saying why a scrape of the page failed. When a field was missing it has the row,
+//symbol, selector and the html that was there instead, which is usually enough to tell how the page's layout changed
+pub fn error_report(error: &ScrapeError, address: &str, failed_at: DateTime<Utc>) -> Result<Digest, askama::Error> {
+    let causes = causes(error);
+    let missing = match error {
+        ScrapeError::ExpectedFieldNotFound(missing) => Some(missing),
+        _ => None,
+    };
+    let failed_at = failed_at.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+    Ok(Digest {
+        subject: match missing {
+            Some(missing) => format!("Scraping Failed: no {} found", missing.field),
+            None => "Scraping Failed".to_string(),
+        },
+        html: ErrorReportHtml { address, failed_at: failed_at.clone(), error: error.to_string(), causes: &causes, missing }.render()?,
+        text: ErrorReportText { address, failed_at, error: error.to_string(), causes: &causes, missing }.render()?,
+        //Sent every time it happens, until it's fixed
+        alerts: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::fda_scraper::{self, ScrapePredicates};
+
+    #[test]
+    fn missing_field_report() {
+        let html = std::fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap()
+            .replace("class=\"drug\"", "class=\"drug-name\"");
+        let error = fda_scraper::parse_html(&html, &ScrapePredicates::new()).unwrap_err();
+        let report = error_report(&error, "https://www.biopharmcatalyst.com/calendars/fda-calendar", Utc.ymd(2019, 5, 1).and_hms(7, 0, 0)).unwrap();
+
+        assert_eq!(report.subject, "Scraping Failed: no drug name found");
+        assert!(report.text.contains("Failed at 2019-05-01 07:00:00 UTC"), "{}", report.text);
+        assert!(report.text.contains("Row:      1\nSymbol:   BTX\nField:    drug name\nSelector: strong[class=drug]\n"), "{}", report.text);
+        assert!(report.text.contains("<strong class=\"drug-name\">OpRegen </strong>"), "{}", report.text);
+        assert!(report.html.contains("<td>BTX</td>"), "{}", report.html);
+        assert!(report.html.contains("&lt;strong class=&quot;drug-name&quot;&gt;"), "{}", report.html);
+        assert!(report.alerts.is_empty());
+    }
+
+    #[test]
+    fn other_error_report() {
+        let error = fda_scraper::parse_rows(std::path::Path::new("test-resources/fda_calendar_sample_files/fda_calendar_malformed_price.html"), ScrapePredicates::new()).unwrap_err();
+        let report = error_report(&error, "https://www.biopharmcatalyst.com/calendars/fda-calendar", Utc.ymd(2019, 5, 1).and_hms(7, 0, 0)).unwrap();
+        assert_eq!(report.subject, "Scraping Failed");
+        assert!(!report.text.contains("Selector:"), "{}", report.text);
+    }
+}
@@ -2,38 +2,74 @@ use std::fs;
 use scraper::{Html, Selector};
 use chrono::NaiveDate;
 use std::collections::BTreeMap;
-use log::{info, error};
+use log::Level;
 use std::fmt;
 use std::error;
 use std::io;
 use scraper::ElementRef;
 use super::currency;
+use super::catalyst_note::{self, NoteClassification, EventType, Outcome};
+use super::note_events::{self, NoteEvent};
+use super::catalyst_date::CatalystDate;
+use super::company::CompanyProfile;
+use super::scoring::{Scorer, MarketMetrics, Ratio};
+use super::taxonomy::Taxonomy;
+use super::fetcher::{Fetcher, FetchError};
+use super::archive::{PageArchive, ArchiveEntry, ArchiveError};
 use std::path::Path;
-use askama::Template;
 use std::time::SystemTime;
-use tempfile::NamedTempFile;
-use std::io::Write;
+use std::io::Read;
+use super::row_stream::RowStream;
+use super::text;
+use super::metrics;
+use super::logging;
+use serde_json::json;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct ParsedRow {
     pub price: currency::USD,
     pub url: String,
     pub symbol: String,
-    pub catalyst_date: NaiveDate,
+    pub catalyst_date: CatalystDate,
     pub drug_name: String,
     pub drug_indication: String,
+    //From the drug indication, empty when the taxonomy doesn't cover it
+    pub therapeutic_areas: Vec<String>,
     pub catalyst_note: String,
+    pub note_classification: NoteClassification,
+    pub note_events: Vec<NoteEvent>,
     pub phase: String,
+    //Only logged in users see which rows are in their portfolio, false otherwise
+    pub in_portfolio: bool,
+    //Filled in from the company page by an Enricher, None until then
+    pub company: Option<CompanyProfile>,
+    pub metrics: MarketMetrics,
+    //Set by a Scorer, None until then
+    pub score: Option<u32>,
 }
 
 //We have multiple errors possible, so enumerate them here so we have a common wrapping to match & deconstruct on
 #[derive(Debug)]
 pub enum ScrapeError {
     InvalidSelector(String),
-    ExpectedFieldNotFound(Selector),
+    ExpectedFieldNotFound(FieldNotFound),
     DateParseFailure(chrono::format::ParseError),
     FileReadError(io::Error),
     CurrencyParseError(currency::USDParseError),
+    FetchFailure(FetchError),
+    ArchiveFailure(ArchiveError),
+}
+
+impl From<ArchiveError> for ScrapeError {
+    fn from(e: ArchiveError) -> Self {
+        ScrapeError::ArchiveFailure(e)
+    }
+}
+
+impl From<FetchError> for ScrapeError {
+    fn from(e: FetchError) -> Self {
+        ScrapeError::FetchFailure(e)
+    }
 }
 
 impl From<currency::USDParseError> for ScrapeError {
@@ -51,15 +87,15 @@ impl From<chrono::format::ParseError> for ScrapeError {
 impl fmt::Display for ScrapeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ScrapeError::ExpectedFieldNotFound(ref e) => write!(f, "Didn't match css selector {:?}", e),
+            ScrapeError::ExpectedFieldNotFound(ref e) => std::fmt::Display::fmt(&e, f),
             ScrapeError::InvalidSelector(ref e) => write!(f, "Malformed CSS Selector {:?}", e),
-            // This is a wrapper, so defer to the underlying types' implementation of `fmt`.
-            //ScrapeError::InvalidSelector(ref e) => e.fmt(f),
 
             //chrono::ParseError implements Display and Debug both of which have a fmt function, as such just calling e.fmt is ambiguous
             ScrapeError::DateParseFailure(ref e) => std::fmt::Display::fmt(&e, f),
             ScrapeError::FileReadError(ref e) => std::fmt::Display::fmt(&e, f),
             ScrapeError::CurrencyParseError(ref e) => std::fmt::Display::fmt(&e, f),
+            ScrapeError::FetchFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            ScrapeError::ArchiveFailure(ref e) => std::fmt::Display::fmt(&e, f),
         }
     }
 }
@@ -73,10 +109,79 @@ impl error::Error for ScrapeError {
             ScrapeError::DateParseFailure(ref e) => Some(e),
             ScrapeError::FileReadError(ref e) => Some(e),
             ScrapeError::CurrencyParseError(ref e) => Some(e),
+            ScrapeError::FetchFailure(ref e) => Some(e),
+            ScrapeError::ArchiveFailure(ref e) => Some(e),
+        }
+    }
+}
+
+impl ScrapeError {
+    //For counting failures by what went wrong
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ScrapeError::InvalidSelector(_) => "invalid_selector",
+            ScrapeError::ExpectedFieldNotFound(_) => "field_not_found",
+            ScrapeError::DateParseFailure(_) => "date_parse",
+            ScrapeError::FileReadError(_) => "file_read",
+            ScrapeError::CurrencyParseError(_) => "currency_parse",
+            ScrapeError::FetchFailure(_) => "fetch",
+            ScrapeError::ArchiveFailure(_) => "archive",
+        }
+    }
+
+    //Says which row a missing field was in, the other errors are left as they are
+    fn in_row(self, row: usize, symbol: Option<String>) -> ScrapeError {
+        match self {
+            ScrapeError::ExpectedFieldNotFound(missing) => ScrapeError::ExpectedFieldNotFound(FieldNotFound { row: Some(row), symbol, ..missing }),
+            e => e,
         }
     }
 }
 
+//Where a field was expected and what was there instead, enough to find the row on the page and see what changed
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FieldNotFound {
+    pub field: &'static str,
+    pub css_selector: &'static str,
+    //Counting from 1, None until the row is known
+    pub row: Option<usize>,
+    //None when the symbol is the field that's missing, or the row couldn't be found at all
+    pub symbol: Option<String>,
+    //The outer html of the element the field was looked for in, truncated
+    pub html: String,
+}
+
+//Long enough for the first few cells of a row, where the symbol, drug and dates are
+const SNIPPET_LENGTH: usize = 1000;
+
+impl FieldNotFound {
+    fn new(field: &FieldSelector, html: &str) -> FieldNotFound {
+        FieldNotFound { field: field.field, css_selector: field.css_selector, row: None, symbol: None, html: snippet(html) }
+    }
+}
+
+impl fmt::Display for FieldNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(row) = self.row {
+            write!(f, "Row {}", row)?;
+            if let Some(symbol) = &self.symbol {
+                write!(f, " ({})", symbol)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "No {} matching css selector {:?} in {}", self.field, self.css_selector, self.html)
+    }
+}
+
+//Whitespace between tags collapsed so the snippet reads on one line
+fn snippet(html: &str) -> String {
+    let collapsed = html.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match collapsed.char_indices().nth(SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}...", &collapsed[..end]),
+        None => collapsed,
+    }
+}
+
 //New type to make sure only PhaseLabels are used as the key type in ScrapedCatalysts. Zero cost abstraction
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
 struct PhaseLabel(String);
@@ -87,121 +192,475 @@ impl fmt::Display for PhaseLabel {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Template)]
-// Template will generate the code...
-#[template(path = "scraped_catalysts_email_body.html")] // using the template in this path, relative
+#[derive(Debug, Eq, PartialEq)]
 pub struct ScrapedCatalysts {
-    catalysts: BTreeMap<(PhaseLabel, NaiveDate), Vec<ParsedRow>>
+    catalysts: BTreeMap<(PhaseLabel, CatalystDate), Vec<ParsedRow>>,
+}
+
+//How rows are grouped and ordered in the report
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Grouping {
+    //The original layout, unlabelled groups of phase then catalyst date
+    ByPhaseThenDate,
+    ByDateThenPhase,
+    BySymbol,
+    ByIndication,
+    //Rows under several areas are listed under each of them, rows under none come last
+    ByTherapeuticArea,
+    ByPriceBucket,
+    //One list, highest score first
+    ByScore,
+    //One list in catalyst date order
+    Flat,
 }
 
-fn build_selector_for(css_selector: &str) -> Selector {
-    Selector::parse(css_selector).expect(format!("Malformed CSS Selector {:?}", css_selector).as_str())
+impl Grouping {
+    //The names accepted in configuration, e.g. "date" for ByDateThenPhase
+    pub fn from_name(name: &str) -> Option<Grouping> {
+        match name.trim().to_lowercase().as_str() {
+            "phase" => Some(Grouping::ByPhaseThenDate),
+            "date" => Some(Grouping::ByDateThenPhase),
+            "symbol" => Some(Grouping::BySymbol),
+            "indication" => Some(Grouping::ByIndication),
+            "area" => Some(Grouping::ByTherapeuticArea),
+            "price" => Some(Grouping::ByPriceBucket),
+            "score" => Some(Grouping::ByScore),
+            "flat" => Some(Grouping::Flat),
+            _ => None
+        }
+    }
+}
+
+//A row along with the calendar's stage label for it, e.g. "phase3"
+#[derive(Debug, Eq, PartialEq)]
+pub struct GroupedRow<'a> {
+    pub phase: &'a str,
+    pub row: &'a ParsedRow,
+}
+
+//How far along the calendar's stage labels are, unknown labels first
+fn phase_rank(phase: &str) -> u8 {
+    match phase {
+        "phase1" => 1,
+        "phase1.5" => 2,
+        "phase2" => 3,
+        "phase2.5" => 4,
+        "phase3" => 5,
+        "nda" | "bla" | "pdufa" => 6,
+        "approved" => 7,
+        _ => 0
+    }
+}
+
+//Every catalyst for one symbol, wherever its rows fall in the phase groups
+#[derive(Debug, Eq, PartialEq)]
+pub struct CompanySummary<'a> {
+    pub symbol: &'a str,
+    pub url: &'a str,
+    pub catalyst_count: usize,
+    pub nearest_date: CatalystDate,
+    //Display text of the most advanced row's phase, e.g. "Phase 3"
+    pub highest_phase: &'a str,
+    //"drug: note" for each row
+    pub notes: Vec<String>,
+    //Soonest first
+    pub rows: Vec<GroupedRow<'a>>,
+}
+
+impl<'a> CompanySummary<'a> {
+    //Rows must all be for the same symbol and already in catalyst date order
+    fn new(rows: Vec<GroupedRow<'a>>) -> CompanySummary<'a> {
+        let first = rows[0].row;
+        let highest_phase = rows.iter().max_by_key(|x| phase_rank(x.phase)).map_or("", |x| x.row.phase.as_str());
+        CompanySummary {
+            symbol: &first.symbol,
+            url: &first.url,
+            catalyst_count: rows.len(),
+            nearest_date: first.catalyst_date,
+            highest_phase,
+            notes: rows.iter().map(|x| format!("{}: {}", x.row.drug_name, x.row.catalyst_note)).collect(),
+            rows,
+        }
+    }
+}
+
+//Unlabelled groups are laid out one after the other without a heading
+#[derive(Debug, Eq, PartialEq)]
+pub struct RowGroup<'a> {
+    pub label: Option<String>,
+    pub rows: Vec<GroupedRow<'a>>,
+}
+
+const PRICE_BUCKETS: &[(&str, &str)] = &[("$1", "Under $1"), ("$5", "$1 to $5"), ("$20", "$5 to $20")];
+
+fn price_bucket(price: &currency::USD) -> (usize, &'static str) {
+    PRICE_BUCKETS.iter().enumerate()
+        .find(|(_, (below, _))| *price < currency::USD::new(below).unwrap())
+        .map(|(index, (_, label))| (index, *label))
+        .unwrap_or((PRICE_BUCKETS.len(), "$20 and up"))
+}
+
+//Groups keep the order rows are given in, and are ordered by their key
+fn group_by<'a, K: Ord>(rows: Vec<GroupedRow<'a>>, key_and_label: impl Fn(&GroupedRow<'a>) -> (K, String)) -> Vec<RowGroup<'a>> {
+    let mut groups = BTreeMap::new();
+    for a_row in rows {
+        let (key, label) = key_and_label(&a_row);
+        groups.entry(key).or_insert_with(|| RowGroup { label: Some(label), rows: Vec::new() }).rows.push(a_row);
+    }
+    groups.into_iter().map(|x| x.1).collect()
+}
+
+pub(crate) fn build_selector_for(css_selector: &str) -> Result<Selector, ScrapeError> {
+    Selector::parse(css_selector).map_err(|_| ScrapeError::InvalidSelector(css_selector.to_owned()))
+}
+
+//A selector along with which field it's for, so a field that isn't there can be named
+struct FieldSelector {
+    field: &'static str,
+    css_selector: &'static str,
+    selector: Selector,
+}
+
+impl FieldSelector {
+    fn new(field: &'static str, css_selector: &'static str) -> Result<FieldSelector, ScrapeError> {
+        Ok(FieldSelector { field, css_selector, selector: build_selector_for(css_selector)? })
+    }
+
+    fn not_found(&self, an_element_ref: &ElementRef) -> ScrapeError {
+        ScrapeError::ExpectedFieldNotFound(FieldNotFound::new(self, &an_element_ref.html()))
+    }
 }
 
 //for a lifetime called 'a is borrowed from the lifetime of the given element ref,
 // the returned &str's lifetime needs to be no longer than the given ElementRef
-fn select_first_element_from<'a>(an_element_ref: &ElementRef<'a>, selector: &Selector) -> Result<ElementRef<'a>, ScrapeError> {
-    //Ok or else so it only invokes clone when it needs to. Ok_or would clone each time even though it would be discarded
-    an_element_ref.select(selector).nth(0).ok_or_else(||ScrapeError::ExpectedFieldNotFound(selector.clone()))
+fn select_first_element_from<'a>(an_element_ref: &ElementRef<'a>, selector: &FieldSelector) -> Result<ElementRef<'a>, ScrapeError> {
+    //Ok or else so the html is only copied when it's needed
+    an_element_ref.select(&selector.selector).nth(0).ok_or_else(|| selector.not_found(an_element_ref))
 }
 
-fn select_first_text_from<'a>(an_element_ref: &ElementRef<'a>, selector: &Selector) -> Result<&'a str, ScrapeError> {
+fn select_first_text_from(an_element_ref: &ElementRef, selector: &FieldSelector) -> Result<String, ScrapeError> {
     retrieve_text_from(&select_first_element_from(an_element_ref, selector)?, selector)
 }
 
-fn retrieve_text_from<'a>(an_element_ref: &ElementRef<'a>, underlying_selector: &Selector) -> Result<&'a str, ScrapeError> {
-    an_element_ref.text().next().map(|x| x.trim()).ok_or_else(||ScrapeError::ExpectedFieldNotFound(underlying_selector.clone()))
+//All of the element's text, normalized. An element without any text at all is missing the field
+fn retrieve_text_from(an_element_ref: &ElementRef, underlying_selector: &FieldSelector) -> Result<String, ScrapeError> {
+    match an_element_ref.text().next() {
+        Some(_) => Ok(text::element_text(an_element_ref)),
+        None => Err(underlying_selector.not_found(an_element_ref))
+    }
 }
 
-fn retrieve_attr_from<'a>(an_element_ref: &ElementRef<'a>, attr: &str, underlying_selector: &Selector) -> Result<&'a str, ScrapeError> {
-    an_element_ref.value().attr(attr).ok_or_else(||ScrapeError::ExpectedFieldNotFound(underlying_selector.clone()))
+fn retrieve_attr_from<'a>(an_element_ref: &ElementRef<'a>, attr: &str, underlying_selector: &FieldSelector) -> Result<&'a str, ScrapeError> {
+    an_element_ref.value().attr(attr).ok_or_else(|| underlying_selector.not_found(an_element_ref))
 }
 
 
-impl ScrapedCatalysts {
-    //Should price_limit and retrieval_limit be some kind of predicates instead?
-    fn new(document: &Html, scrape_predicate: (&Fn(&currency::USD) -> bool, &Fn(&NaiveDate) -> bool)) -> Result<ScrapedCatalysts, ScrapeError> {
+//Built once per parse rather than once per row
+struct RowSelectors {
+    event_table_row: FieldSelector,
+    price: FieldSelector,
+    symbol_and_url: FieldSelector,
+    catalyst_date: FieldSelector,
+    drug_name: FieldSelector,
+    drug_indication: FieldSelector,
+    catalyst_note: FieldSelector,
+    phase: FieldSelector,
+    portfolio: FieldSelector,
+    market_cap: FieldSelector,
+    short_ratio: FieldSelector,
+    relative_volume: FieldSelector,
+}
+
+impl RowSelectors {
+    fn new() -> Result<RowSelectors, ScrapeError> {
+        Ok(RowSelectors {
+            event_table_row: FieldSelector::new("event table row", "tr.js-tr.js-drug")?,
+            price: FieldSelector::new("price", "div[class=price]")?,
+            symbol_and_url: FieldSelector::new("symbol", "td a[href]")?,
+            catalyst_date: FieldSelector::new("catalyst date", "time[class=catalyst-date]")?,
+            drug_name: FieldSelector::new("drug name", "strong[class=drug]")?,
+            drug_indication: FieldSelector::new("drug indication", "div[class=indication]")?,
+            catalyst_note: FieldSelector::new("catalyst note", "div[class=catalyst-note]")?,
+            phase: FieldSelector::new("phase", "td.js-td--stage[data-value]")?,
+            portfolio: FieldSelector::new("portfolio", "td.js-td--portfolio[data-portfolio]")?,
+            market_cap: FieldSelector::new("market cap", "td.js-td--market-cap[data-value]")?,
+            short_ratio: FieldSelector::new("short ratio", "td.js-td--short-ratio")?,
+            relative_volume: FieldSelector::new("relative volume", "td.js-td--relative-volume")?,
+        })
+    }
+}
+
+//The row's number and, if it can be read, its symbol are added to any field missing from it
+fn parse_numbered_row(an_event_table_row: &ElementRef, row: usize, selectors: &RowSelectors, predicates: &ScrapePredicates) -> Result<Option<(PhaseLabel, ParsedRow)>, ScrapeError> {
+    parse_row(an_event_table_row, selectors, predicates)
+        .map_err(|x| x.in_row(row, select_first_text_from(an_event_table_row, &selectors.symbol_and_url).ok()))
+}
+
+//Returns None when the row is filtered out by the predicates
+fn parse_row(an_event_table_row: &ElementRef, selectors: &RowSelectors, predicates: &ScrapePredicates) -> Result<Option<(PhaseLabel, ParsedRow)>, ScrapeError> {
+    let price = currency::USD::new(&select_first_text_from(an_event_table_row, &selectors.price)?)?;
+
+    let catalyst_date = CatalystDate::new(&select_first_text_from(an_event_table_row, &selectors.catalyst_date)?)?;
+
+    let url_symbol_ref = select_first_element_from(an_event_table_row, &selectors.symbol_and_url)?;
+    let symbol = retrieve_text_from(&url_symbol_ref, &selectors.symbol_and_url)?;
+
+    if !predicates.test_price(&price, &symbol) || !predicates.test_date(&catalyst_date) {
+        return Ok(None); //if this isn't within our date or price limits we should skip it
+    }
 
-        let (price_limit, date_limit) = scrape_predicate;
+    let url = retrieve_attr_from(&url_symbol_ref, "href", &selectors.symbol_and_url)?.to_owned();
 
-        let event_table_row_selector = build_selector_for("tr.js-tr.js-drug");
-        let price = build_selector_for("div[class=price]");
-        let symbol_and_url = build_selector_for("td a[href]");
-        let catalyst_date = build_selector_for("time[class=catalyst-date]");
-        let drug_name = build_selector_for("strong[class=drug]");
-        let drug_indication = build_selector_for("div[class=indication]");
-        let catalyst_note = build_selector_for("div[class=catalyst-note]");
-        let phase = build_selector_for("td.js-td--stage[data-value]");
+    let drug_name = select_first_text_from(an_event_table_row, &selectors.drug_name)?;
+    let drug_indication = select_first_text_from(an_event_table_row, &selectors.drug_indication)?;
+    let therapeutic_areas = predicates.taxonomy().areas_for(&drug_indication);
+    if !predicates.test_therapeutic_areas(&therapeutic_areas) {
+        return Ok(None);
+    }
+    let catalyst_note = select_first_text_from(an_event_table_row, &selectors.catalyst_note)?;
+
+    let note_classification = catalyst_note::classify_note(&catalyst_note);
+    if !predicates.test_note(&note_classification) {
+        return Ok(None);
+    }
+    let note_events = note_events::extract_note_events(&catalyst_note);
+
+    let phase_element = select_first_element_from(an_event_table_row, &selectors.phase)?;
+    let phase_grouping = PhaseLabel(retrieve_attr_from(&phase_element, "data-value", &selectors.phase)?.to_owned());
+    let phase = retrieve_text_from(&phase_element, &selectors.phase)?;
+
+    //The ids of the user's portfolios holding the ticker, always [] when logged out
+    let in_portfolio = select_first_element_from(an_event_table_row, &selectors.portfolio).ok()
+        .and_then(|x| x.value().attr("data-portfolio"))
+        .map_or(false, |x| !x.trim_start_matches('[').trim_end_matches(']').trim().is_empty());
+
+    let metrics = parse_metrics(an_event_table_row, selectors);
+
+    Ok(Some((phase_grouping, ParsedRow { price, url, symbol, catalyst_date, drug_name, drug_indication, therapeutic_areas, catalyst_note, note_classification, note_events, phase, in_portfolio, company: None, metrics, score: None })))
+}
+
+//These columns are hidden on the page and often blank, so anything missing or unreadable is left as None rather than failing the row
+fn parse_metrics(an_event_table_row: &ElementRef, selectors: &RowSelectors) -> MarketMetrics {
+    //The market cap's data-value is in whole dollars
+    let market_cap = select_first_element_from(an_event_table_row, &selectors.market_cap).ok()
+        .and_then(|x| x.value().attr("data-value"))
+        .and_then(|x| currency::USD::new(&format!("${}", x.trim())).ok());
+    let short_ratio = select_first_text_from(an_event_table_row, &selectors.short_ratio).ok().and_then(|x| Ratio::new(&x));
+    let relative_volume = select_first_text_from(an_event_table_row, &selectors.relative_volume).ok().and_then(|x| Ratio::new(&x));
+    MarketMetrics { market_cap, short_ratio, relative_volume }
+}
+
+impl ScrapedCatalysts {
+    //Selects rows out of a fully built document, kept to benchmark the streaming path against
+    fn from_document(document: &Html, predicates: &ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
+        let selectors = RowSelectors::new()?;
 
         let mut catalysts = BTreeMap::new();
-        for an_event_table_row in document.select(&event_table_row_selector) {
+        for (index, an_event_table_row) in document.select(&selectors.event_table_row.selector).enumerate() {
+            if let Some((phase_grouping, to_insert)) = parse_numbered_row(&an_event_table_row, index + 1, &selectors, predicates)? {
+                catalysts.entry((phase_grouping, to_insert.catalyst_date)).or_insert(Vec::new()).push(to_insert);
+            }
+        }
 
-            let price = currency::USD::new(select_first_text_from(&an_event_table_row, &price)?)?;
+        Ok(ScrapedCatalysts { catalysts })
+    }
 
-            let catalyst_date = NaiveDate::parse_from_str(select_first_text_from(&an_event_table_row, &catalyst_date)?, "%m/%d/%Y")?;
+    //Each row's markup is parsed on its own as it comes off the stream, so only one row's tree is held at a time.
+    //Every row on the page is counted, including those the predicates filter out
+    fn from_row_stream<R: Read>(rows: RowStream<R>, predicates: &ScrapePredicates, rows_on_page: &mut usize) -> Result<ScrapedCatalysts, ScrapeError> {
+        let selectors = RowSelectors::new()?;
 
-            if !price_limit(&price) || !date_limit(&catalyst_date) {
-                continue; //if this isn't within our date or price limits we should skip it
+        let mut catalysts = BTreeMap::new();
+        for (index, row_markup) in rows.enumerate() {
+            let row_markup = row_markup.map_err(ScrapeError::FileReadError)?;
+            *rows_on_page = index + 1;
+            //Rows only parse as rows inside a table, otherwise the tr and td tags are dropped
+            let fragment = Html::parse_fragment(&format!("<table><tbody>{}</tbody></table>", row_markup));
+            let an_event_table_row = select_first_element_from(&fragment.root_element(), &selectors.event_table_row)
+                .map_err(|x| x.in_row(index + 1, None))?;
+            if let Some((phase_grouping, to_insert)) = parse_numbered_row(&an_event_table_row, index + 1, &selectors, predicates)? {
+                catalysts.entry((phase_grouping, to_insert.catalyst_date)).or_insert(Vec::new()).push(to_insert);
             }
+        }
 
-            let url_symbol_ref = select_first_element_from(&an_event_table_row, &symbol_and_url)?;
-            let url = retrieve_attr_from(&url_symbol_ref, "href", &symbol_and_url)?.to_owned();
-            let symbol = retrieve_text_from(&url_symbol_ref, &symbol_and_url)?.to_owned();
+        Ok(ScrapedCatalysts { catalysts })
+    }
 
-            let drug_name = select_first_text_from(&an_event_table_row, &drug_name)?.to_owned();
-            let drug_indication = select_first_text_from(&an_event_table_row, &drug_indication)?.to_owned();
-            let catalyst_note = select_first_text_from(&an_event_table_row, &catalyst_note)?.to_owned();
+    //Number of rows across every grouping
+    pub fn len(&self) -> usize {
+        self.catalysts.values().map(|x| x.len()).sum()
+    }
 
+    pub fn is_empty(&self) -> bool {
+        self.catalysts.is_empty()
+    }
 
-            let phase_element = select_first_element_from(&an_event_table_row, &phase)?;
-            let phase_grouping = PhaseLabel(retrieve_attr_from(&phase_element, "data-value", &phase)?.to_owned());
-            let phase = retrieve_text_from(&phase_element, &phase)?.to_owned();
+    pub fn rows(&self) -> impl Iterator<Item = &ParsedRow> {
+        self.catalysts.values().flatten()
+    }
 
-            let to_insert = ParsedRow { price, url, symbol, catalyst_date, drug_name, drug_indication, catalyst_note, phase };
+    pub(crate) fn rows_mut(&mut self) -> impl Iterator<Item = &mut ParsedRow> {
+        self.catalysts.values_mut().flatten()
+    }
 
-            catalysts.entry((phase_grouping, catalyst_date)).or_insert(Vec::new()).push(to_insert);
+    pub fn score_with(&mut self, scorer: &dyn Scorer, today: NaiveDate) {
+        for ((phase_grouping, _), rows) in self.catalysts.iter_mut() {
+            for a_row in rows.iter_mut() {
+                a_row.score = Option::Some(scorer.score(&phase_grouping.0, a_row, today));
+            }
         }
+    }
 
-        Ok(ScrapedCatalysts { catalysts })
+    //Every row in phase then catalyst date order
+    fn grouped_rows(&self) -> Vec<GroupedRow<'_>> {
+        self.catalysts.iter()
+            .flat_map(|((phase_grouping, _), rows)| rows.iter().map(move |row| GroupedRow { phase: &phase_grouping.0, row }))
+            .collect()
+    }
+
+    //Every row in catalyst date order, ties kept in phase order
+    pub(crate) fn dated_rows(&self) -> Vec<GroupedRow<'_>> {
+        let mut dated = self.grouped_rows();
+        dated.sort_by_key(|x| x.row.catalyst_date);
+        dated
+    }
+
+    //e.g. "3 catalysts, next PDUFA GWPH 2019-05-03", falling back to the nearest catalyst of any kind.
+    //A PDUFA whose note already has an outcome has been decided, so isn't next
+    pub fn summary_line(&self) -> String {
+        let count = match self.len() {
+            0 => return "no catalysts".to_string(),
+            1 => "1 catalyst".to_string(),
+            count => format!("{} catalysts", count)
+        };
+        let nearest = |rows: &mut dyn Iterator<Item = &ParsedRow>| rows.min_by_key(|x| (x.catalyst_date, x.symbol.clone())).map(|x| (x.symbol.clone(), x.catalyst_date));
+        match nearest(&mut self.rows().filter(|x| x.note_classification.event_types.contains(&EventType::Pdufa) && x.note_classification.outcome.is_none())) {
+            Some((symbol, catalyst_date)) => format!("{}, next PDUFA {} {}", count, symbol, catalyst_date),
+            None => match nearest(&mut self.rows()) {
+                Some((symbol, catalyst_date)) => format!("{}, next {} {}", count, symbol, catalyst_date),
+                None => count
+            }
+        }
+    }
+
+    //One summary per symbol, in symbol order
+    pub fn companies(&self) -> Vec<CompanySummary<'_>> {
+        let mut by_symbol = BTreeMap::new();
+        for a_row in self.dated_rows() {
+            by_symbol.entry(a_row.row.symbol.as_str()).or_insert_with(Vec::new).push(a_row);
+        }
+        by_symbol.into_values().map(CompanySummary::new).collect()
+    }
+
+    //Companies with more than one catalyst in the window, most catalysts first then soonest.
+    //Several near term events for one ticker compound its risk
+    pub fn clustered_companies(&self) -> Vec<CompanySummary<'_>> {
+        let mut clustered: Vec<CompanySummary> = self.companies().into_iter().filter(|x| x.catalyst_count > 1).collect();
+        clustered.sort_by(|a, b| b.catalyst_count.cmp(&a.catalyst_count).then(a.nearest_date.cmp(&b.nearest_date)));
+        clustered
+    }
+
+    //The rows laid out in the given grouping, for a report or anything else presenting them
+    pub fn groups(&self, grouping: Grouping) -> Vec<RowGroup<'_>> {
+        match grouping {
+            Grouping::ByPhaseThenDate => self.catalysts.iter()
+                .map(|((phase_grouping, _), rows)| RowGroup {
+                    label: None,
+                    rows: rows.iter().map(|row| GroupedRow { phase: &phase_grouping.0, row }).collect(),
+                })
+                .collect(),
+            Grouping::ByDateThenPhase => group_by(self.grouped_rows(), |x| (x.row.catalyst_date, x.row.catalyst_date.to_string())),
+            Grouping::BySymbol => group_by(self.dated_rows(), |x| (x.row.symbol.clone(), x.row.symbol.clone())),
+            Grouping::ByIndication => group_by(self.dated_rows(), |x| (x.row.drug_indication.to_lowercase(), x.row.drug_indication.clone())),
+            Grouping::ByTherapeuticArea => {
+                let mut by_area = BTreeMap::new();
+                for a_row in self.dated_rows() {
+                    let areas = &a_row.row.therapeutic_areas;
+                    let keys: Vec<(bool, &str)> = if areas.is_empty() { vec![(true, "other")] } else { areas.iter().map(|x| (false, x.as_str())).collect() };
+                    for key in keys {
+                        by_area.entry(key).or_insert_with(Vec::new).push(GroupedRow { phase: a_row.phase, row: a_row.row });
+                    }
+                }
+                by_area.into_iter().map(|((_, area), rows)| RowGroup { label: Some(area.to_string()), rows }).collect()
+            },
+            Grouping::ByPriceBucket => group_by(self.dated_rows(), |x| {
+                let (index, label) = price_bucket(&x.row.price);
+                (index, label.to_string())
+            }),
+            Grouping::ByScore => {
+                //Highest score first, unscored rows last, ties go to the sooner catalyst
+                let mut ranked = self.grouped_rows();
+                ranked.sort_by(|a, b| b.row.score.cmp(&a.row.score).then(a.row.catalyst_date.cmp(&b.row.catalyst_date)).then(a.row.symbol.cmp(&b.row.symbol)));
+                vec![RowGroup { label: None, rows: ranked }]
+            },
+            Grouping::Flat => vec![RowGroup { label: None, rows: self.dated_rows() }],
+        }
     }
 }
 
-pub fn do_scraping(address_to_scrape: &str, price_limit: currency::USD, date_limit: NaiveDate) -> Result<ScrapedCatalysts, ScrapeError> {
+//A page matching the previous download is still parsed, so the report goes out either way
+pub fn do_scraping(fetcher: &dyn Fetcher, archive: Option<&PageArchive>, address_to_scrape: &str, predicates: &ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
     let download_start_time = SystemTime::now();
-    let website_body = reqwest::get(address_to_scrape).unwrap().text().unwrap();
+    let downloaded_page = fetcher.fetch(address_to_scrape)?;
 
     //Duration can return errors if the system clock has been adjusted to prior to app start, etc
     //Since that's unlikely use if let to just log if the parsing went as expected
     // (we don't care for unexpected system clock events)
     if let Ok(download_duration) = download_start_time.elapsed() {
-        info!("Download took {} millis", download_duration.as_millis());
+        logging::event(module_path!(), Level::Info, "downloaded", &[("url", json!(downloaded_page.url)), ("status", json!(downloaded_page.status)), ("bytes", json!(downloaded_page.body.len())), ("millis", json!(download_duration.as_millis() as u64))]);
+        metrics::registry().observe_stage("download", download_duration);
     }
 
-    let write_to_disk_start_time = SystemTime::now();
-    let mut downloaded_fda_events: NamedTempFile = tempfile::NamedTempFile::new().unwrap();
-    downloaded_fda_events.write_all(website_body.as_bytes()).unwrap();
-
-    if let Ok(write_duration) = write_to_disk_start_time.elapsed() {
-        info!("Write took {} millis", write_duration.as_millis());
+    if let Some(archive) = archive {
+        let archive_start_time = SystemTime::now();
+        let archived = archive.store(&downloaded_page)?;
+        if let Ok(archive_duration) = archive_start_time.elapsed() {
+            logging::event(module_path!(), Level::Info, "archived", &[("hash", json!(archived.entry.hash)), ("unchanged", json!(archived.unchanged)), ("millis", json!(archive_duration.as_millis() as u64))]);
+            metrics::registry().observe_stage("archive", archive_duration);
+        }
     }
-
+    //The body's already held whole, as the archive and recordings keep it, so memory still grows with the page.
+    //Streaming it only saves building the document tree
     let parsing_start_time = SystemTime::now();
-    let parsing_result = parse_rows(downloaded_fda_events.path(),
-                                    ScrapePredicates::new().set_price_limit(price_limit).set_date_limit(date_limit));
-    if let Ok(parsing_duration) = parsing_start_time.elapsed() {
-        info!("Parsing took {} millis", parsing_duration.as_millis());
+    let mut rows_on_page = 0;
+    let parsing_result = ScrapedCatalysts::from_row_stream(RowStream::new(downloaded_page.body.as_bytes()), predicates, &mut rows_on_page);
+    let parsing_duration = parsing_start_time.elapsed().unwrap_or_default();
+    metrics::registry().observe_stage("parse", parsing_duration);
+    match &parsing_result {
+        Ok(catalysts) => {
+            logging::event(module_path!(), Level::Info, "parsed", &[("rows", json!(rows_on_page)), ("filtered", json!(rows_on_page - catalysts.len())), ("summary", json!(catalysts.summary_line())), ("millis", json!(parsing_duration.as_millis() as u64))]);
+            metrics::registry().set(&metrics::ROWS_PARSED, &[], rows_on_page as f64);
+            metrics::registry().set(&metrics::ROWS_FILTERED, &[], (rows_on_page - catalysts.len()) as f64);
+        },
+        Err(e) => log_parse_failure(e),
     }
     parsing_result
 }
 
+//How a catalyst dated with a range, like "Q3 2019", is tested against the date limit
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DateWindowMode {
+    //Kept if any part of the range falls before the limit, TBD dates are always kept
+    Overlap,
+    //Kept only if the whole range falls before the limit, TBD dates are always dropped
+    FullyContained,
+}
+
 pub struct ScrapePredicates {
     price_limit: Option<currency::USD>,
-    date_limit: Option<NaiveDate>
+    date_limit: Option<NaiveDate>,
+    date_window_mode: DateWindowMode,
+    event_types: Option<Vec<EventType>>,
+    outcomes: Option<Vec<Outcome>>,
+    always_included: Vec<String>,
+    //The built in taxonomy when None
+    taxonomy: Option<Taxonomy>,
+    therapeutic_areas: Option<Vec<String>>,
 }
 
 impl ScrapePredicates {
     pub fn new() -> ScrapePredicates {
-        ScrapePredicates {price_limit: Option::None, date_limit: Option::None}
+        ScrapePredicates {price_limit: Option::None, date_limit: Option::None, date_window_mode: DateWindowMode::FullyContained, event_types: Option::None, outcomes: Option::None, always_included: Vec::new(), taxonomy: Option::None, therapeutic_areas: Option::None}
     }
 
     pub fn set_price_limit(mut self, price_limit: currency::USD) -> Self {
@@ -214,33 +673,135 @@ impl ScrapePredicates {
         self
     }
 
-    fn test_price(&self, test_value: &currency::USD) -> bool {
+    pub fn set_date_window_mode(mut self, date_window_mode: DateWindowMode) -> Self {
+        self.date_window_mode = date_window_mode;
+        self
+    }
+
+    //Rows are kept if their note mentions any of the given event types
+    pub fn set_event_types(mut self, event_types: Vec<EventType>) -> Self {
+        self.event_types = Option::Some(event_types);
+        self
+    }
+
+    //Rows are kept if their note's outcome is one of the given outcomes, rows without an outcome are dropped
+    pub fn set_outcomes(mut self, outcomes: Vec<Outcome>) -> Self {
+        self.outcomes = Option::Some(outcomes);
+        self
+    }
+
+    //Symbols we hold or watch are kept whatever their price
+    pub fn set_always_included(mut self, symbols: Vec<String>) -> Self {
+        self.always_included = symbols;
+        self
+    }
+
+    pub fn set_taxonomy(mut self, taxonomy: Taxonomy) -> Self {
+        self.taxonomy = Option::Some(taxonomy);
+        self
+    }
+
+    //Rows are kept if their indication falls under any of the given areas, rows under none are dropped
+    pub fn set_therapeutic_areas(mut self, therapeutic_areas: Vec<String>) -> Self {
+        self.therapeutic_areas = Option::Some(therapeutic_areas);
+        self
+    }
+
+    fn taxonomy(&self) -> &Taxonomy {
+        self.taxonomy.as_ref().unwrap_or_else(|| Taxonomy::built_in())
+    }
+
+    fn test_price(&self, test_value: &currency::USD, symbol: &str) -> bool {
+        if self.always_included.iter().any(|x| x.eq_ignore_ascii_case(symbol)) {
+            return true;
+        }
         match &self.price_limit {
             Some(price_limit) => test_value <= price_limit,
             None => true //if no limit was set
         }
     }
 
-    fn test_date(&self, test_value: &NaiveDate) -> bool {
+    fn test_date(&self, test_value: &CatalystDate) -> bool {
         match &self.date_limit {
-            Some(date_limit) => test_value < date_limit,
+            Some(date_limit) => {
+                let compared_day = match self.date_window_mode {
+                    DateWindowMode::Overlap => test_value.start(),
+                    DateWindowMode::FullyContained => test_value.end(),
+                };
+                match compared_day {
+                    Some(compared_day) => compared_day < *date_limit,
+                    None => self.date_window_mode == DateWindowMode::Overlap
+                }
+            },
             None => true //if no limit was set
         }
     }
+
+    fn test_therapeutic_areas(&self, test_value: &[String]) -> bool {
+        match &self.therapeutic_areas {
+            Some(therapeutic_areas) => test_value.iter().any(|x| therapeutic_areas.iter().any(|y| y.eq_ignore_ascii_case(x))),
+            None => true //if no filter was set
+        }
+    }
+
+    fn test_note(&self, test_value: &NoteClassification) -> bool {
+        let event_type_matches = match &self.event_types {
+            Some(event_types) => test_value.event_types.iter().any(|x| event_types.contains(x)),
+            None => true //if no filter was set
+        };
+        let outcome_matches = match &self.outcomes {
+            Some(outcomes) => test_value.outcome.map_or(false, |x| outcomes.contains(&x)),
+            None => true //if no filter was set
+        };
+        event_type_matches && outcome_matches
+    }
 }
 
 pub fn parse_rows(file_path: &Path, predicates: ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
-    let parsed = fs::read_to_string(file_path)
-        .map_err(|x| ScrapeError::FileReadError(x))
-        .map(|contents| Html::parse_document(&contents))
-        .and_then(|document| ScrapedCatalysts::new(&document, (&|x| predicates.test_price(x), &|x| predicates.test_date(x))));
-    match parsed {
-        Ok(_) => info!("parsed = {:?}", parsed),
-        Err(_) => error!("failed = {:?}", parsed),
+    fs::File::open(file_path)
+        .map_err(ScrapeError::FileReadError)
+        .and_then(|file| parse_reader(file, &predicates))
+}
+
+pub fn parse_html(contents: &str, predicates: &ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
+    parse_reader(contents.as_bytes(), predicates)
+}
+
+pub fn parse_reader<R: Read>(input: R, predicates: &ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
+    log_parsed(ScrapedCatalysts::from_row_stream(RowStream::new(input), predicates, &mut 0))
+}
+
+//Builds the whole document before selecting rows, the way parsing used to work
+pub fn parse_html_dom(contents: &str, predicates: &ScrapePredicates) -> Result<ScrapedCatalysts, ScrapeError> {
+    log_parsed(ScrapedCatalysts::from_document(&Html::parse_document(contents), predicates))
+}
+
+//A summary of what was parsed rather than every row, which is too much for a log line
+fn log_parsed(parsed: Result<ScrapedCatalysts, ScrapeError>) -> Result<ScrapedCatalysts, ScrapeError> {
+    match &parsed {
+        Ok(catalysts) => logging::event(module_path!(), Level::Info, "parsed", &[("summary", json!(catalysts.summary_line()))]),
+        Err(e) => log_parse_failure(e),
     }
     parsed
 }
 
+fn log_parse_failure(e: &ScrapeError) {
+    logging::event(module_path!(), Level::Error, "parse_failed", &[("kind", json!(e.kind())), ("error", json!(e.to_string()))]);
+}
+
+pub type ReparsedEntry = (ArchiveEntry, Result<ScrapedCatalysts, ScrapeError>);
+
+//Parses every archived download since the given day again, for rebuilding history after a parser fix
+pub fn reparse_archive(archive: &PageArchive, since: NaiveDate) -> Result<Vec<ReparsedEntry>, ArchiveError> {
+    let mut reparsed = Vec::new();
+    for entry in archive.entries_since(since)? {
+        let contents = archive.load(&entry)?;
+        let parsed = parse_html(&contents, &ScrapePredicates::new());
+        reparsed.push((entry, parsed));
+    }
+    Ok(reparsed)
+}
+
 //Child modules can access "private" module things, so for this test module to access the
 //ParsedRow struct, that's private, we need to be a child module
 #[cfg(test)]
@@ -249,7 +810,17 @@ mod tests {
 
     use super::*;
     use std::collections::btree_map::BTreeMap;
-    use chrono::NaiveDate;
+    use chrono::{NaiveDate, DateTime};
+    use crate::note_events::{NoteEventKind, UsTimeZone};
+    use crate::fetcher::{FetchedPage, HttpFetcher, ReplayFetcher};
+    use crate::archive::RetentionPolicy;
+    use crate::test_support::OneByteReader;
+    use crate::scoring::DefaultScorer;
+    use crate::notifier::{Notifier, NotifyError};
+    use crate::digest::Digest;
+    use crate::report::Report;
+    use askama::Template;
+    use std::cell::RefCell;
 
     #[test]
     #[should_panic(expected = "Failed to parse date")]
@@ -269,21 +840,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn missing_field_located() {
+        //The second row's catalyst note renamed, then the third row's links dropped
+        let html = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+        let renamed = html.replacen("class=\"catalyst-note\"", "class=\"note\"", 2).replacen("class=\"note\"", "class=\"catalyst-note\"", 1);
+        for parse in &[parse_html, parse_html_dom] {
+            match parse(&renamed, &ScrapePredicates::new()) {
+                Err(ScrapeError::ExpectedFieldNotFound(missing)) => {
+                    assert_eq!((missing.field, missing.css_selector, missing.row, missing.symbol.as_deref()), ("catalyst note", "div[class=catalyst-note]", Some(2), Some("GWPH")));
+                    assert!(missing.html.starts_with("<tr class=\"js-tr js-drug\"> <td"), "{}", missing.html);
+                    assert!(missing.html.ends_with("..."));
+                },
+                x => panic!("Unexpected result {:?}", x)
+            }
+        }
+
+        let unlinked = html.replace("<a href=\"https://www.biopharmcatalyst.com/company/EYEN\"", "<a").replace("<a href=\"https://eyenoviabio", "<a data-href=\"https://eyenoviabio");
+        match parse_html(&unlinked, &ScrapePredicates::new()) {
+            Err(ScrapeError::ExpectedFieldNotFound(missing)) => {
+                assert_eq!((missing.field, missing.row, &missing.symbol), ("symbol", Some(3), &None));
+                assert!(missing.to_string().starts_with("Row 3: No symbol matching css selector \"td a[href]\" in <tr"), "{}", missing);
+            },
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn invalid_selector() {
+        match build_selector_for("td[") {
+            Err(ScrapeError::InvalidSelector(css_selector)) => assert_eq!(css_selector, "td["),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+
+    fn date(ymd: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap()
+    }
+
     #[test]
     fn parse_single_row() {
         let expected_row = ParsedRow {
             price: currency::USD::new("$1.26").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/BTX".to_string(),
             symbol: "BTX".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()),
             drug_name: "OpRegen".to_string(),
             drug_indication: "Dry age-related macular degeneration (AMD)".to_string(),
-            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
+            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due May 2, 2019,10:15am ET at ARVO.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::EnrollmentComplete, EventType::ConferencePresentation],
+                outcome: None,
+                conferences: vec!["ARVO".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![NoteEvent {
+                kind: NoteEventKind::Presentation,
+                date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+                time: Some((DateTime::parse_from_rfc3339("2019-05-02T10:15:00-04:00").unwrap(), UsTimeZone::Eastern)),
+            }],
             phase: "Phase 1/2".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$188194767").unwrap()),
+                short_ratio: Ratio::new("12.47"),
+                relative_volume: Ratio::new("1.04"),
+            },
+            score: None,
         };
 
         let mut catalysts = BTreeMap::new();
-        catalysts.insert((PhaseLabel("phase1.5".to_string()), NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()), vec![expected_row]);
+        catalysts.insert((PhaseLabel("phase1.5".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap())), vec![expected_row]);
 
         assert_eq!(ScrapedCatalysts { catalysts },
                    parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_sample.html"), ScrapePredicates::new()).unwrap());
@@ -295,38 +924,97 @@ mod tests {
             price: currency::USD::new("$1.26").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/BTX".to_string(),
             symbol: "BTX".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()),
             drug_name: "OpRegen".to_string(),
             drug_indication: "Dry age-related macular degeneration (AMD)".to_string(),
-            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
+            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due May 2, 2019,10:15am ET at ARVO.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::EnrollmentComplete, EventType::ConferencePresentation],
+                outcome: None,
+                conferences: vec!["ARVO".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![NoteEvent {
+                kind: NoteEventKind::Presentation,
+                date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+                time: Some((DateTime::parse_from_rfc3339("2019-05-02T10:15:00-04:00").unwrap(), UsTimeZone::Eastern)),
+            }],
             phase: "Phase 1/2".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$188194767").unwrap()),
+                short_ratio: Ratio::new("12.47"),
+                relative_volume: Ratio::new("1.04"),
+            },
+            score: None,
         };
 
         let expected_row2 = ParsedRow {
             price: currency::USD::new("$173.16").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/GWPH".to_string(),
             symbol: "GWPH".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()),
             drug_name: "Epidiolex GWPCARE2".to_string(),
             drug_indication: "Dravet Syndrome".to_string(),
+            therapeutic_areas: vec!["neurology".to_string(), "rare disease".to_string()],
             catalyst_note: "Phase 3 data to be presented at AAN in late-breaker May 7, 2019. Abstract embargoed until May 3, 2019.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ConferencePresentation],
+                outcome: None,
+                conferences: vec!["AAN".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-05-07", "%Y-%m-%d").unwrap(), NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![
+                NoteEvent { kind: NoteEventKind::Presentation, date: NaiveDate::parse_from_str("2019-05-07", "%Y-%m-%d").unwrap(), time: None },
+                NoteEvent { kind: NoteEventKind::EmbargoLift, date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(), time: None },
+            ],
             phase: "Phase 3".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$5295132021").unwrap()),
+                short_ratio: Option::None,
+                relative_volume: Ratio::new("0.83"),
+            },
+            score: None,
         };
 
         let expected_row3 = ParsedRow {
             price: currency::USD::new("$6.00").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/EYEN".to_string(),
             symbol: "EYEN".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()),
             drug_name: "MicroStat".to_string(),
             drug_indication: "Mydriasis - pupil dilation".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
             catalyst_note: "Phase 3 trial met primary endpoint - January 31, 2019. Data from second trial also met primary endpoint - February 25, 2019. Detailed data due at (ASCRS) meeting May 3-7, 2019.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::ConferencePresentation],
+                outcome: Some(Outcome::Met),
+                conferences: vec!["ASCRS".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-01-31", "%Y-%m-%d").unwrap(), NaiveDate::parse_from_str("2019-02-25", "%Y-%m-%d").unwrap(), NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![
+                NoteEvent { kind: NoteEventKind::Announcement, date: NaiveDate::parse_from_str("2019-01-31", "%Y-%m-%d").unwrap(), time: None },
+                NoteEvent { kind: NoteEventKind::DataRelease, date: NaiveDate::parse_from_str("2019-02-25", "%Y-%m-%d").unwrap(), time: None },
+                NoteEvent { kind: NoteEventKind::Presentation, date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(), time: None },
+            ],
             phase: "Phase 3".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$72114888").unwrap()),
+                short_ratio: Option::None,
+                relative_volume: Ratio::new("0.54"),
+            },
+            score: None,
         };
 
         let mut catalysts = BTreeMap::new();
-        catalysts.insert((PhaseLabel("phase1.5".to_string()), NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()), vec![expected_row1]);
-        catalysts.insert((PhaseLabel("phase3".to_string()), NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()), vec![expected_row2, expected_row3]);
+        catalysts.insert((PhaseLabel("phase1.5".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap())), vec![expected_row1]);
+        catalysts.insert((PhaseLabel("phase3".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap())), vec![expected_row2, expected_row3]);
 
         assert_eq!(ScrapedCatalysts { catalysts }, parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap());
     }
@@ -337,27 +1025,67 @@ mod tests {
             price: currency::USD::new("$1.26").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/BTX".to_string(),
             symbol: "BTX".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()),
             drug_name: "OpRegen".to_string(),
             drug_indication: "Dry age-related macular degeneration (AMD)".to_string(),
-            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
+            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due May 2, 2019,10:15am ET at ARVO.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::EnrollmentComplete, EventType::ConferencePresentation],
+                outcome: None,
+                conferences: vec!["ARVO".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![NoteEvent {
+                kind: NoteEventKind::Presentation,
+                date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+                time: Some((DateTime::parse_from_rfc3339("2019-05-02T10:15:00-04:00").unwrap(), UsTimeZone::Eastern)),
+            }],
             phase: "Phase 1/2".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$188194767").unwrap()),
+                short_ratio: Ratio::new("12.47"),
+                relative_volume: Ratio::new("1.04"),
+            },
+            score: None,
         };
 
         let expected_row2 = ParsedRow {
             price: currency::USD::new("$6.00").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/EYEN".to_string(),
             symbol: "EYEN".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()),
             drug_name: "MicroStat".to_string(),
             drug_indication: "Mydriasis - pupil dilation".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
             catalyst_note: "Phase 3 trial met primary endpoint - January 31, 2019. Data from second trial also met primary endpoint - February 25, 2019. Detailed data due at (ASCRS) meeting May 3-7, 2019.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::ConferencePresentation],
+                outcome: Some(Outcome::Met),
+                conferences: vec!["ASCRS".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-01-31", "%Y-%m-%d").unwrap(), NaiveDate::parse_from_str("2019-02-25", "%Y-%m-%d").unwrap(), NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![
+                NoteEvent { kind: NoteEventKind::Announcement, date: NaiveDate::parse_from_str("2019-01-31", "%Y-%m-%d").unwrap(), time: None },
+                NoteEvent { kind: NoteEventKind::DataRelease, date: NaiveDate::parse_from_str("2019-02-25", "%Y-%m-%d").unwrap(), time: None },
+                NoteEvent { kind: NoteEventKind::Presentation, date: NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap(), time: None },
+            ],
             phase: "Phase 3".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$72114888").unwrap()),
+                short_ratio: Option::None,
+                relative_volume: Ratio::new("0.54"),
+            },
+            score: None,
         };
 
         let mut catalysts = BTreeMap::new();
-        catalysts.insert((PhaseLabel("phase1.5".to_string()), NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()), vec![expected_row1]);
-        catalysts.insert((PhaseLabel("phase3".to_string()), NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap()), vec![expected_row2]);
+        catalysts.insert((PhaseLabel("phase1.5".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap())), vec![expected_row1]);
+        catalysts.insert((PhaseLabel("phase3".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap())), vec![expected_row2]);
 
         assert_eq!(ScrapedCatalysts { catalysts },
                    parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
@@ -370,15 +1098,35 @@ mod tests {
             price: currency::USD::new("$1.26").unwrap(),
             url: "https://www.biopharmcatalyst.com/company/BTX".to_string(),
             symbol: "BTX".to_string(),
-            catalyst_date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+            catalyst_date: CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()),
             drug_name: "OpRegen".to_string(),
             drug_indication: "Dry age-related macular degeneration (AMD)".to_string(),
-            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.".to_string(),
+            therapeutic_areas: vec!["ophthalmology".to_string()],
+            catalyst_note: "Phase 1/2 enrolment to be completed 2019. Updated data due May 2, 2019,10:15am ET at ARVO.".to_string(),
+            note_classification: NoteClassification {
+                event_types: vec![EventType::ToplineData, EventType::EnrollmentComplete, EventType::ConferencePresentation],
+                outcome: None,
+                conferences: vec!["ARVO".to_string()],
+                embedded_dates: vec![NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()],
+            },
+            note_events: vec![NoteEvent {
+                kind: NoteEventKind::Presentation,
+                date: NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap(),
+                time: Some((DateTime::parse_from_rfc3339("2019-05-02T10:15:00-04:00").unwrap(), UsTimeZone::Eastern)),
+            }],
             phase: "Phase 1/2".to_string(),
+            in_portfolio: false,
+            company: None,
+            metrics: MarketMetrics {
+                market_cap: Some(currency::USD::new("$188194767").unwrap()),
+                short_ratio: Ratio::new("12.47"),
+                relative_volume: Ratio::new("1.04"),
+            },
+            score: None,
         };
 
         let mut catalysts = BTreeMap::new();
-        catalysts.insert((PhaseLabel("phase1.5".to_string()), NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap()), vec![expected_row]);
+        catalysts.insert((PhaseLabel("phase1.5".to_string()), CatalystDate::exact(NaiveDate::parse_from_str("2019-05-02", "%Y-%m-%d").unwrap())), vec![expected_row]);
 
         let date_limit = NaiveDate::parse_from_str("2019-05-03", "%Y-%m-%d").unwrap();
         assert_eq!(ScrapedCatalysts { catalysts },
@@ -395,4 +1143,290 @@ mod tests {
                    parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
                                               ScrapePredicates::new().set_price_limit(price_limit).set_date_limit(date_limit)).unwrap());
     }
+
+    //Each row's phase label, catalyst date and symbol, for tests about which rows a parse keeps rather than what's in them
+    fn kept_rows(scraped: &ScrapedCatalysts) -> Vec<(&str, CatalystDate, &str)> {
+        scraped.catalysts.iter()
+            .flat_map(|((phase_grouping, catalyst_date), rows)| rows.iter().map(move |x| (phase_grouping.0.as_str(), *catalyst_date, x.symbol.as_str())))
+            .collect()
+    }
+
+    #[test]
+    fn parse_with_event_type_filter() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
+                                 ScrapePredicates::new().set_event_types(vec![EventType::ToplineData])).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase1.5", CatalystDate::exact(date("2019-05-02")), "BTX"), ("phase3", CatalystDate::exact(date("2019-05-03")), "EYEN")]);
+    }
+
+    #[test]
+    fn parse_with_outcome_filter() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
+                                 ScrapePredicates::new().set_outcomes(vec![Outcome::Met, Outcome::Approved])).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase3", CatalystDate::exact(date("2019-05-03")), "EYEN")]);
+    }
+
+    #[test]
+    fn parse_fuzzy_dates() {
+        let quarter = CatalystDate::new("Q2 2019").unwrap();
+        let exact = CatalystDate::exact(date("2019-05-01"));
+
+        //The fixture repeats the BTX row under different tickers and catalyst dates
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_fuzzy_dates.html"), ScrapePredicates::new()).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase1.5", quarter, "BTX"), ("phase1.5", exact, "EFGH"), ("phase1.5", CatalystDate::Tbd, "ABCD")]);
+        let efgh = scraped.rows().find(|x| x.symbol == "EFGH").unwrap();
+        assert_eq!((efgh.url.as_str(), efgh.drug_name.as_str()), ("https://www.biopharmcatalyst.com/company/EFGH", "OpRegen"));
+    }
+
+    #[test]
+    fn parse_fuzzy_dates_fully_contained() {
+        let exact = CatalystDate::exact(date("2019-05-01"));
+
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_fuzzy_dates.html"),
+                                 ScrapePredicates::new().set_date_limit(date("2019-05-02"))).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase1.5", exact, "EFGH")]);
+    }
+
+    #[test]
+    fn parse_fuzzy_dates_overlapping() {
+        let quarter = CatalystDate::new("Q2 2019").unwrap();
+        let exact = CatalystDate::exact(date("2019-05-01"));
+
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_fuzzy_dates.html"),
+                                 ScrapePredicates::new().set_date_limit(date("2019-05-02")).set_date_window_mode(DateWindowMode::Overlap)).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase1.5", quarter, "BTX"), ("phase1.5", exact, "EFGH"), ("phase1.5", CatalystDate::Tbd, "ABCD")]);
+    }
+
+    #[test]
+    fn watchlisted_symbols_ignore_the_price_ceiling() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
+                                 ScrapePredicates::new().set_price_limit(currency::USD::new("$5").unwrap()).set_always_included(vec!["gwph".to_string()])).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase1.5", CatalystDate::exact(date("2019-05-02")), "BTX"), ("phase3", CatalystDate::exact(date("2019-05-03")), "GWPH")]);
+    }
+
+    fn group_symbols(scraped: &ScrapedCatalysts, grouping: Grouping) -> Vec<(Option<String>, Vec<&str>)> {
+        scraped.groups(grouping).into_iter()
+            .map(|x| (x.label, x.rows.iter().map(|x| x.row.symbol.as_str()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn filter_by_therapeutic_area() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
+                                 ScrapePredicates::new().set_therapeutic_areas(vec!["Rare Disease".to_string(), "oncology".to_string()])).unwrap();
+        assert_eq!(kept_rows(&scraped), vec![("phase3", CatalystDate::exact(date("2019-05-03")), "GWPH")]);
+
+        //A taxonomy of our own leaves anything it doesn't mention unclassified
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"),
+                                 ScrapePredicates::new().set_taxonomy(Taxonomy::parse("eyes = macular degeneration").unwrap())).unwrap();
+        let areas: Vec<(&str, &Vec<String>)> = scraped.rows().map(|x| (x.symbol.as_str(), &x.therapeutic_areas)).collect();
+        assert_eq!(areas, vec![("BTX", &vec!["eyes".to_string()]), ("GWPH", &Vec::new()), ("EYEN", &Vec::new())]);
+    }
+
+    #[test]
+    fn company_view() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html"), ScrapePredicates::new()).unwrap();
+
+        let companies = scraped.companies();
+        let summary: Vec<(&str, usize, CatalystDate, &str)> = companies.iter().map(|x| (x.symbol, x.catalyst_count, x.nearest_date, x.highest_phase)).collect();
+        assert_eq!(summary, vec![
+            ("BTX", 1, CatalystDate::exact(date("2019-05-02")), "Phase 1/2"),
+            ("EYEN", 1, CatalystDate::exact(date("2019-05-03")), "Phase 3"),
+            ("GWPH", 2, CatalystDate::exact(date("2019-05-03")), "Phase 3"),
+        ]);
+
+        let clustered = scraped.clustered_companies();
+        assert_eq!(clustered.len(), 1);
+        assert_eq!(clustered[0].url, "https://www.biopharmcatalyst.com/company/GWPH");
+        assert_eq!(clustered[0].notes, vec![
+            "Epidiolex GWPCARE2: Phase 3 data to be presented at AAN in late-breaker May 7, 2019. Abstract embargoed until May 3, 2019.".to_string(),
+            "Sativex: Phase 2 topline data due May 6, 2019.".to_string(),
+        ]);
+
+        let rendered = Report::new(&scraped).render().unwrap();
+        assert!(rendered.contains("Companies with several upcoming catalysts"));
+        assert!(rendered.contains("<td>2</td>"));
+        //No clusters, no section
+        let single = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        assert!(!Report::new(&single).render().unwrap().contains("Companies with several upcoming catalysts"));
+    }
+
+    #[test]
+    fn nested_and_escaped_text_normalized() {
+        let html = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap()
+            .replace("Updated data due  May 2", "Updated <b>data</b> due&nbsp; <a href=\"#\">May 2</a>")
+            .replace("<strong class=\"drug\">OpRegen </strong>", "<strong class=\"drug\">\n  Op<em>Regen</em>&amp;\u{200B} </strong>");
+        let scraped = parse_html(&html, &ScrapePredicates::new()).unwrap();
+        let btx = scraped.rows().find(|x| x.symbol == "BTX").unwrap();
+        assert_eq!(btx.drug_name, "OpRegen&");
+        assert_eq!(btx.catalyst_note, "Phase 1/2 enrolment to be completed 2019. Updated data due May 2, 2019,10:15am ET at ARVO.");
+    }
+
+    #[test]
+    fn subject_summary() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        assert_eq!(scraped.summary_line(), "3 catalysts, next BTX 2019-05-02");
+
+        //A PDUFA is named ahead of a sooner catalyst of another kind, the first symbol of those on the same day
+        let mut scraped = scraped;
+        for a_row in scraped.rows_mut().filter(|x| x.symbol != "BTX") {
+            a_row.note_classification.event_types.push(EventType::Pdufa);
+        }
+        //EYEN's note says its trial already met its endpoint
+        assert_eq!(scraped.summary_line(), "3 catalysts, next PDUFA GWPH 2019-05-03");
+        scraped.rows_mut().find(|x| x.symbol == "EYEN").unwrap().note_classification.outcome = None;
+        assert_eq!(scraped.summary_line(), "3 catalysts, next PDUFA EYEN 2019-05-03");
+
+        let scraped = ScrapedCatalysts { catalysts: BTreeMap::new() };
+        assert_eq!(scraped.summary_line(), "no catalysts");
+    }
+
+    #[test]
+    fn ranked_by_score() {
+        let mut scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        scraped.score_with(&DefaultScorer, date("2019-05-01"));
+        let ranked: Vec<(&str, Option<u32>)> = scraped.groups(Grouping::ByScore)[0].rows.iter().map(|x| (x.row.symbol.as_str(), x.row.score)).collect();
+        assert_eq!(ranked, vec![("BTX", Some(74)), ("EYEN", Some(66)), ("GWPH", Some(48))]);
+
+        //Grouped by phase the phase 1/2 row still comes first, so check the order of the two phase 3 rows instead
+        let by_phase = Report::new(&scraped).render().unwrap();
+        assert!(by_phase.find(">GWPH<").unwrap() < by_phase.find(">EYEN<").unwrap());
+        let by_score = Report::new(&scraped).set_grouping(Grouping::from_name("score").unwrap()).render().unwrap();
+        assert!(by_score.find(">EYEN<").unwrap() < by_score.find(">GWPH<").unwrap());
+        assert!(by_score.contains("<small title=\"Risk score out of 100\">74</small>"));
+    }
+
+    #[test]
+    fn configurable_groupings() {
+        let scraped = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let label = |x: &str| Some(x.to_string());
+        assert_eq!(group_symbols(&scraped, Grouping::ByPhaseThenDate), vec![(None, vec!["BTX"]), (None, vec!["GWPH", "EYEN"])]);
+        assert_eq!(group_symbols(&scraped, Grouping::ByDateThenPhase), vec![(label("2019-05-02"), vec!["BTX"]), (label("2019-05-03"), vec!["GWPH", "EYEN"])]);
+        assert_eq!(group_symbols(&scraped, Grouping::BySymbol), vec![(label("BTX"), vec!["BTX"]), (label("EYEN"), vec!["EYEN"]), (label("GWPH"), vec!["GWPH"])]);
+        assert_eq!(group_symbols(&scraped, Grouping::ByIndication), vec![
+            (label("Dravet Syndrome"), vec!["GWPH"]),
+            (label("Dry age-related macular degeneration (AMD)"), vec!["BTX"]),
+            (label("Mydriasis - pupil dilation"), vec!["EYEN"]),
+        ]);
+        assert_eq!(group_symbols(&scraped, Grouping::ByTherapeuticArea), vec![
+            (label("neurology"), vec!["GWPH"]),
+            (label("ophthalmology"), vec!["BTX", "EYEN"]),
+            (label("rare disease"), vec!["GWPH"]),
+        ]);
+        assert_eq!(group_symbols(&scraped, Grouping::ByPriceBucket), vec![(label("$1 to $5"), vec!["BTX"]), (label("$5 to $20"), vec!["EYEN"]), (label("$20 and up"), vec!["GWPH"])]);
+        assert_eq!(group_symbols(&scraped, Grouping::Flat), vec![(None, vec!["BTX", "GWPH", "EYEN"])]);
+        let rendered = Report::new(&scraped).set_grouping(Grouping::Flat).render().unwrap();
+        assert!(!rendered.contains("<th colspan=\"5\""));
+
+        let rendered = Report::new(&scraped).set_grouping(Grouping::from_name("Price").unwrap()).render().unwrap();
+        assert!(rendered.find("$1 to $5</th>").unwrap() < rendered.find(">BTX<").unwrap());
+        assert_eq!(Grouping::from_name("alphabetical"), None);
+    }
+
+    #[test]
+    fn parse_portfolio_flags() {
+        let mut flagged = parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_portfolio.html"), ScrapePredicates::new()).unwrap();
+        let flags: Vec<(&str, bool)> = flagged.rows().map(|x| (x.symbol.as_str(), x.in_portfolio)).collect();
+        assert_eq!(flags, vec![("BTX", true), ("GWPH", false), ("EYEN", false)]);
+
+        //Otherwise the same rows as the page without a portfolio
+        for a_row in flagged.rows_mut() {
+            a_row.in_portfolio = false;
+        }
+        assert_eq!(flagged, parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap());
+    }
+
+    #[test]
+    fn streaming_and_dom_parsing_agree() {
+        for fixture in &["fda_calendar_sample.html", "fda_calendar_multiple_rows.html", "fda_calendar_fuzzy_dates.html", "fda_calendar_portfolio.html", "fda_calendar_clustered.html"] {
+            let contents = fs::read_to_string(Path::new("test-resources/fda_calendar_sample_files").join(fixture)).unwrap();
+            assert_eq!(parse_html_dom(&contents, &ScrapePredicates::new()).unwrap(),
+                       parse_html(&contents, &ScrapePredicates::new()).unwrap(), "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn parse_reader_with_tiny_reads() {
+        let contents = fs::read("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+
+        assert_eq!(parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap(),
+                   parse_reader(OneByteReader(&contents), &ScrapePredicates::new()).unwrap());
+    }
+
+    struct CapturingNotifier {
+        sent: RefCell<Vec<String>>
+    }
+
+    impl Notifier for CapturingNotifier {
+        fn recipient(&self) -> String {
+            "someone@example.com".to_string()
+        }
+
+        fn notify(&self, report: &Report) -> Result<(), NotifyError> {
+            self.sent.borrow_mut().push(report.render()?);
+            Ok(())
+        }
+
+        fn send_digest(&self, digest: &Digest) -> Result<(), NotifyError> {
+            self.sent.borrow_mut().push(digest.html.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replayed_fetch_parse_render_notify() {
+        let fetcher = ReplayFetcher::new(Path::new("test-resources/recordings"));
+        let predicates = ScrapePredicates::new().set_price_limit(currency::USD::new("$6").unwrap()).set_date_limit(date("2019-05-03"));
+        let scraped = do_scraping(&fetcher, None, "https://www.biopharmcatalyst.com/calendars/fda-calendar", &predicates).unwrap();
+
+        let notifier = CapturingNotifier { sent: RefCell::new(Vec::new()) };
+        notifier.notify(&Report::new(&scraped)).unwrap();
+
+        let sent = notifier.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].contains(">BTX</a>"));
+        assert!(sent[0].contains("Presentation May 02 2019 10:15am EDT"));
+        assert!(!sent[0].contains("GWPH"));
+        assert!(!sent[0].contains("EYEN"));
+    }
+
+    #[test]
+    fn fetch_from_stub_server() {
+        let recording = FetchedPage::from_recording(&fs::read_to_string(
+            "test-resources/recordings/www_biopharmcatalyst_com_calendars_fda_calendar_898a9f3c596b.http").unwrap()).unwrap();
+        let url = crate::fetcher::tests::serve_recording(recording, 1);
+
+        let predicates = ScrapePredicates::new().set_price_limit(currency::USD::new("$200").unwrap()).set_date_limit(date("2019-05-04"));
+        let scraped = do_scraping(&HttpFetcher::new(), None, &url, &predicates).unwrap();
+
+        //The recording is of the multiple rows page
+        assert_eq!(parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap(), scraped);
+    }
+
+    #[test]
+    fn unchanged_archived_page_still_parsed() {
+        let fetcher = ReplayFetcher::new(Path::new("test-resources/recordings"));
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+        let address = "https://www.biopharmcatalyst.com/calendars/fda-calendar";
+
+        let predicates = ScrapePredicates::new().set_price_limit(currency::USD::new("$6").unwrap()).set_date_limit(date("2019-05-03"));
+
+        let first = do_scraping(&fetcher, Some(&archive), address, &predicates).unwrap();
+        assert_eq!(do_scraping(&fetcher, Some(&archive), address, &predicates).unwrap(), first);
+        assert_eq!(archive.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reparse_from_archive() {
+        let fetcher = ReplayFetcher::new(Path::new("test-resources/recordings"));
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+        archive.store(&fetcher.fetch("https://www.biopharmcatalyst.com/calendars/fda-calendar").unwrap()).unwrap();
+
+        let reparsed = reparse_archive(&archive, date("2019-05-01")).unwrap();
+        assert_eq!(reparsed.len(), 1);
+        assert_eq!(reparsed[0].1.as_ref().unwrap(), &parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap());
+
+        assert!(reparse_archive(&archive, date("2019-05-02")).unwrap().is_empty());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,431 @@
+use chrono::{DateTime, Utc};
+use log::info;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::archive::content_hash;
+
+//Everything the scraper needs from a response, and everything a recording has to keep to be replayed later
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FetchedPage {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub fetched_at: DateTime<Utc>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    HttpError(reqwest::Error),
+    RecordingIoError(PathBuf, io::Error),
+    MalformedRecording(PathBuf),
+    InvalidUrl(String),
+    DisallowedByRobots(String),
+    DecodeError(String, io::Error),
+    CacheIoError(PathBuf, io::Error),
+    CookieJarIoError(PathBuf, io::Error),
+    LoginFailed(String),
+    LoggedOut(String),
+    TooManyRedirects(String),
+    //The url and the status it answered with
+    ErrorStatus(String, u16),
+    //The header's name, for a value that can't be sent in it
+    InvalidHeaderValue(String, reqwest::header::InvalidHeaderValue),
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        FetchError::HttpError(e)
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FetchError::HttpError(ref e) => std::fmt::Display::fmt(&e, f),
+            FetchError::RecordingIoError(ref path, ref e) => write!(f, "Recording {:?}: {}", path, e),
+            FetchError::MalformedRecording(ref path) => write!(f, "Malformed recording {:?}", path),
+            FetchError::InvalidUrl(ref url) => write!(f, "Invalid url {}", url),
+            FetchError::DisallowedByRobots(ref url) => write!(f, "robots.txt disallows fetching {}", url),
+            FetchError::DecodeError(ref url, ref e) => write!(f, "Couldn't decode the body of {}: {}", url, e),
+            FetchError::CacheIoError(ref path, ref e) => write!(f, "HTTP cache {:?}: {}", path, e),
+            FetchError::CookieJarIoError(ref path, ref e) => write!(f, "Cookie jar {:?}: {}", path, e),
+            FetchError::LoginFailed(ref url) => write!(f, "Logging in at {} failed", url),
+            FetchError::LoggedOut(ref url) => write!(f, "Still logged out after logging in, fetching {}", url),
+            FetchError::TooManyRedirects(ref url) => write!(f, "Too many redirects fetching {}", url),
+            FetchError::ErrorStatus(ref url, status) => write!(f, "{} answered with status {}", url, status),
+            FetchError::InvalidHeaderValue(ref name, _) => write!(f, "The {} header's value can't be sent", name),
+        }
+    }
+}
+
+impl error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FetchError::HttpError(ref e) => Some(e),
+            FetchError::RecordingIoError(_, ref e) => Some(e),
+            FetchError::MalformedRecording(_) => None,
+            FetchError::InvalidUrl(_) => None,
+            FetchError::DisallowedByRobots(_) => None,
+            FetchError::DecodeError(_, ref e) => Some(e),
+            FetchError::CacheIoError(_, ref e) => Some(e),
+            FetchError::CookieJarIoError(_, ref e) => Some(e),
+            FetchError::LoginFailed(_) => None,
+            FetchError::LoggedOut(_) => None,
+            FetchError::TooManyRedirects(_) => None,
+            FetchError::ErrorStatus(_, _) => None,
+            FetchError::InvalidHeaderValue(_, ref e) => Some(e),
+        }
+    }
+}
+
+pub trait Fetcher {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError>;
+}
+
+//Lets one fetcher be shared between the calendar scrape and the company page workers
+impl<F: Fetcher + ?Sized> Fetcher for Arc<F> {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        self.as_ref().fetch(url)
+    }
+}
+
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    pub fn new() -> HttpFetcher {
+        HttpFetcher { client: reqwest::Client::new() }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        let mut response = self.client.get(url).send()?.error_for_status()?;
+        let headers = response.headers().iter()
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str().to_owned(), value.to_owned())))
+            .collect();
+        Ok(FetchedPage {
+            url: url.to_owned(),
+            status: response.status().as_u16(),
+            headers,
+            fetched_at: Utc::now(),
+            body: response.text()?,
+        })
+    }
+}
+
+//Fetches through another fetcher and saves every page it sees, the latest fetch of a url wins
+pub struct RecordingFetcher<F: Fetcher> {
+    inner: F,
+    fixtures_dir: PathBuf,
+}
+
+impl<F: Fetcher> RecordingFetcher<F> {
+    pub fn new(inner: F, fixtures_dir: &Path) -> RecordingFetcher<F> {
+        RecordingFetcher { inner, fixtures_dir: fixtures_dir.to_owned() }
+    }
+}
+
+impl<F: Fetcher> Fetcher for RecordingFetcher<F> {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        let page = self.inner.fetch(url)?;
+        let recording_path = recording_path_for(&self.fixtures_dir, url);
+        fs::create_dir_all(&self.fixtures_dir)
+            .and_then(|_| fs::write(&recording_path, page.to_recording()))
+            .map_err(|x| FetchError::RecordingIoError(recording_path.clone(), x))?;
+        info!("Recorded {} to {:?}", url, recording_path);
+        Ok(page)
+    }
+}
+
+//Serves pages saved by a RecordingFetcher without touching the network
+pub struct ReplayFetcher {
+    fixtures_dir: PathBuf,
+}
+
+impl ReplayFetcher {
+    pub fn new(fixtures_dir: &Path) -> ReplayFetcher {
+        ReplayFetcher { fixtures_dir: fixtures_dir.to_owned() }
+    }
+}
+
+impl Fetcher for ReplayFetcher {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        let recording_path = recording_path_for(&self.fixtures_dir, url);
+        let recording = fs::read_to_string(&recording_path)
+            .map_err(|x| FetchError::RecordingIoError(recording_path.clone(), x))?;
+        FetchedPage::from_recording(&recording).ok_or_else(|| FetchError::MalformedRecording(recording_path))
+    }
+}
+
+//A token bucket per host, each request takes a token and tokens come back at requests_per_second up to burst.
+//Shared by every thread fetching through the same fetcher
+pub struct HostRateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    buckets_by_host: Mutex<HashMap<String, TokenBucket>>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl HostRateLimiter {
+    pub fn new(requests_per_second: f64, burst: u32) -> HostRateLimiter {
+        HostRateLimiter { requests_per_second, burst: f64::from(burst.max(1)), buckets_by_host: Mutex::new(HashMap::new()) }
+    }
+
+    //Blocks until the url's host has a token free
+    pub fn acquire(&self, url: &str) {
+        let wait = self.reserve(&host_of(url), Instant::now());
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+
+    //Takes the token under the lock even if it means going into debt, the waiting happens outside it so other hosts aren't held up
+    fn reserve(&self, host: &str, now: Instant) -> Duration {
+        let mut buckets_by_host = self.buckets_by_host.lock().unwrap();
+        let bucket = buckets_by_host.entry(host.to_owned()).or_insert(TokenBucket { tokens: self.burst, refilled_at: now });
+
+        let refilled = duration_as_secs(now - bucket.refilled_at) * self.requests_per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst) - 1.0;
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_millis((-bucket.tokens / self.requests_per_second * 1000.0).ceil() as u64)
+        }
+    }
+}
+
+fn duration_as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+//Host and port, so servers on the same machine are limited separately
+pub fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url).ok()
+        .and_then(|x| x.host_str().map(|host| format!("{}:{}", host, x.port_or_known_default().unwrap_or(0))))
+        .unwrap_or_default()
+}
+
+const MAX_RECORDING_NAME_CHARS: usize = 100;
+
+//Recordings are named after the url with the scheme dropped then the start of the url's hash, as urls differing only
+//in punctuation read the same, e.g. www_biopharmcatalyst_com_calendars_fda_calendar_898a9f3c596b.http.
+//Long urls are cut short, the hash still tells them apart, so the name stays within file systems' limits
+pub fn recording_path_for(fixtures_dir: &Path, url: &str) -> PathBuf {
+    let without_scheme = url.splitn(2, "://").last().unwrap_or(url);
+    let file_name: String = without_scheme.trim_end_matches('/').chars()
+        .take(MAX_RECORDING_NAME_CHARS)
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    fixtures_dir.join(format!("{}_{}.http", file_name, &content_hash(url)[..12]))
+}
+
+const RECORDED_URL_HEADER: &str = "X-Recorded-Url";
+const RECORDED_AT_HEADER: &str = "X-Recorded-At";
+
+//A recording is laid out like the raw HTTP response, with the url and fetch time as extra headers
+impl FetchedPage {
+    pub fn to_recording(&self) -> String {
+        let mut recording = format!("HTTP/1.1 {}\n{}: {}\n{}: {}\n", self.status,
+                                    RECORDED_URL_HEADER, self.url, RECORDED_AT_HEADER, self.fetched_at.to_rfc3339());
+        for (name, value) in &self.headers {
+            recording.push_str(&format!("{}: {}\n", name, value));
+        }
+        recording.push('\n');
+        recording.push_str(&self.body);
+        recording
+    }
+
+    //Header names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|x| x.0.eq_ignore_ascii_case(name)).map(|x| x.1.as_str())
+    }
+
+    pub fn from_recording(recording: &str) -> Option<FetchedPage> {
+        let head_end = recording.find("\n\n")?;
+        let body = recording[head_end + 2..].to_owned();
+        let mut head_lines = recording[..head_end].lines();
+
+        let status = head_lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+
+        let mut url = None;
+        let mut fetched_at = None;
+        let mut headers = Vec::new();
+        for a_line in head_lines {
+            let mut name_and_value = a_line.splitn(2, ':');
+            let name = name_and_value.next()?.trim();
+            let value = name_and_value.next()?.trim();
+            if name == RECORDED_URL_HEADER {
+                url = Some(value.to_owned());
+            } else if name == RECORDED_AT_HEADER {
+                fetched_at = DateTime::parse_from_rfc3339(value).ok().map(|x| x.with_timezone(&Utc));
+            } else {
+                headers.push((name.to_owned(), value.to_owned()));
+            }
+        }
+
+        Some(FetchedPage { url: url?, status, headers, fetched_at: fetched_at?, body })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::net::{TcpListener, SocketAddr};
+    use std::io::{Read, Write};
+    use std::thread;
+
+    //Answers each incoming connection with whatever the handler returns for its request
+    pub fn serve<H>(connections: usize, handler: H) -> SocketAddr where H: Fn(&str) -> Vec<u8> + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                //Reads the head, then as much body as its Content-Length says
+                loop {
+                    let request_text = String::from_utf8_lossy(&request).to_lowercase();
+                    if let Some(head_end) = request_text.find("\r\n\r\n") {
+                        let content_length: usize = request_text[..head_end].lines()
+                            .find(|x| x.starts_with("content-length:"))
+                            .and_then(|x| x["content-length:".len()..].trim().parse().ok())
+                            .unwrap_or(0);
+                        if request.len() >= head_end + 4 + content_length {
+                            break;
+                        }
+                    }
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                stream.write_all(&handler(&String::from_utf8_lossy(&request))).unwrap();
+            }
+        });
+        address
+    }
+
+    pub fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {} OK\r\n", status);
+        for (name, value) in headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    //Serves the recording's status, headers and body to each incoming connection, standing in for the live site
+    pub fn serve_recording(recording: FetchedPage, connections: usize) -> String {
+        let address = serve(connections, move |_| {
+            let headers: Vec<(&str, &str)> = recording.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+            response(recording.status, &headers, recording.body.as_bytes())
+        });
+        format!("http://{}/calendars/fda-calendar", address)
+    }
+
+    fn sample_page() -> FetchedPage {
+        FetchedPage {
+            url: "https://www.biopharmcatalyst.com/calendars/fda-calendar".to_string(),
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/html; charset=UTF-8".to_string())],
+            fetched_at: DateTime::parse_from_rfc3339("2019-05-01T12:00:00Z").unwrap().with_timezone(&Utc),
+            body: "<html>\n\n<body>calendar</body></html>".to_string(),
+        }
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let page = sample_page();
+        assert_eq!(FetchedPage::from_recording(&page.to_recording()), Some(page));
+    }
+
+    #[test]
+    fn malformed_recording() {
+        assert_eq!(FetchedPage::from_recording("HTTP/1.1 200\nno blank line"), None);
+        assert_eq!(FetchedPage::from_recording("HTTP/1.1 200\nContent-Type: text/html\n\nmissing recorded url"), None);
+    }
+
+    #[test]
+    fn recording_paths() {
+        assert_eq!(recording_path_for(Path::new("fixtures"), "https://www.biopharmcatalyst.com/calendars/fda-calendar"),
+                   Path::new("fixtures/www_biopharmcatalyst_com_calendars_fda_calendar_898a9f3c596b.http"));
+        assert_eq!(recording_path_for(Path::new("fixtures"), "https://www.biopharmcatalyst.com/company/BTX/"),
+                   Path::new("fixtures/www_biopharmcatalyst_com_company_BTX_61b9643ec492.http"));
+        assert_ne!(recording_path_for(Path::new("fixtures"), "https://example.com/a-b"),
+                   recording_path_for(Path::new("fixtures"), "https://example.com/a_b"));
+        let long_url = format!("https://example.com/search?q={}", "a".repeat(1000));
+        let long_path = recording_path_for(Path::new("fixtures"), &long_url);
+        assert_eq!(long_path.file_name().unwrap().len(), 100 + "_".len() + 12 + ".http".len());
+        assert_ne!(long_path, recording_path_for(Path::new("fixtures"), &format!("{}b", long_url)));
+    }
+
+    #[test]
+    fn record_then_replay_from_stub_server() {
+        let url = serve_recording(sample_page(), 1);
+        let fixtures_dir = tempfile::tempdir().unwrap();
+
+        let recorded = RecordingFetcher::new(HttpFetcher::new(), fixtures_dir.path()).fetch(&url).unwrap();
+        assert_eq!(recorded.body, sample_page().body);
+        assert!(recorded.headers.contains(&("content-type".to_string(), "text/html; charset=UTF-8".to_string())));
+
+        //the stub server has shut down, so this can only come from the recording
+        let replayed = ReplayFetcher::new(fixtures_dir.path()).fetch(&url).unwrap();
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn token_bucket_per_host() {
+        let limiter = HostRateLimiter::new(10.0, 2);
+        let host = host_of("https://www.biopharmcatalyst.com/company/BTX");
+        let start = Instant::now();
+
+        assert_eq!(limiter.reserve(&host, start), Duration::from_millis(0));
+        assert_eq!(limiter.reserve(&host, start), Duration::from_millis(0));
+        assert_eq!(limiter.reserve(&host_of("https://example.com/other-host"), start), Duration::from_millis(0));
+
+        //the burst is used up, so these wait for tokens to come back
+        assert_eq!(limiter.reserve(&host, start), Duration::from_millis(100));
+        assert_eq!(limiter.reserve(&host, start), Duration::from_millis(200));
+
+        //a second later the bucket is full again, but never over the burst
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.reserve(&host, later), Duration::from_millis(0));
+        assert_eq!(limiter.reserve(&host, later), Duration::from_millis(0));
+        assert_eq!(limiter.reserve(&host, later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn hosts_include_port() {
+        assert_eq!(host_of("https://www.biopharmcatalyst.com/company/BTX"), "www.biopharmcatalyst.com:443");
+        assert_eq!(host_of("http://127.0.0.1:8080/robots.txt"), "127.0.0.1:8080");
+    }
+
+    #[test]
+    fn replay_missing_recording() {
+        let fixtures_dir = tempfile::tempdir().unwrap();
+        match ReplayFetcher::new(fixtures_dir.path()).fetch("https://www.biopharmcatalyst.com/calendars/fda-calendar") {
+            Err(FetchError::RecordingIoError(_, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use chrono::{DateTime, Utc, NaiveDate, Duration};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::error;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Instant;
+use super::archive::content_hash;
+use super::catalyst_id::CatalystId;
+use super::digest::Digest;
+use super::fda_scraper::{ScrapedCatalysts, ParsedRow};
+use super::report::Report;
+use super::notifier::{Notifier, NotifyError};
+
+#[derive(Debug)]
+pub enum LedgerError {
+    IoError(PathBuf, io::Error),
+    MalformedLine(usize, String),
+    //Another run held the lock for longer than LOCK_WAIT
+    Locked(PathBuf),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LedgerError::IoError(ref path, ref e) => write!(f, "Ledger {:?}: {}", path, e),
+            LedgerError::MalformedLine(line_number, ref line) => write!(f, "Malformed ledger line {}: {:?}", line_number, line),
+            LedgerError::Locked(ref path) => write!(f, "Ledger still locked by {:?}", path),
+        }
+    }
+}
+
+impl error::Error for LedgerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            LedgerError::IoError(_, ref e) => Some(e),
+            LedgerError::MalformedLine(_, _) => None,
+            LedgerError::Locked(_) => None,
+        }
+    }
+}
+
+//One catalyst's part in an email. The kind says which email it was in, and for the regular ones which day or week,
+//so the same catalyst is sent again in tomorrow's report but a reminder about it is only ever sent once
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Alert {
+    pub catalyst: CatalystId,
+    pub kind: String,
+}
+
+impl Alert {
+    pub fn new(row: &ParsedRow, kind: &str) -> Alert {
+        Alert { catalyst: CatalystId::of(row), kind: kind.to_string() }
+    }
+
+    //The same for every send of this alert to the recipient, however many times it's attempted. Keyed on the
+    //catalyst's key, so an alert about the same catalyst with its drug or indication punctuated differently isn't new
+    pub fn idempotency_key(&self, recipient: &str) -> String {
+        content_hash(&format!("{}\n{}\n{}", recipient, self.kind, self.catalyst.key()))
+    }
+}
+
+//The standard report's alerts, which are new again each day
+pub fn report_alerts(catalysts: &ScrapedCatalysts, today: NaiveDate) -> Vec<Alert> {
+    let kind = format!("report {}", today);
+    catalysts.rows().map(|x| Alert::new(x, &kind)).collect()
+}
+
+//How long a run waits for another to be done with the ledger
+const LOCK_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+//A lock older than this was left behind by a run that died, as sending an email doesn't take anywhere near as long
+const STALE_LOCK: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+//Every alert sent, one tab separated line each: idempotency key, sent at, recipient, kind and catalyst.
+//The key and sent at are read back, the rest is for whoever's looking at the file
+pub struct SentLedger {
+    ledger_path: PathBuf,
+    //Lines sent longer ago than this are dropped, the ledger is kept whole when None
+    max_age: Option<Duration>,
+}
+
+//The ledger file's name with .lock on the end, created exclusively so only one run at a time checks, sends and records.
+//Removed when dropped
+struct LedgerLock {
+    lock_path: PathBuf,
+}
+
+impl Drop for LedgerLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.lock_path) {
+            warn!("Removing the ledger lock {:?} failed {}", self.lock_path, e);
+        }
+    }
+}
+
+impl SentLedger {
+    pub fn new(ledger_path: &Path) -> SentLedger {
+        SentLedger { ledger_path: ledger_path.to_owned(), max_age: Option::None }
+    }
+
+    pub fn set_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Option::Some(max_age);
+        self
+    }
+
+    //Each line's key and when it was sent
+    fn read(&self) -> Result<Vec<(String, DateTime<Utc>, String)>, LedgerError> {
+        let ledger = match fs::read_to_string(&self.ledger_path) {
+            Ok(ledger) => ledger,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(LedgerError::IoError(self.ledger_path.clone(), e)),
+        };
+        ledger.lines().enumerate()
+            .filter(|(_, x)| !x.is_empty())
+            .map(|(index, a_line)| match a_line.split('\t').collect::<Vec<&str>>().as_slice() {
+                [key, sent_at, _, _, _] => DateTime::parse_from_rfc3339(sent_at)
+                    .map(|sent_at| (key.to_string(), sent_at.with_timezone(&Utc), a_line.to_owned()))
+                    .map_err(|_| LedgerError::MalformedLine(index + 1, a_line.to_owned())),
+                _ => Err(LedgerError::MalformedLine(index + 1, a_line.to_owned())),
+            })
+            .collect()
+    }
+
+    fn sent_keys(&self) -> Result<HashSet<String>, LedgerError> {
+        Ok(self.read()?.into_iter().map(|(key, _, _)| key).collect())
+    }
+
+    fn lock(&self) -> Result<LedgerLock, LedgerError> {
+        let mut lock_path = self.ledger_path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let waiting_since = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+                //The pid is for whoever finds a lock left behind
+                Ok(mut lock_file) => {
+                    let lock = LedgerLock { lock_path };
+                    writeln!(lock_file, "{}", process::id()).map_err(|x| LedgerError::IoError(lock.lock_path.clone(), x))?;
+                    return Ok(lock);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let lock_age = fs::metadata(&lock_path).and_then(|x| x.modified()).ok().and_then(|x| x.elapsed().ok());
+                    if lock_age.map_or(false, |x| x > STALE_LOCK) {
+                        warn!("Removing the ledger lock {:?} left behind {:?} ago", lock_path, lock_age.unwrap_or_default());
+                        fs::remove_file(&lock_path).map_err(|x| LedgerError::IoError(lock_path.clone(), x))?;
+                    } else if waiting_since.elapsed() > LOCK_WAIT {
+                        return Err(LedgerError::Locked(lock_path));
+                    } else {
+                        thread::sleep(std::time::Duration::from_millis(100));
+                    }
+                },
+                Err(e) => return Err(LedgerError::IoError(lock_path, e)),
+            }
+        }
+    }
+
+    //Drops the lines sent longer than max_age before now
+    pub fn prune(&self, now: DateTime<Utc>) -> Result<usize, LedgerError> {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return Ok(0),
+        };
+        let lines = self.read()?;
+        let kept: Vec<&String> = lines.iter().filter(|(_, sent_at, _)| now.signed_duration_since(*sent_at) <= max_age).map(|(_, _, line)| line).collect();
+        let pruned = lines.len() - kept.len();
+        if pruned == 0 {
+            return Ok(0);
+        }
+        let ledger: String = kept.iter().map(|x| format!("{}\n", x)).collect();
+        fs::write(&self.ledger_path, ledger).map_err(|x| LedgerError::IoError(self.ledger_path.clone(), x))?;
+        info!("Pruned {} ledger lines", pruned);
+        Ok(pruned)
+    }
+
+    //The alerts not yet sent to the recipient
+    pub fn unsent<'a>(&self, recipient: &str, alerts: &'a [Alert]) -> Result<Vec<&'a Alert>, LedgerError> {
+        let sent = self.sent_keys()?;
+        Ok(alerts.iter().filter(|x| !sent.contains(&x.idempotency_key(recipient))).collect())
+    }
+
+    pub fn record(&self, recipient: &str, alerts: &[Alert], sent_at: DateTime<Utc>) -> Result<(), LedgerError> {
+        let lines: String = alerts.iter()
+            .map(|x| format!("{}\t{}\t{}\t{}\t{}\n", x.idempotency_key(recipient), sent_at.to_rfc3339(), recipient, x.kind, x.catalyst))
+            .collect();
+        fs::OpenOptions::new().create(true).append(true).open(&self.ledger_path)
+            .and_then(|mut ledger| ledger.write_all(lines.as_bytes()))
+            .map_err(|x| LedgerError::IoError(self.ledger_path.clone(), x))
+    }
+}
+
+//Sends through another notifier only when an email has an alert the recipient hasn't had yet,
+//recording its alerts once it's sent. An email with no alerts, like a report with no rows, is always sent.
+//It works per email: one with a new alert is sent whole, alerts already sent included, as emails arrive rendered
+pub struct LedgeredNotifier {
+    notifier: Box<dyn Notifier>,
+    ledger: SentLedger,
+    today: NaiveDate,
+}
+
+impl LedgeredNotifier {
+    pub fn new(notifier: Box<dyn Notifier>, ledger: SentLedger, today: NaiveDate) -> LedgeredNotifier {
+        LedgeredNotifier { notifier, ledger, today }
+    }
+
+    //Locked from the check until it's recorded, so a run at the same time can't send it as well
+    fn send_unsent(&self, alerts: &[Alert], send: impl FnOnce() -> Result<(), NotifyError>) -> Result<(), NotifyError> {
+        let recipient = self.notifier.recipient();
+        let _lock = self.ledger.lock()?;
+        if !alerts.is_empty() && self.ledger.unsent(&recipient, alerts)?.is_empty() {
+            info!("Every alert has already been sent to {}, skipping", recipient);
+            return Ok(());
+        }
+        send()?;
+        let sent_at = Utc::now();
+        self.ledger.record(&recipient, alerts, sent_at)?;
+        self.ledger.prune(sent_at)?;
+        Ok(())
+    }
+}
+
+impl Notifier for LedgeredNotifier {
+    fn recipient(&self) -> String {
+        self.notifier.recipient()
+    }
+
+    fn notify(&self, report: &Report) -> Result<(), NotifyError> {
+        self.send_unsent(&report_alerts(report.catalysts(), self.today), || self.notifier.notify(report))
+    }
+
+    fn send_digest(&self, digest: &Digest) -> Result<(), NotifyError> {
+        self.send_unsent(&digest.alerts, || self.notifier.send_digest(digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use crate::digest;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    struct CountingNotifier {
+        sent: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Notifier for CountingNotifier {
+        fn recipient(&self) -> String {
+            "someone@example.com".to_string()
+        }
+
+        fn notify(&self, report: &Report) -> Result<(), NotifyError> {
+            self.sent.borrow_mut().push(report.summary_line());
+            Ok(())
+        }
+
+        fn send_digest(&self, digest: &Digest) -> Result<(), NotifyError> {
+            self.sent.borrow_mut().push(digest.subject.clone());
+            Ok(())
+        }
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    fn catalysts() -> ScrapedCatalysts {
+        fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap()
+    }
+
+    #[test]
+    fn reruns_are_skipped() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger_path = ledger_dir.path().join("sent.tsv");
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let notifier_on = |today: &str| LedgeredNotifier::new(Box::new(CountingNotifier { sent: Rc::clone(&sent) }), SentLedger::new(&ledger_path), date(today));
+
+        notifier_on("2019-04-30").notify(&Report::new(&catalysts())).unwrap();
+        notifier_on("2019-04-30").notify(&Report::new(&catalysts())).unwrap();
+        assert_eq!(sent.borrow().len(), 1);
+        //Tomorrow's report is a new email
+        notifier_on("2019-05-01").notify(&Report::new(&catalysts())).unwrap();
+        assert_eq!(sent.borrow().len(), 2);
+
+        //A reminder is only sent once, whichever day the job runs
+        let reminders = digest::tomorrow_reminders(&catalysts(), date("2019-05-01")).unwrap();
+        notifier_on("2019-05-01").send_digest(&reminders[0]).unwrap();
+        notifier_on("2019-05-02").send_digest(&reminders[0]).unwrap();
+        assert_eq!(*sent.borrow(), vec!["3 catalysts, next BTX 2019-05-02", "3 catalysts, next BTX 2019-05-02", "Tomorrow: BTX OpRegen (Phase 1/2)"]);
+    }
+
+    #[test]
+    fn keys_are_per_recipient() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger = SentLedger::new(&ledger_dir.path().join("sent.tsv"));
+        let alerts = report_alerts(&catalysts(), date("2019-05-01"));
+        assert_eq!((alerts[0].catalyst.to_string(), alerts[0].kind.as_str()), ("BTX/opregen/dry age-related macular degeneration (amd)".to_string(), "report 2019-05-01"));
+
+        ledger.record("a@example.com", &alerts[..1], Utc::now()).unwrap();
+        assert_eq!(ledger.unsent("a@example.com", &alerts).unwrap().len(), 2);
+        assert_eq!(ledger.unsent("b@example.com", &alerts).unwrap().len(), 3);
+    }
+
+    //Slow enough that two runs at once would both find the alerts unsent without the lock
+    struct SlowNotifier {
+        sent: Arc<Mutex<usize>>,
+    }
+
+    impl Notifier for SlowNotifier {
+        fn recipient(&self) -> String {
+            "someone@example.com".to_string()
+        }
+
+        fn notify(&self, _report: &Report) -> Result<(), NotifyError> {
+            thread::sleep(std::time::Duration::from_millis(200));
+            *self.sent.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn send_digest(&self, _digest: &Digest) -> Result<(), NotifyError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn concurrent_runs_send_once() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let sent = Arc::new(Mutex::new(0));
+        let runs: Vec<thread::JoinHandle<()>> = (0..2).map(|_| {
+            let ledger_path = ledger_dir.path().join("sent.tsv");
+            let sent = Arc::clone(&sent);
+            thread::spawn(move || {
+                let notifier = LedgeredNotifier::new(Box::new(SlowNotifier { sent }), SentLedger::new(&ledger_path), date("2019-05-01"));
+                notifier.notify(&Report::new(&catalysts())).unwrap();
+            })
+        }).collect();
+        for a_run in runs {
+            a_run.join().unwrap();
+        }
+        assert_eq!(*sent.lock().unwrap(), 1);
+        assert!(!ledger_dir.path().join("sent.tsv.lock").exists());
+    }
+
+    #[test]
+    fn pruned_by_sent_at() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger = SentLedger::new(&ledger_dir.path().join("sent.tsv")).set_max_age(Duration::days(90));
+        let alerts = report_alerts(&catalysts(), date("2019-05-01"));
+        let now = DateTime::parse_from_rfc3339("2019-05-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        ledger.record("a@example.com", &alerts[..1], now - Duration::days(91)).unwrap();
+        ledger.record("a@example.com", &alerts[1..], now - Duration::days(89)).unwrap();
+
+        assert_eq!(ledger.prune(now).unwrap(), 1);
+        assert_eq!(ledger.unsent("a@example.com", &alerts).unwrap(), vec![&alerts[0]]);
+        assert_eq!(ledger.prune(now).unwrap(), 0);
+        assert_eq!(SentLedger::new(&ledger_dir.path().join("sent.tsv")).prune(now + Duration::days(365)).unwrap(), 0);
+    }
+
+    #[test]
+    fn same_catalyst_differently_punctuated() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger = SentLedger::new(&ledger_dir.path().join("sent.tsv"));
+        let alerts = report_alerts(&catalysts(), date("2019-05-01"));
+        ledger.record("a@example.com", &alerts, Utc::now()).unwrap();
+
+        let html = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap()
+            .replace("OpRegen", "Op-Regen");
+        let reworded = report_alerts(&fda_scraper::parse_html(&html, &ScrapePredicates::new()).unwrap(), date("2019-05-01"));
+        assert_ne!(reworded[0].catalyst, alerts[0].catalyst);
+        assert!(ledger.unsent("a@example.com", &reworded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_ledger() {
+        let ledger_dir = tempfile::tempdir().unwrap();
+        let ledger_path = ledger_dir.path().join("sent.tsv");
+        fs::write(&ledger_path, "abc\t2019-05-01T00:00:00+00:00\ta@example.com\treport 2019-05-01\tBTX\nnot a line\n").unwrap();
+        match SentLedger::new(&ledger_path).unsent("a@example.com", &[]) {
+            Err(LedgerError::MalformedLine(2, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+}
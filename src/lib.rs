@@ -1,2 +1,29 @@
 pub mod currency; //Declares that we have a module called currency in file currency.rs in src/
 pub mod fda_scraper;
+pub mod report;
+pub mod catalyst_note;
+pub mod note_events;
+pub mod catalyst_date;
+pub mod catalyst_id;
+pub mod fetcher;
+pub mod notifier;
+pub mod archive;
+pub mod company;
+pub mod polite_fetcher;
+pub mod session_fetcher;
+pub mod watchlist;
+pub mod scoring;
+pub mod taxonomy;
+pub mod theme;
+pub mod user_template;
+pub mod price_history;
+pub mod sparkline;
+pub mod digest;
+pub mod ledger;
+pub mod error_report;
+pub mod metrics;
+pub mod logging;
+mod row_stream;
+mod text;
+#[cfg(test)]
+mod test_support;
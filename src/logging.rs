@@ -0,0 +1,137 @@
+use env_logger::fmt::Formatter;
+use lazy_static::lazy_static;
+use log::{log, Level, LevelFilter, Record};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use chrono::{SecondsFormat, Utc};
+use super::archive::content_hash;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    //One line of text per record, for reading in a terminal
+    Text,
+    //One JSON object per line, for log pipelines
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+lazy_static! {
+    //Shared by every thread, as company pages are fetched on worker threads
+    static ref RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+}
+
+thread_local! {
+    //The event being logged on this thread, read back by the formatter while the record is written
+    static EVENT: RefCell<Option<(String, Map<String, Value>)>> = const { RefCell::new(None) };
+}
+
+//Gives every record logged from now on a new id, so one run's fetch, parse and notify can be picked out of the rest
+pub fn start_run() -> String {
+    let run_id = content_hash(&format!("{:?} {}", SystemTime::now(), process::id()))[..12].to_string();
+    *RUN_ID.lock().unwrap_or_else(|x| x.into_inner()) = Some(run_id.clone());
+    run_id
+}
+
+pub fn current_run() -> Option<String> {
+    RUN_ID.lock().unwrap_or_else(|x| x.into_inner()).clone()
+}
+
+//Something that happened along with its details, like a parse and how many rows it found. In text it's the name
+//followed by key=value pairs, in JSON the name and details are fields of their own. Logged under the given target,
+//module_path!() of the caller, so it can be filtered like any other record from there
+pub fn event(target: &str, level: Level, name: &str, fields: &[(&str, Value)]) {
+    let text = fields.iter().fold(name.to_string(), |text, (key, value)| format!("{} {}={}", text, key, value));
+    EVENT.with(|x| *x.borrow_mut() = Some((name.to_string(), fields.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())));
+    log!(target: target, level, "{}", text);
+    EVENT.with(|x| *x.borrow_mut() = None);
+}
+
+fn format_text(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let run = current_run().map(|x| format!(" run={}", x)).unwrap_or_default();
+    writeln!(buf, "[{} {:<5} {}{}] {}", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true), record.level(), record.target(), run, record.args())
+}
+
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    writeln!(buf, "{}", json_line(record, current_run()))
+}
+
+fn json_line(record: &Record, run_id: Option<String>) -> Value {
+    let mut line = json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "run_id": run_id,
+        "message": record.args().to_string(),
+    });
+    if let Some((name, fields)) = EVENT.with(|x| x.borrow().clone()) {
+        line["event"] = Value::String(name);
+        line["fields"] = Value::Object(fields);
+    }
+    line
+}
+
+//Info and above, RUST_LOG can change that
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Info); //defaults to error otherwise
+//    builder.filter_module("lettre", LevelFilter::Debug);
+//    builder.filter_module("lettre_email", LevelFilter::Debug);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    match format {
+        LogFormat::Text => builder.format(format_text),
+        LogFormat::Json => builder.format(format_json),
+    };
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robots_line(run_id: Option<String>) -> Value {
+        json_line(&Record::builder().level(Level::Warn).target("fda_calendar_scraper::fetcher").args(format_args!("Read {}", "robots.txt")).build(), run_id)
+    }
+
+    #[test]
+    fn json_lines() {
+        let line = robots_line(Some("abc123".to_string()));
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "fda_calendar_scraper::fetcher");
+        assert_eq!(line["run_id"], "abc123");
+        assert_eq!(line["message"], "Read robots.txt");
+        assert!(line.get("event").is_none());
+        assert_eq!(robots_line(None)["run_id"], Value::Null);
+
+        EVENT.with(|x| *x.borrow_mut() = Some(("parsed".to_string(), vec![("rows".to_string(), json!(3))].into_iter().collect())));
+        let line = robots_line(None);
+        EVENT.with(|x| *x.borrow_mut() = None);
+        assert_eq!(line["event"], "parsed");
+        assert_eq!(line["fields"], json!({ "rows": 3 }));
+    }
+
+    #[test]
+    fn run_ids() {
+        let first = start_run();
+        assert_eq!(first.len(), 12);
+        assert_eq!(current_run(), Some(first.clone()));
+        assert_ne!(start_run(), first);
+        assert_eq!(LogFormat::from_name("json"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::from_name("xml"), None);
+    }
+}
@@ -0,0 +1,258 @@
+use lazy_static::lazy_static;
+use log::error;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+}
+
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub metric_type: MetricType,
+}
+
+//How long each stage of the last run took: download, archive, parse, email and overall
+pub const STAGE_DURATION: Metric = Metric { name: "fda_scraper_stage_duration_seconds", help: "How long the stage took in the last run", metric_type: MetricType::Gauge };
+pub const ROWS_PARSED: Metric = Metric { name: "fda_scraper_rows_parsed", help: "Rows on the calendar page in the last parse", metric_type: MetricType::Gauge };
+pub const ROWS_FILTERED: Metric = Metric { name: "fda_scraper_rows_filtered", help: "Rows dropped by the predicates in the last parse", metric_type: MetricType::Gauge };
+pub const ERRORS: Metric = Metric { name: "fda_scraper_errors_total", help: "Failures by kind", metric_type: MetricType::Counter };
+pub const LAST_SUCCESS: Metric = Metric { name: "fda_scraper_last_success_timestamp_seconds", help: "When the calendar was last scraped without an error, in seconds since the epoch", metric_type: MetricType::Gauge };
+
+struct Family {
+    help: &'static str,
+    metric_type: MetricType,
+    //By the rendered labels, like {stage="parse"}
+    samples: BTreeMap<String, f64>,
+}
+
+//Metrics kept in memory and rendered in Prometheus' text format
+pub struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+}
+
+//The one the scraper records into as it runs
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+//Label values are escaped as the text format needs
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let rendered: Vec<String> = labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    format!("{{{}}}", rendered.join(","))
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { families: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn update(&self, metric: &Metric, labels: &[(&str, &str)], update: impl FnOnce(&mut f64)) {
+        let mut families = self.families.lock().unwrap_or_else(|x| x.into_inner());
+        let family = families.entry(metric.name).or_insert_with(|| Family { help: metric.help, metric_type: metric.metric_type, samples: BTreeMap::new() });
+        update(family.samples.entry(render_labels(labels)).or_insert(0.0));
+    }
+
+    pub fn set(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |x| *x = value);
+    }
+
+    pub fn increment(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        self.update(metric, labels, |x| *x += 1.0);
+    }
+
+    pub fn observe_stage(&self, stage: &str, duration: Duration) {
+        self.set(&STAGE_DURATION, &[("stage", stage)], duration.as_secs_f64());
+    }
+
+    pub fn record_error(&self, kind: &str) {
+        self.increment(&ERRORS, &[("kind", kind)]);
+    }
+
+    //The last run's stage durations and row counts are cleared, so a stage this run doesn't reach isn't left showing
+    //how the run before it went
+    pub fn start_run(&self) {
+        let mut families = self.families.lock().unwrap_or_else(|x| x.into_inner());
+        for metric in &[STAGE_DURATION, ROWS_PARSED, ROWS_FILTERED] {
+            families.remove(metric.name);
+        }
+    }
+
+    pub fn record_success(&self, at: SystemTime) {
+        if let Ok(since_epoch) = at.duration_since(UNIX_EPOCH) {
+            self.set(&LAST_SUCCESS, &[], since_epoch.as_secs() as f64);
+        }
+    }
+
+    pub fn get(&self, metric: &Metric, labels: &[(&str, &str)]) -> Option<f64> {
+        let families = self.families.lock().unwrap_or_else(|x| x.into_inner());
+        families.get(metric.name).and_then(|x| x.samples.get(&render_labels(labels))).cloned()
+    }
+
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap_or_else(|x| x.into_inner());
+        let mut rendered = String::new();
+        for (name, family) in families.iter() {
+            let metric_type = match family.metric_type {
+                MetricType::Counter => "counter",
+                MetricType::Gauge => "gauge",
+            };
+            rendered.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, family.help, name, metric_type));
+            for (labels, value) in &family.samples {
+                rendered.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        }
+        rendered
+    }
+
+    //For node_exporter's textfile collector. Written alongside then renamed over the file, so it's never read half written.
+    //A one-shot run that fails keeps the last success from the run before, otherwise it would go missing rather than go stale
+    pub fn write_textfile(&self, textfile_path: &Path) -> io::Result<()> {
+        if self.get(&LAST_SUCCESS, &[]).is_none() {
+            let previous_success = fs::read_to_string(textfile_path).ok().and_then(|previous| {
+                previous.lines()
+                    .find(|x| x.starts_with(&format!("{} ", LAST_SUCCESS.name)))
+                    .and_then(|x| x[LAST_SUCCESS.name.len()..].trim().parse().ok())
+            });
+            if let Some(previous_success) = previous_success {
+                self.set(&LAST_SUCCESS, &[], previous_success);
+            }
+        }
+        let partial_path = textfile_path.with_extension("prom.partial");
+        fs::write(&partial_path, self.render())?;
+        fs::rename(&partial_path, textfile_path)
+    }
+}
+
+//Reads up to the blank line ending the headers, so the connection isn't reset for closing with the request unread.
+//Scrapes don't have a body
+fn read_request_head(stream: TcpStream, request_line: &mut String) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    reader.read_line(request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    Ok(())
+}
+
+//Answers GET /metrics with the registry, anything else is a 404. Runs until the listener fails
+pub fn serve(listener: TcpListener, registry: &'static Registry) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Metrics connection failed {}", e);
+                continue;
+            }
+        };
+        let mut request_line = String::new();
+        if let Err(e) = stream.try_clone().and_then(|x| read_request_head(x, &mut request_line)) {
+            error!("Reading metrics request failed {}", e);
+            continue;
+        }
+        let (status, body) = match request_line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["GET", "/metrics", _] => ("200 OK", registry.render()),
+            _ => ("404 Not Found", String::new()),
+        };
+        let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+        if let Err(e) = stream.write_all(response.as_bytes()) {
+            error!("Answering metrics request failed {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+
+    #[test]
+    fn rendered_text_format() {
+        let registry = Registry::new();
+        registry.observe_stage("parse", Duration::from_millis(1500));
+        registry.observe_stage("download", Duration::from_millis(250));
+        registry.record_error("fetch");
+        registry.record_error("fetch");
+        registry.record_error("field \"not\" found");
+        registry.set(&ROWS_PARSED, &[], 3.0);
+        assert_eq!(registry.render(), "# HELP fda_scraper_errors_total Failures by kind\n\
+                                       # TYPE fda_scraper_errors_total counter\n\
+                                       fda_scraper_errors_total{kind=\"fetch\"} 2\n\
+                                       fda_scraper_errors_total{kind=\"field \\\"not\\\" found\"} 1\n\
+                                       # HELP fda_scraper_rows_parsed Rows on the calendar page in the last parse\n\
+                                       # TYPE fda_scraper_rows_parsed gauge\n\
+                                       fda_scraper_rows_parsed 3\n\
+                                       # HELP fda_scraper_stage_duration_seconds How long the stage took in the last run\n\
+                                       # TYPE fda_scraper_stage_duration_seconds gauge\n\
+                                       fda_scraper_stage_duration_seconds{stage=\"download\"} 0.25\n\
+                                       fda_scraper_stage_duration_seconds{stage=\"parse\"} 1.5\n");
+    }
+
+    #[test]
+    fn run_gauges_cleared() {
+        let registry = Registry::new();
+        registry.observe_stage("parse", Duration::from_millis(1500));
+        registry.set(&ROWS_PARSED, &[], 3.0);
+        registry.record_error("fetch");
+        registry.record_success(UNIX_EPOCH + Duration::from_secs(1_556_694_000));
+
+        registry.start_run();
+        assert_eq!(registry.get(&STAGE_DURATION, &[("stage", "parse")]), None);
+        assert_eq!(registry.get(&ROWS_PARSED, &[]), None);
+        assert_eq!(registry.get(&ERRORS, &[("kind", "fetch")]), Some(1.0));
+        assert_eq!(registry.get(&LAST_SUCCESS, &[]), Some(1_556_694_000.0));
+    }
+
+    #[test]
+    fn textfile_keeps_last_success() {
+        let textfile_dir = tempfile::tempdir().unwrap();
+        let textfile_path = textfile_dir.path().join("fda_scraper.prom");
+        let succeeded = Registry::new();
+        succeeded.record_success(UNIX_EPOCH + Duration::from_secs(1_556_694_000));
+        succeeded.write_textfile(&textfile_path).unwrap();
+
+        let failed = Registry::new();
+        failed.record_error("fetch");
+        failed.write_textfile(&textfile_path).unwrap();
+        let written = fs::read_to_string(&textfile_path).unwrap();
+        assert!(written.contains("\nfda_scraper_last_success_timestamp_seconds 1556694000\n"), "{}", written);
+        assert!(written.contains("\nfda_scraper_errors_total{kind=\"fetch\"} 1\n"), "{}", written);
+        assert_eq!(fs::read_dir(textfile_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn served_over_http() {
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        registry.set(&ROWS_FILTERED, &[], 2.0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, registry));
+
+        let mut response = reqwest::get(&format!("http://{}/metrics", address)).unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        assert!(body.ends_with("\nfda_scraper_rows_filtered 2\n"), "{}", body);
+        assert_eq!(reqwest::get(&format!("http://{}/", address)).unwrap().status().as_u16(), 404);
+    }
+}
@@ -0,0 +1,273 @@
+use chrono::{NaiveDate, NaiveTime, DateTime, FixedOffset, TimeZone, Datelike};
+use regex::{Regex, Captures};
+use lazy_static::lazy_static;
+use std::fmt;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NoteEventKind {
+    Pdufa,
+    EmbargoLift,
+    Presentation,
+    DataRelease,
+    Announcement,
+}
+
+//US zones only, the calendar is written for US markets
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum UsTimeZone {
+    Eastern,
+    Central,
+    Mountain,
+    Pacific,
+}
+
+//A dated mention inside a catalyst note, with the time of day when the note gives one
+#[derive(Debug, Eq, PartialEq)]
+pub struct NoteEvent {
+    pub kind: NoteEventKind,
+    pub date: NaiveDate,
+    pub time: Option<(DateTime<FixedOffset>, UsTimeZone)>,
+}
+
+impl fmt::Display for NoteEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match *self {
+            NoteEventKind::Pdufa => "PDUFA",
+            NoteEventKind::EmbargoLift => "Embargo lifts",
+            NoteEventKind::Presentation => "Presentation",
+            NoteEventKind::DataRelease => "Data release",
+            NoteEventKind::Announcement => "Announcement",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+impl UsTimeZone {
+    fn from_label(label: &str) -> Option<UsTimeZone> {
+        match &label.to_uppercase()[..1] {
+            "E" => Some(UsTimeZone::Eastern),
+            "C" => Some(UsTimeZone::Central),
+            "M" => Some(UsTimeZone::Mountain),
+            "P" => Some(UsTimeZone::Pacific),
+            _ => None,
+        }
+    }
+
+    fn standard_offset_hours(self) -> i32 {
+        match self {
+            UsTimeZone::Eastern => 5,
+            UsTimeZone::Central => 6,
+            UsTimeZone::Mountain => 7,
+            UsTimeZone::Pacific => 8,
+        }
+    }
+
+    //Notes say "EST" in May as often as "EDT", so the offset always comes from the date rather than the label
+    pub fn offset_on(self, date: NaiveDate) -> FixedOffset {
+        let daylight_hours = if observes_daylight_saving(date) { 1 } else { 0 };
+        FixedOffset::west((self.standard_offset_hours() - daylight_hours) * 3600)
+    }
+
+    pub fn abbreviation_on(self, date: NaiveDate) -> &'static str {
+        let daylight = observes_daylight_saving(date);
+        match self {
+            UsTimeZone::Eastern => if daylight { "EDT" } else { "EST" },
+            UsTimeZone::Central => if daylight { "CDT" } else { "CST" },
+            UsTimeZone::Mountain => if daylight { "MDT" } else { "MST" },
+            UsTimeZone::Pacific => if daylight { "PDT" } else { "PST" },
+        }
+    }
+}
+
+impl fmt::Display for NoteEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.time {
+            Some((time, zone)) => write!(f, "{} {} {}", self.kind, time.format("%b %d %Y %-I:%M%P"), zone.abbreviation_on(self.date)),
+            None => write!(f, "{} {}", self.kind, self.date.format("%b %d %Y")),
+        }
+    }
+}
+
+//Since 2007 US daylight saving runs from the second Sunday in March to the first Sunday in November
+fn observes_daylight_saving(date: NaiveDate) -> bool {
+    let starts = nth_sunday_of(date.year(), 3, 2);
+    let ends = nth_sunday_of(date.year(), 11, 1);
+    date >= starts && date < ends
+}
+
+fn nth_sunday_of(year: i32, month: u32, n: u32) -> NaiveDate {
+    let first = NaiveDate::from_ymd(year, month, 1);
+    let days_until_sunday = (7 - first.weekday().num_days_from_sunday()) % 7;
+    first + chrono::Duration::days(i64::from(days_until_sunday + (n - 1) * 7))
+}
+
+lazy_static! {
+    //Matches "May 2, 2019", "Jan. 31 2019", the first day of ranges like "May 3-7, 2019" and an optional trailing
+    //time such as "May 2, 2019,10:15am ET" or "June 3, 2019 at 4:30 p.m. PT"
+    static ref DATE_AND_TIME: Regex = Regex::new(
+        r"(?i)\b((?-u:jan(?:uary)?|feb(?:ruary)?|mar(?:ch)?|apr(?:il)?|may|june?|july?|aug(?:ust)?|sep(?:t(?:ember)?)?|oct(?:ober)?|nov(?:ember)?|dec(?:ember)?))\.? (\d{1,2})(?:-\d{1,2})?,? (\d{4})\b(?:[,\s]*(?:at\s+)?(\d{1,2})(?::(\d{2}))?\s*([ap])\.?m(?:\.|\b)(?:\s*\(?([ECMP])[SD]?T\b\)?)?)?"
+    ).unwrap();
+
+    static ref SENTENCE_BOUNDARY: Regex = Regex::new(r"[.;]\s+[A-Z(]").unwrap();
+
+    static ref KIND_RULES: Vec<(NoteEventKind, Regex)> = vec![
+        (NoteEventKind::EmbargoLift, Regex::new(r"(?i)\bembargo").unwrap()),
+        (NoteEventKind::Pdufa, Regex::new(r"(?i)\bpdufa\b").unwrap()),
+        (NoteEventKind::Presentation, Regex::new(r"(?i)\bpresent|\blate-breaker\b|\bposter\b|\bconference\b|\bmeeting\b|(?-i:\bat [A-Z]{2,}\b)").unwrap()),
+        (NoteEventKind::DataRelease, Regex::new(r"(?i)\bdata\b|\bresults\b|\btop-?line\b|\bread-?out\b").unwrap()),
+    ];
+}
+
+pub fn extract_note_events(note: &str) -> Vec<NoteEvent> {
+    DATE_AND_TIME.captures_iter(note)
+        .filter_map(|captures| {
+            let date = date_from(&captures)?;
+            let whole_match = captures.get(0)?;
+            Some(NoteEvent {
+                kind: kind_from(sentence_around(note, whole_match.start(), whole_match.end())),
+                date,
+                time: time_from(&captures, date),
+            })
+        })
+        .collect()
+}
+
+fn date_from(captures: &Captures) -> Option<NaiveDate> {
+    let month = month_number(&captures[1])?;
+    let day = captures[2].parse().ok()?;
+    let year = captures[3].parse().ok()?;
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+//A time without a zone is taken as Eastern, which is what the calendar uses throughout
+fn time_from(captures: &Captures, date: NaiveDate) -> Option<(DateTime<FixedOffset>, UsTimeZone)> {
+    let hour: u32 = captures.get(4)?.as_str().parse().ok()?;
+    let minute: u32 = captures.get(5).map_or(Some(0), |x| x.as_str().parse().ok())?;
+    let is_pm = captures.get(6)?.as_str().eq_ignore_ascii_case("p");
+    if hour == 0 || hour > 12 {
+        return None;
+    }
+    let hour = match (hour, is_pm) {
+        (12, false) => 0,
+        (12, true) => 12,
+        (x, false) => x,
+        (x, true) => x + 12,
+    };
+
+    let zone = captures.get(7).and_then(|x| UsTimeZone::from_label(x.as_str())).unwrap_or(UsTimeZone::Eastern);
+    let local = date.and_time(NaiveTime::from_hms_opt(hour, minute, 0)?);
+    let time = zone.offset_on(date).from_local_datetime(&local).single()?;
+    Some((time, zone))
+}
+
+fn sentence_around(note: &str, start: usize, end: usize) -> &str {
+    let sentence_start = SENTENCE_BOUNDARY.find_iter(&note[..start]).last().map_or(0, |x| x.end() - 1);
+    let sentence_end = SENTENCE_BOUNDARY.find(&note[end..]).map_or(note.len(), |x| end + x.start());
+    &note[sentence_start..sentence_end]
+}
+
+//Earlier rules take priority, an embargo on a conference abstract is still an embargo
+fn kind_from(sentence: &str) -> NoteEventKind {
+    KIND_RULES.iter()
+        .find(|(_, rule)| rule.is_match(sentence))
+        .map_or(NoteEventKind::Announcement, |(kind, _)| *kind)
+}
+
+pub(crate) fn month_number(month_name: &str) -> Option<u32> {
+    let prefix: String = month_name.to_lowercase().chars().take(3).collect();
+    let month = match prefix.as_str() {
+        "jan" => 1,
+        "feb" => 2,
+        "mar" => 3,
+        "apr" => 4,
+        "may" => 5,
+        "jun" => 6,
+        "jul" => 7,
+        "aug" => 8,
+        "sep" => 9,
+        "oct" => 10,
+        "nov" => 11,
+        "dec" => 12,
+        _ => return None,
+    };
+    Some(month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(ymd: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(ymd, "%Y-%m-%d").unwrap()
+    }
+
+    fn time(rfc3339: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap()
+    }
+
+    #[test]
+    fn presentation_time_in_eastern_daylight() {
+        let actual = extract_note_events("Phase 1/2 enrolment to be completed 2019. Updated data due  May 2, 2019,10:15am ET at ARVO.");
+        assert_eq!(actual, vec![NoteEvent {
+            kind: NoteEventKind::Presentation,
+            date: date("2019-05-02"),
+            time: Some((time("2019-05-02T10:15:00-04:00"), UsTimeZone::Eastern)),
+        }]);
+        assert_eq!(actual[0].to_string(), "Presentation May 02 2019 10:15am EDT");
+    }
+
+    #[test]
+    fn presentation_and_embargo_lift() {
+        let actual = extract_note_events("Phase 3 data to be presented at AAN in late-breaker May 7, 2019. Abstract embargoed until May 3, 2019.");
+        assert_eq!(actual, vec![
+            NoteEvent { kind: NoteEventKind::Presentation, date: date("2019-05-07"), time: None },
+            NoteEvent { kind: NoteEventKind::EmbargoLift, date: date("2019-05-03"), time: None },
+        ]);
+        assert_eq!(actual[1].to_string(), "Embargo lifts May 03 2019");
+    }
+
+    #[test]
+    fn announcements_and_ranges() {
+        let actual = extract_note_events("Phase 3 trial met primary endpoint - January 31, 2019. Detailed data due at (ASCRS) meeting May 3-7, 2019.");
+        assert_eq!(actual, vec![
+            NoteEvent { kind: NoteEventKind::Announcement, date: date("2019-01-31"), time: None },
+            NoteEvent { kind: NoteEventKind::Presentation, date: date("2019-05-03"), time: None },
+        ]);
+    }
+
+    #[test]
+    fn pacific_standard_time() {
+        let actual = extract_note_events("PDUFA date December 3, 2019 at 4:30 p.m. PT.");
+        assert_eq!(actual, vec![NoteEvent {
+            kind: NoteEventKind::Pdufa,
+            date: date("2019-12-03"),
+            time: Some((time("2019-12-03T16:30:00-08:00"), UsTimeZone::Pacific)),
+        }]);
+        assert_eq!(actual[0].to_string(), "PDUFA Dec 03 2019 4:30pm PST");
+    }
+
+    #[test]
+    fn mislabelled_zone_uses_the_dates_offset() {
+        let actual = extract_note_events("Topline data March 20, 2019 8am EST.");
+        assert_eq!(actual[0].time, Some((time("2019-03-20T08:00:00-04:00"), UsTimeZone::Eastern)));
+    }
+
+    #[test]
+    fn numbers_after_the_date_arent_times() {
+        let actual = extract_note_events("NDA accepted May 2, 2019 3 amendments filed since.");
+        assert_eq!(actual, vec![NoteEvent { kind: NoteEventKind::Announcement, date: date("2019-05-02"), time: None }]);
+    }
+
+    #[test]
+    fn months_in_other_letters() {
+        assert_eq!(extract_note_events("Data Auguſt 2, 2019."), vec![]);
+    }
+
+    #[test]
+    fn daylight_saving_boundaries() {
+        assert!(!observes_daylight_saving(date("2019-03-09")));
+        assert!(observes_daylight_saving(date("2019-03-10")));
+        assert!(observes_daylight_saving(date("2019-11-02")));
+        assert!(!observes_daylight_saving(date("2019-11-03")));
+    }
+}
@@ -0,0 +1,428 @@
+use lettre::{SmtpClient, Transport, ClientSecurity, ClientTlsParameters, SendableEmail, smtp};
+use lettre::smtp::authentication::{Mechanism, Credentials};
+use lettre_email::{Email, EmailBuilder, PartBuilder, MimeMessage, MimeMultipartType, Header};
+use native_tls::TlsConnector;
+use askama::Template;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::{Regex, Captures};
+use log::{info, Level};
+use std::env;
+use std::fmt;
+use std::error;
+use std::time::SystemTime;
+use super::report::Report;
+use super::digest::Digest;
+use super::ledger::LedgerError;
+use super::metrics;
+use super::logging;
+use serde_json::json;
+use super::user_template::{UserTemplate, UserTemplateError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::fs;
+use std::io;
+use std::io::Write;
+
+#[derive(Debug)]
+pub enum NotifyError {
+    RenderError(askama::Error),
+    UserTemplateFailure(UserTemplateError),
+    LedgerFailure(LedgerError),
+    EmailBuildError(lettre_email::error::Error),
+    TlsError(native_tls::Error),
+    SmtpError(smtp::error::Error),
+    PreviewIoError(io::Error),
+}
+
+impl From<askama::Error> for NotifyError {
+    fn from(e: askama::Error) -> Self {
+        NotifyError::RenderError(e)
+    }
+}
+
+impl From<UserTemplateError> for NotifyError {
+    fn from(e: UserTemplateError) -> Self {
+        NotifyError::UserTemplateFailure(e)
+    }
+}
+
+impl From<LedgerError> for NotifyError {
+    fn from(e: LedgerError) -> Self {
+        NotifyError::LedgerFailure(e)
+    }
+}
+
+impl From<lettre_email::error::Error> for NotifyError {
+    fn from(e: lettre_email::error::Error) -> Self {
+        NotifyError::EmailBuildError(e)
+    }
+}
+
+impl From<native_tls::Error> for NotifyError {
+    fn from(e: native_tls::Error) -> Self {
+        NotifyError::TlsError(e)
+    }
+}
+
+impl From<smtp::error::Error> for NotifyError {
+    fn from(e: smtp::error::Error) -> Self {
+        NotifyError::SmtpError(e)
+    }
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotifyError::RenderError(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::UserTemplateFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::LedgerFailure(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::EmailBuildError(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::TlsError(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::SmtpError(ref e) => std::fmt::Display::fmt(&e, f),
+            NotifyError::PreviewIoError(ref e) => write!(f, "Writing the preview failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for NotifyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            NotifyError::RenderError(ref e) => Some(e),
+            NotifyError::UserTemplateFailure(ref e) => Some(e),
+            NotifyError::LedgerFailure(ref e) => Some(e),
+            NotifyError::EmailBuildError(ref e) => Some(e),
+            NotifyError::TlsError(ref e) => Some(e),
+            NotifyError::SmtpError(ref e) => Some(e),
+            NotifyError::PreviewIoError(ref e) => Some(e),
+        }
+    }
+}
+
+pub trait Notifier {
+    //Who's being notified, alerts already sent to them are kept track of by it
+    fn recipient(&self) -> String;
+    fn notify(&self, report: &Report) -> Result<(), NotifyError>;
+    fn send_digest(&self, digest: &Digest) -> Result<(), NotifyError>;
+}
+
+pub struct EmailNotifier {
+    to_address: String,
+    smtp_server: String,
+    smtp_username: String,
+    smtp_password: String,
+    //Replaces the compiled in html report when set, loaded once and shared by every notifier built from it
+    html_template: Option<Arc<UserTemplate>>,
+    //Writes emails out rather than sending them when set
+    preview: Option<Preview>,
+}
+
+//Where a dry run's emails go instead of to the SMTP server
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Preview {
+    //The whole MIME message, headers included
+    Stdout,
+    //Each email's MIME message as <subject>.eml and its html as <subject>.html, to open in a browser. The html has its
+    //sparklines as data: urls, as a browser can't follow cid: ones. A subject already written is numbered, <subject>-2
+    Directory(PathBuf),
+}
+
+impl Preview {
+    //"-" for stdout, otherwise a directory
+    pub fn from_target(target: &str) -> Preview {
+        match target {
+            "-" => Preview::Stdout,
+            _ => Preview::Directory(PathBuf::from(target)),
+        }
+    }
+
+    fn write(&self, subject: &str, email: Email, html: &str) -> Result<(), NotifyError> {
+        let sendable: SendableEmail = email.into();
+        let message = sendable.message_to_string().map_err(NotifyError::PreviewIoError)?;
+        match self {
+            Preview::Stdout => io::stdout().write_all(message.as_bytes()).map_err(NotifyError::PreviewIoError),
+            Preview::Directory(preview_dir) => {
+                fs::create_dir_all(preview_dir).map_err(NotifyError::PreviewIoError)?;
+                let (file_stem, mut eml_file) = new_preview_file(preview_dir, &file_stem(subject)).map_err(NotifyError::PreviewIoError)?;
+                let html_path = preview_dir.join(format!("{}.html", file_stem));
+                eml_file.write_all(message.as_bytes())
+                    .and_then(|_| fs::write(&html_path, html))
+                    .map_err(NotifyError::PreviewIoError)?;
+                info!("Preview of {:?} written to file://{}", subject, html_path.canonicalize().unwrap_or_else(|_| html_path.clone()).display());
+                Ok(())
+            }
+        }
+    }
+}
+
+//Creating the .eml claims the name, so two emails with the same subject, even from runs at the same time, both get written
+fn new_preview_file(preview_dir: &Path, file_stem: &str) -> Result<(String, fs::File), io::Error> {
+    let mut number = 1;
+    loop {
+        let numbered = if number == 1 { file_stem.to_string() } else { format!("{}-{}", file_stem, number) };
+        match fs::OpenOptions::new().write(true).create_new(true).open(preview_dir.join(format!("{}.eml", numbered))) {
+            Ok(eml_file) => return Ok((numbered, eml_file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+//The subject lowercased with anything but letters and digits as single dashes, e.g. catalyst-update-may-01-2019
+fn file_stem(subject: &str) -> String {
+    subject.to_lowercase()
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+impl EmailNotifier {
+    pub fn from_env() -> EmailNotifier {
+        EmailNotifier {
+            to_address: env::var("TO_ADDRESS").unwrap(),
+            smtp_server: "smtp.gmail.com".to_string(),
+            //Can't move these out otherwise someone else looking at env::var wouldn't see them
+            smtp_username: env::var("GMAIL_USERNAME").unwrap(),
+            smtp_password: env::var("GMAIL_PASSWORD").unwrap(),
+            html_template: None,
+            preview: None,
+        }
+    }
+
+    //Built and rendered the same as from_env but never sent, so no Gmail credentials are needed
+    pub fn preview_from_env(preview: Preview) -> EmailNotifier {
+        EmailNotifier {
+            to_address: env::var("TO_ADDRESS").unwrap_or_else(|_| "preview@example.com".to_string()),
+            smtp_server: "smtp.gmail.com".to_string(),
+            smtp_username: env::var("GMAIL_USERNAME").unwrap_or_else(|_| "preview@example.com".to_string()),
+            smtp_password: String::new(),
+            html_template: None,
+            preview: Option::Some(preview),
+        }
+    }
+
+    pub fn set_to_address(mut self, to_address: &str) -> Self {
+        self.to_address = to_address.to_owned();
+        self
+    }
+
+    pub fn set_html_template(mut self, html_template: Arc<UserTemplate>) -> Self {
+        self.html_template = Option::Some(html_template);
+        self
+    }
+}
+
+impl EmailNotifier {
+    fn render_html(&self, report: &Report) -> Result<String, NotifyError> {
+        match &self.html_template {
+            Some(html_template) => Ok(html_template.render(report)?),
+            None => Ok(report.render()?)
+        }
+    }
+}
+
+fn text_part(content_type: &str, body: &str) -> MimeMessage {
+    PartBuilder::new()
+        .body(body)
+        .header(("Content-Type", format!("{}; charset=utf-8", content_type)))
+        .build()
+}
+
+//The html and the images it refers to. The email crate has no multipart/related, so PartBuilder would call it mixed,
+//and the Content-Type is written here instead
+fn related_part(html: MimeMessage, images: Vec<MimeMessage>) -> MimeMessage {
+    let mut related = MimeMessage::new_blank_message();
+    let content_type = format!("multipart/related; type=\"text/html\"; boundary=\"{}\"", related.boundary);
+    related.headers.insert(Header::new("Content-Type".to_string(), content_type));
+    related.children.push(html);
+    related.children.extend(images);
+    related
+}
+
+//An image the html refers to as cid:<cid>. Gmail drops data: urls, but shows inline parts
+fn inline_image(cid: &str, png: &[u8]) -> MimeMessage {
+    PartBuilder::new()
+        .body(base64::encode(png))
+        .header(("Content-Type", "image/png"))
+        .header(("Content-Transfer-Encoding", "base64"))
+        .header(("Content-Disposition", format!("inline; filename=\"{}.png\"", cid)))
+        .header(("Content-ID", format!("<{}>", cid)))
+        .build()
+}
+
+lazy_static! {
+    //A cid: url however it's quoted, up to the end of the content id, which never ends in a dot
+    static ref CID_URL: Regex = Regex::new(r"cid:([\w.-]*[\w-])").unwrap();
+}
+
+//The html with each cid: the sparklines are referred to by replaced with the PNG itself
+fn with_data_urls(html: &str, sparklines: &[(String, &[u8])]) -> String {
+    CID_URL.replace_all(html, |captures: &Captures| match sparklines.iter().find(|(cid, _)| cid == &captures[1]) {
+        Some((_, png)) => format!("data:image/png;base64,{}", base64::encode(png)),
+        None => captures[0].to_string(),
+    }).into_owned()
+}
+
+impl EmailNotifier {
+    fn send(&self, subject: &str, email: Email, html: &str) -> Result<(), NotifyError> {
+        if let Some(preview) = &self.preview {
+            return preview.write(subject, email, html);
+        }
+        let email_start_time = SystemTime::now();
+
+        let security = ClientSecurity::Wrapper(ClientTlsParameters::new(self.smtp_server.clone(), TlsConnector::new()?));
+        let mut mailer = SmtpClient::new((self.smtp_server.as_str(), smtp::SUBMISSIONS_PORT), security)?
+            .authentication_mechanism(Mechanism::Login).credentials(Credentials::new(self.smtp_username.clone(), self.smtp_password.clone())).transport();
+
+        let sent = mailer.send(email.into());
+        if let Ok(email_send_duration) = email_start_time.elapsed() {
+            metrics::registry().observe_stage("email", email_send_duration);
+            if sent.is_ok() {
+                logging::event(module_path!(), Level::Info, "email_sent", &[("to", json!(self.to_address)), ("millis", json!(email_send_duration.as_millis() as u64))]);
+            }
+        }
+        sent?;
+        Ok(())
+    }
+
+    //The run's id goes in a header, so an email can be matched up with the logs of the run that sent it
+    fn email_builder(&self) -> EmailBuilder {
+        let builder = Email::builder()
+            .to(self.to_address.as_str())
+            .from(self.smtp_username.as_str());
+        match logging::current_run() {
+            Some(run_id) => builder.header(("X-Run-Id", run_id)),
+            None => builder,
+        }
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn recipient(&self) -> String {
+        self.to_address.clone()
+    }
+
+    fn notify(&self, report: &Report) -> Result<(), NotifyError> {
+        let subject_date = Utc::now().format("%b %d %Y").to_string();
+        let subject = format!("Catalyst Update {}: {}", subject_date, report.summary_line());
+        let html = self.render_html(report)?;
+
+        let images = report.sparklines().into_iter().map(|(cid, png)| inline_image(&cid, png)).collect();
+        //mixed > alternative > (text, related > (html, sparklines)). Clients that can't or won't show html get the plain
+        //text report instead, and the sparklines stay with the html rather than showing up as attachments
+        let alternative = PartBuilder::new()
+            .message_type(MimeMultipartType::Alternative)
+            .child(text_part("text/plain", &report.render_text()?))
+            .child(related_part(text_part("text/html", &html), images))
+            .build();
+        let email = self.email_builder()
+            .subject(subject.as_str())
+            .message_type(MimeMultipartType::Mixed)
+            .child(alternative)
+            .build()?;
+        self.send(&subject, email, &with_data_urls(&html, &report.sparklines()))
+    }
+
+    //A digest is already rendered, so is sent as is
+    fn send_digest(&self, digest: &Digest) -> Result<(), NotifyError> {
+        let email = self.email_builder()
+            .subject(digest.subject.as_str())
+            .alternative(digest.html.as_str(), digest.text.as_str())
+            .build()?;
+        self.send(&digest.subject, email, &digest.html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use std::path::Path;
+
+    fn previewing(preview: Preview) -> EmailNotifier {
+        EmailNotifier {
+            to_address: "someone@example.com".to_string(),
+            smtp_server: "smtp.invalid".to_string(),
+            smtp_username: "scraper@example.com".to_string(),
+            smtp_password: String::new(),
+            html_template: None,
+            preview: Option::Some(preview),
+        }
+    }
+
+    #[test]
+    fn preview_written_instead_of_sent() {
+        let preview_dir = tempfile::tempdir().unwrap();
+        let catalysts = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        previewing(Preview::Directory(preview_dir.path().join("previews"))).notify(&Report::new(&catalysts)).unwrap();
+
+        let stem = file_stem(&format!("Catalyst Update {}: 3 catalysts, next BTX 2019-05-02", Utc::now().format("%b %d %Y")));
+        assert!(stem.starts_with("catalyst-update-") && stem.ends_with("-3-catalysts-next-btx-2019-05-02"), "{}", stem);
+        let html = fs::read_to_string(preview_dir.path().join("previews").join(format!("{}.html", stem))).unwrap();
+        assert_eq!(html, Report::new(&catalysts).render().unwrap());
+        let message = fs::read_to_string(preview_dir.path().join("previews").join(format!("{}.eml", stem))).unwrap();
+        assert!(message.contains("To: <someone@example.com>"), "{}", message);
+        assert!(message.contains("Subject: Catalyst Update"), "{}", message);
+        let mixed_at = message.find("Content-Type: multipart/mixed").unwrap();
+        let alternative_at = message.find("Content-Type: multipart/alternative").unwrap();
+        let text_at = message.find("Content-Type: text/plain").unwrap();
+        let related_at = message.find("Content-Type: multipart/related").unwrap();
+        let html_at = message.find("Content-Type: text/html").unwrap();
+        assert!(mixed_at < alternative_at && alternative_at < text_at && text_at < related_at && related_at < html_at, "{}", message);
+    }
+
+    #[test]
+    fn preview_sparklines_inlined() {
+        let preview_dir = tempfile::tempdir().unwrap();
+        let calendar = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+        let catalysts = fda_scraper::parse_html(&calendar, &ScrapePredicates::new()).unwrap();
+        let mut history = crate::price_history::PriceHistory::new();
+        history.record(chrono::NaiveDate::from_ymd(2019, 4, 30), &fda_scraper::parse_html(&calendar.replace("$1.26", "$1.40"), &ScrapePredicates::new()).unwrap());
+        history.record(chrono::NaiveDate::from_ymd(2019, 5, 1), &catalysts);
+        previewing(Preview::Directory(preview_dir.path().to_owned())).notify(&Report::new(&catalysts).set_price_history(&history)).unwrap();
+
+        let preview_path = |extension: &str| fs::read_dir(preview_dir.path()).unwrap().map(|x| x.unwrap().path()).find(|x| x.extension().map_or(false, |x| x == extension)).unwrap();
+        let html = fs::read_to_string(preview_path("html")).unwrap();
+        assert!(html.contains("src=\"data:image/png;base64,iVBOR"), "{}", html);
+        assert!(!html.contains("cid:"));
+        let message = fs::read_to_string(preview_path("eml")).unwrap();
+        assert!(message.contains("src=\"cid:sparkline-BTX\""));
+        assert!(message.contains("Content-ID: <sparkline-BTX>"));
+    }
+
+    #[test]
+    fn cid_urls_however_quoted() {
+        let sparklines = vec![("sparkline-BTX".to_string(), &b"png"[..])];
+        assert_eq!(with_data_urls("<img src=cid:sparkline-BTX alt=x><img src='cid:sparkline-BTX'>", &sparklines),
+                   "<img src=data:image/png;base64,cG5n alt=x><img src='data:image/png;base64,cG5n'>");
+        assert_eq!(with_data_urls("<img src=\"cid:sparkline-BTXA\">", &sparklines), "<img src=\"cid:sparkline-BTXA\">");
+    }
+
+    #[test]
+    fn user_template_preview() {
+        let preview_dir = tempfile::tempdir().unwrap();
+        let catalysts = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let html_template = Arc::new(UserTemplate::parse("<p>{{summary}}</p>").unwrap());
+        previewing(Preview::Directory(preview_dir.path().to_owned())).set_html_template(html_template).notify(&Report::new(&catalysts)).unwrap();
+
+        let html_path = fs::read_dir(preview_dir.path()).unwrap().map(|x| x.unwrap().path()).find(|x| x.extension().map_or(false, |x| x == "html")).unwrap();
+        assert_eq!(fs::read_to_string(html_path).unwrap(), "<p>3 catalysts, next BTX 2019-05-02</p>");
+    }
+
+    #[test]
+    fn digest_preview() {
+        let preview_dir = tempfile::tempdir().unwrap();
+        let digest = Digest { subject: "Tomorrow: BTX OpRegen (Phase 1/2)".to_string(), html: "<p>BTX</p>".to_string(), text: "BTX".to_string(), alerts: Vec::new() };
+        previewing(Preview::Directory(preview_dir.path().to_owned())).send_digest(&digest).unwrap();
+        assert_eq!(fs::read_to_string(preview_dir.path().join("tomorrow-btx-opregen-phase-1-2.html")).unwrap(), "<p>BTX</p>");
+        //The same subject again is numbered rather than written over
+        let digest = Digest { html: "<p>BTX again</p>".to_string(), ..digest };
+        previewing(Preview::Directory(preview_dir.path().to_owned())).send_digest(&digest).unwrap();
+        assert_eq!(fs::read_to_string(preview_dir.path().join("tomorrow-btx-opregen-phase-1-2.html")).unwrap(), "<p>BTX</p>");
+        assert_eq!(fs::read_to_string(preview_dir.path().join("tomorrow-btx-opregen-phase-1-2-2.html")).unwrap(), "<p>BTX again</p>");
+        assert!(preview_dir.path().join("tomorrow-btx-opregen-phase-1-2-2.eml").exists());
+        assert_eq!(Preview::from_target("-"), Preview::Stdout);
+        assert_eq!(Preview::from_target("previews"), Preview::Directory(PathBuf::from("previews")));
+    }
+}
@@ -0,0 +1,483 @@
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, FROM, ACCEPT_ENCODING, IF_NONE_MATCH, IF_MODIFIED_SINCE};
+use reqwest::{RedirectPolicy, StatusCode};
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use log::info;
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::fetcher::{Fetcher, FetchedPage, FetchError, HostRateLimiter, recording_path_for, host_of};
+
+const MAX_REDIRECTS: usize = 5;
+
+//How the scraper behaves towards the sites it fetches from
+pub struct CrawlPolicy {
+    user_agent: String,
+    contact: Option<String>,
+    requests_per_second: f64,
+    burst: u32,
+    cache_dir: Option<PathBuf>,
+    cache_read_only: bool,
+}
+
+impl CrawlPolicy {
+    pub fn new(user_agent: &str) -> CrawlPolicy {
+        CrawlPolicy { user_agent: user_agent.to_owned(), contact: Option::None, requests_per_second: 1.0, burst: 1, cache_dir: Option::None, cache_read_only: false }
+    }
+
+    //Sent as the From header, so a site operator knows who to get in touch with
+    pub fn set_contact(mut self, contact: &str) -> Self {
+        self.contact = Option::Some(contact.to_owned());
+        self
+    }
+
+    pub fn set_rate_limit(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.requests_per_second = requests_per_second;
+        self.burst = burst;
+        self
+    }
+
+    //Pages are kept here with their validators so they can be fetched again conditionally
+    pub fn set_cache_dir(mut self, cache_dir: &Path) -> Self {
+        self.cache_dir = Option::Some(cache_dir.to_owned());
+        self
+    }
+
+    //Cached pages are still revalidated, but new ones aren't kept, for a dry run
+    pub fn set_cache_read_only(mut self) -> Self {
+        self.cache_read_only = true;
+        self
+    }
+
+    //The headers every request made under this policy carries
+    pub(crate) fn default_headers(&self) -> Result<HeaderMap, FetchError> {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(USER_AGENT, header_value(USER_AGENT.as_str(), &self.user_agent)?);
+        if let Some(contact) = &self.contact {
+            default_headers.insert(FROM, header_value(FROM.as_str(), contact)?);
+        }
+        default_headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        Ok(default_headers)
+    }
+
+    pub(crate) fn rate_limiter(&self) -> HostRateLimiter {
+        HostRateLimiter::new(self.requests_per_second, self.burst)
+    }
+}
+
+fn header_value(name: &str, value: &str) -> Result<HeaderValue, FetchError> {
+    HeaderValue::from_str(value).map_err(|e| FetchError::InvalidHeaderValue(name.to_owned(), e))
+}
+
+//Identifies itself, obeys robots.txt, rate limits per host, makes conditional requests and accepts compressed bodies
+pub struct PoliteFetcher {
+    client: reqwest::Client,
+    //robots.txt groups are matched against the product name, the part before any version
+    product_token: String,
+    rate_limiter: HostRateLimiter,
+    robots_by_host: Mutex<HashMap<String, Arc<RobotsRules>>>,
+    cache_dir: Option<PathBuf>,
+    cache_read_only: bool,
+}
+
+impl PoliteFetcher {
+    pub fn new(policy: CrawlPolicy) -> Result<PoliteFetcher, FetchError> {
+        //reqwest only knows gzip, so decoding is done here for both
+        let client = reqwest::Client::builder()
+            .gzip(false)
+            .redirect(RedirectPolicy::none())
+            .default_headers(policy.default_headers()?)
+            .build()?;
+
+        Ok(PoliteFetcher {
+            client,
+            product_token: policy.user_agent.split('/').next().unwrap_or("").trim().to_lowercase(),
+            rate_limiter: policy.rate_limiter(),
+            robots_by_host: Mutex::new(HashMap::new()),
+            cache_dir: policy.cache_dir,
+            cache_read_only: policy.cache_read_only,
+        })
+    }
+
+    fn robots_for(&self, url: &reqwest::Url) -> Arc<RobotsRules> {
+        let host = host_of(url.as_str());
+        if let Some(rules) = self.robots_by_host.lock().unwrap().get(&host) {
+            return Arc::clone(rules);
+        }
+
+        let robots_url = url.join("/robots.txt").map(|x| x.to_string()).unwrap_or_default();
+        self.rate_limiter.acquire(&robots_url);
+        //A missing robots.txt allows everything, one that can't be reached disallows everything
+        let rules = match self.client.get(&robots_url).send() {
+            Ok(ref mut response) if response.status().is_success() => RobotsRules::parse(&response.text().unwrap_or_default(), &self.product_token),
+            Ok(ref response) if response.status().is_client_error() => RobotsRules::allow_all(),
+            _ => RobotsRules::disallow_all(),
+        };
+        info!("Read {}", robots_url);
+
+        let rules = Arc::new(rules);
+        self.robots_by_host.lock().unwrap().insert(host, Arc::clone(&rules));
+        rules
+    }
+
+    fn load_cached(&self, url: &str) -> Option<FetchedPage> {
+        let cache_path = recording_path_for(self.cache_dir.as_ref()?, url);
+        fs::read_to_string(cache_path).ok().and_then(|x| FetchedPage::from_recording(&x))
+    }
+
+    //Cookies a page sets are left out, a session's are kept in its own jar
+    fn store_cached(&self, page: &FetchedPage) -> Result<(), FetchError> {
+        if self.cache_read_only {
+            return Ok(());
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            let cache_path = recording_path_for(cache_dir, &page.url);
+            let headers = page.headers.iter().filter(|x| !x.0.eq_ignore_ascii_case("set-cookie")).cloned().collect();
+            let cached = FetchedPage { headers, ..page.clone() };
+            fs::create_dir_all(cache_dir)
+                .and_then(|_| fs::write(&cache_path, cached.to_recording()))
+                .map_err(|x| FetchError::CacheIoError(cache_path.clone(), x))?;
+        }
+        Ok(())
+    }
+}
+
+impl PoliteFetcher {
+    //One request and its response. Redirects aren't followed, so whoever's asking sees every response's headers,
+    //like the Set-Cookie on a login's redirect. GETs are made conditionally when the page is cached, and kept there
+    pub(crate) fn send(&self, url: &str, extra_headers: HeaderMap, form: Option<&[(&str, &str)]>) -> Result<FetchedPage, FetchError> {
+        let parsed_url = reqwest::Url::parse(url).map_err(|_| FetchError::InvalidUrl(url.to_owned()))?;
+        let path = match parsed_url.query() {
+            Some(query) => format!("{}?{}", parsed_url.path(), query),
+            None => parsed_url.path().to_owned(),
+        };
+        if !self.robots_for(&parsed_url).allows(&path) {
+            return Err(FetchError::DisallowedByRobots(url.to_owned()));
+        }
+
+        let cached = if form.is_none() { self.load_cached(url) } else { None };
+        let mut request = match form {
+            Some(form) => self.client.post(url).form(form),
+            None => self.client.get(url),
+        };
+        request = request.headers(extra_headers);
+        if let Some(cached) = &cached {
+            if let Some(etag) = cached.header("etag") {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = cached.header("last-modified") {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        self.rate_limiter.acquire(url);
+        let mut response = request.send()?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                info!("{} not modified, using the cached copy", url);
+                return Ok(FetchedPage { fetched_at: Utc::now(), ..cached });
+            }
+        }
+
+        let mut compressed = Vec::new();
+        response.copy_to(&mut compressed)?;
+        let content_encoding = response.headers().get("content-encoding")
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("identity")
+            .to_lowercase();
+        let body = decode_body(&compressed, &content_encoding).map_err(|x| FetchError::DecodeError(url.to_owned(), x))?;
+
+        //The body is kept decoded, so the headers describing the encoded one no longer apply
+        let headers = response.headers().iter()
+            .filter(|(name, _)| name.as_str() != "content-encoding" && name.as_str() != "content-length")
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str().to_owned(), value.to_owned())))
+            .collect();
+
+        let page = FetchedPage { url: url.to_owned(), status: response.status().as_u16(), headers, fetched_at: Utc::now(), body };
+        if form.is_none() && response.status().is_success() {
+            self.store_cached(&page)?;
+        }
+        Ok(page)
+    }
+}
+
+//Where a redirect points, None for any other response
+pub(crate) fn redirect_location(page: &FetchedPage) -> Result<Option<reqwest::Url>, FetchError> {
+    if page.status < 300 || page.status >= 400 || page.status == 304 {
+        return Ok(None);
+    }
+    page.header("location")
+        .and_then(|location| reqwest::Url::parse(&page.url).ok()?.join(location).ok())
+        .map(Some)
+        .ok_or_else(|| FetchError::InvalidUrl(page.url.clone()))
+}
+
+//Each redirect is its own request, so robots.txt and the rate limit apply to every hop.
+//The page is given the url asked for, wherever it ended up
+impl Fetcher for PoliteFetcher {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        let mut current_url = url.to_owned();
+        for _ in 0..MAX_REDIRECTS {
+            let page = self.send(&current_url, HeaderMap::new(), None)?;
+            match redirect_location(&page)? {
+                Some(location) => current_url = location.into_string(),
+                None if page.status >= 400 => return Err(FetchError::ErrorStatus(current_url, page.status)),
+                None => return Ok(FetchedPage { url: url.to_owned(), ..page }),
+            }
+        }
+        Err(FetchError::TooManyRedirects(url.to_owned()))
+    }
+}
+
+pub(crate) fn decode_body(compressed: &[u8], content_encoding: &str) -> io::Result<String> {
+    let mut decoded = Vec::new();
+    match content_encoding {
+        "gzip" | "x-gzip" => { GzDecoder::new(compressed).read_to_end(&mut decoded)?; },
+        "br" => { brotli_decompressor::Decompressor::new(compressed, 4096).read_to_end(&mut decoded)?; },
+        _ => decoded.extend_from_slice(compressed),
+    }
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+//(allowed, path pattern)
+type RobotsRule = (bool, String);
+//(user agents, rules)
+type RobotsGroup = (Vec<String>, Vec<RobotsRule>);
+
+//The Allow and Disallow lines of the robots.txt group that applies to us
+#[derive(Debug, Eq, PartialEq)]
+struct RobotsRules {
+    rules: Vec<RobotsRule>,
+}
+
+impl RobotsRules {
+    fn allow_all() -> RobotsRules {
+        RobotsRules { rules: Vec::new() }
+    }
+
+    fn disallow_all() -> RobotsRules {
+        RobotsRules { rules: vec![(false, "/".to_string())] }
+    }
+
+    //Groups naming our product token win over the * group, and groups naming the same agents are merged
+    fn parse(robots_txt: &str, product_token: &str) -> RobotsRules {
+        let mut groups: Vec<RobotsGroup> = Vec::new();
+        let mut previous_was_agent = false;
+
+        for a_line in robots_txt.lines() {
+            let a_line = a_line.split('#').next().unwrap_or("").trim();
+            let mut key_and_value = a_line.splitn(2, ':');
+            let key = key_and_value.next().unwrap_or("").trim().to_lowercase();
+            let value = match key_and_value.next() {
+                Some(value) => value.trim(),
+                None => continue
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !previous_was_agent {
+                        groups.push((Vec::new(), Vec::new()));
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.0.push(value.to_lowercase());
+                    }
+                    previous_was_agent = true;
+                },
+                "allow" | "disallow" => {
+                    previous_was_agent = false;
+                    //An empty Disallow means nothing is disallowed
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.1.push((key == "allow", value.to_owned()));
+                    }
+                },
+                _ => previous_was_agent = false,
+            }
+        }
+
+        let rules_for = |agent: &str| -> Option<Vec<RobotsRule>> {
+            let matching: Vec<&RobotsGroup> = groups.iter().filter(|x| x.0.iter().any(|x| x == agent)).collect();
+            if matching.is_empty() {
+                None
+            } else {
+                Some(matching.iter().flat_map(|x| x.1.iter().cloned()).collect())
+            }
+        };
+
+        RobotsRules { rules: rules_for(product_token).or_else(|| rules_for("*")).unwrap_or_default() }
+    }
+
+    //The longest matching pattern wins, Allow wins a tie
+    fn allows(&self, path: &str) -> bool {
+        self.rules.iter()
+            .filter(|(_, pattern)| pattern_matches(pattern, path))
+            .max_by_key(|(allowed, pattern)| (pattern.len(), *allowed))
+            .map_or(true, |(allowed, _)| *allowed)
+    }
+}
+
+//Patterns are path prefixes where * matches anything and a trailing $ anchors the end
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false)
+    };
+    let mut pieces = pattern.split('*');
+    let first_piece = pieces.next().unwrap_or("");
+    if !path.starts_with(first_piece) {
+        return false;
+    }
+
+    let mut position = first_piece.len();
+    let pieces: Vec<&str> = pieces.collect();
+    for (index, a_piece) in pieces.iter().enumerate() {
+        if anchored && index == pieces.len() - 1 {
+            return path[position..].ends_with(a_piece);
+        }
+        match path[position..].find(a_piece) {
+            Some(found) => position += found + a_piece.len(),
+            None => return false
+        }
+    }
+    !anchored || position == path.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::tests::{serve, response};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn policy() -> CrawlPolicy {
+        CrawlPolicy::new("fda_calendar_scraper/0.1").set_contact("alerts@example.com").set_rate_limit(100.0, 10)
+    }
+
+    fn request_line(request: &str) -> &str {
+        request.lines().next().unwrap_or("")
+    }
+
+    #[test]
+    fn robots_groups() {
+        let robots_txt = "User-agent: *\nDisallow: /\n\n# ours\nUser-agent: FDA_Calendar_Scraper\nUser-agent: other\nDisallow: /calendars/ # not these\nAllow: /calendars/fda-calendar\nDisallow:\n";
+        let rules = RobotsRules::parse(robots_txt, "fda_calendar_scraper");
+        assert!(rules.allows("/company/BTX"));
+        assert!(rules.allows("/calendars/fda-calendar"));
+        assert!(!rules.allows("/calendars/historical"));
+
+        let rules = RobotsRules::parse(robots_txt, "someone_else");
+        assert!(!rules.allows("/company/BTX"));
+        assert_eq!(RobotsRules::parse("", "fda_calendar_scraper"), RobotsRules::allow_all());
+    }
+
+    #[test]
+    fn robots_patterns() {
+        assert!(pattern_matches("/company/", "/company/BTX"));
+        assert!(pattern_matches("/*.pdf$", "/reports/q1.pdf"));
+        assert!(!pattern_matches("/*.pdf$", "/reports/q1.pdf?download=1"));
+        assert!(pattern_matches("/company/*/news", "/company/BTX/news/latest"));
+        assert!(!pattern_matches("/company/*/news", "/company/BTX"));
+        assert!(pattern_matches("/calendars$", "/calendars"));
+        assert!(!pattern_matches("/calendars$", "/calendars/fda-calendar"));
+    }
+
+    #[test]
+    fn identifies_itself_and_obeys_robots() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        let address = serve(3, move |request| {
+            seen.lock().unwrap().push(request.to_owned());
+            match request_line(request) {
+                "GET /robots.txt HTTP/1.1" => response(200, &[], b"User-agent: *\nDisallow: /\n\nUser-agent: fda_calendar_scraper\nDisallow: /calendars/historical\n"),
+                _ => response(200, &[], b"<html>page</html>"),
+            }
+        });
+        let fetcher = PoliteFetcher::new(policy()).unwrap();
+
+        assert_eq!(fetcher.fetch(&format!("http://{}/calendars/fda-calendar", address)).unwrap().body, "<html>page</html>");
+        assert_eq!(fetcher.fetch(&format!("http://{}/company/BTX", address)).unwrap().body, "<html>page</html>");
+        match fetcher.fetch(&format!("http://{}/calendars/historical", address)) {
+            Err(FetchError::DisallowedByRobots(_)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+
+        let requests = requests.lock().unwrap();
+        //robots.txt is read once per host, the disallowed page is never requested
+        assert_eq!(requests.iter().map(|x| request_line(x)).collect::<Vec<&str>>(),
+                   vec!["GET /robots.txt HTTP/1.1", "GET /calendars/fda-calendar HTTP/1.1", "GET /company/BTX HTTP/1.1"]);
+        for a_request in requests.iter() {
+            let a_request = a_request.to_lowercase();
+            assert!(a_request.contains("user-agent: fda_calendar_scraper/0.1\r\n"));
+            assert!(a_request.contains("from: alerts@example.com\r\n"));
+            assert!(a_request.contains("accept-encoding: gzip, br\r\n"));
+        }
+    }
+
+    #[test]
+    fn unreachable_robots_disallows_everything() {
+        let address = serve(1, |_| response(503, &[], b""));
+        match PoliteFetcher::new(policy()).unwrap().fetch(&format!("http://{}/calendars/fda-calendar", address)) {
+            Err(FetchError::DisallowedByRobots(_)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn invalid_header_values() {
+        match PoliteFetcher::new(policy().set_contact("alerts@example.com\n")) {
+            Err(FetchError::InvalidHeaderValue(name, _)) => assert_eq!(name, "from"),
+            Err(x) => panic!("Unexpected error {:?}", x),
+            Ok(_) => panic!("Built with an invalid contact"),
+        }
+    }
+
+    #[test]
+    fn conditional_requests_use_the_cache() {
+        let address = serve(3, |request| {
+            let request = request.to_lowercase();
+            if request.starts_with("get /robots.txt") {
+                response(404, &[], b"")
+            } else if request.contains("if-none-match: \"v1\"\r\n") && request.contains("if-modified-since: wed, 01 may 2019 12:00:00 gmt\r\n") {
+                response(304, &[], b"")
+            } else {
+                response(200, &[("ETag", "\"v1\""), ("Last-Modified", "Wed, 01 May 2019 12:00:00 GMT")], b"<html>calendar</html>")
+            }
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let fetcher = PoliteFetcher::new(policy().set_cache_dir(cache_dir.path())).unwrap();
+        let url = format!("http://{}/calendars/fda-calendar", address);
+
+        let fetched = fetcher.fetch(&url).unwrap();
+        let revalidated = fetcher.fetch(&url).unwrap();
+        assert_eq!(revalidated.body, "<html>calendar</html>");
+        assert_eq!(revalidated.headers, fetched.headers);
+    }
+
+    #[test]
+    fn decodes_compressed_bodies() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"<html>gzip</html>").unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        brotli.write_all(b"<html>brotli</html>").unwrap();
+        let brotli = brotli.into_inner();
+
+        let address = serve(3, move |request| match request_line(request) {
+            "GET /gzip HTTP/1.1" => response(200, &[("Content-Encoding", "gzip")], &gzip),
+            "GET /br HTTP/1.1" => response(200, &[("Content-Encoding", "br")], &brotli),
+            _ => response(404, &[], b""),
+        });
+        let fetcher = PoliteFetcher::new(policy()).unwrap();
+
+        let gzip_page = fetcher.fetch(&format!("http://{}/gzip", address)).unwrap();
+        assert_eq!(gzip_page.body, "<html>gzip</html>");
+        assert_eq!(gzip_page.header("content-encoding"), None);
+        assert_eq!(fetcher.fetch(&format!("http://{}/br", address)).unwrap().body, "<html>brotli</html>");
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{NaiveDate, Duration};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use super::currency;
+use super::archive::{PageArchive, ArchiveError};
+use super::fda_scraper::{self, ScrapedCatalysts, ScrapePredicates};
+
+//How far back a row's price history goes
+pub const HISTORY_DAYS: i64 = 30;
+
+//Each download's prices by its hash, kept in the archive directory so a download is only parsed once
+const PRICE_CACHE_FILE_NAME: &str = "prices.tsv";
+
+//A download's symbols and their prices
+type Prices = Vec<(String, currency::USD)>;
+
+//Each symbol's calendar price by day, built from archived downloads
+#[derive(Debug, Default)]
+pub struct PriceHistory {
+    prices: BTreeMap<String, BTreeMap<NaiveDate, currency::USD>>,
+}
+
+impl PriceHistory {
+    pub fn new() -> PriceHistory {
+        PriceHistory::default()
+    }
+
+    //Every download in the last HISTORY_DAYS. Only downloads missing from the price cache are parsed, and one that no
+    //longer parses is skipped rather than failing the report. The cache is rewritten with just the downloads in range,
+    //unless the archive is read only
+    pub fn from_archive(archive: &PageArchive, today: NaiveDate) -> Result<PriceHistory, ArchiveError> {
+        let cache_path = archive.archive_dir().join(PRICE_CACHE_FILE_NAME);
+        let cached = read_price_cache(&cache_path);
+        let mut in_range = BTreeMap::new();
+        let mut history = PriceHistory::new();
+        for entry in archive.entries_since(today - Duration::days(HISTORY_DAYS))? {
+            let prices = match cached.get(&entry.hash).or_else(|| in_range.get(&entry.hash)) {
+                Some(prices) => prices.clone(),
+                None => match fda_scraper::parse_html(&archive.load(&entry)?, &ScrapePredicates::new()) {
+                    Ok(catalysts) => catalysts.rows().map(|x| (x.symbol.clone(), x.price.clone())).collect(),
+                    Err(err) => {
+                        warn!("Leaving {} from {} out of the price history: {}", entry.hash, entry.fetched_at, err);
+                        continue;
+                    }
+                }
+            };
+            history.record_prices(entry.fetched_at.naive_utc().date(), &prices);
+            in_range.insert(entry.hash, prices);
+        }
+        if in_range != cached && !archive.is_read_only() {
+            if let Err(err) = write_price_cache(&cache_path, &in_range) {
+                warn!("Caching prices in {:?} failed, they'll be parsed again next run: {}", cache_path, err);
+            }
+        }
+        Ok(history)
+    }
+
+    pub fn record(&mut self, day: NaiveDate, catalysts: &ScrapedCatalysts) {
+        let prices: Prices = catalysts.rows().map(|x| (x.symbol.clone(), x.price.clone())).collect();
+        self.record_prices(day, &prices);
+    }
+
+    //The last download of a day wins
+    fn record_prices(&mut self, day: NaiveDate, prices: &[(String, currency::USD)]) {
+        for (symbol, price) in prices {
+            self.prices.entry(symbol.clone()).or_default().insert(day, price.clone());
+        }
+    }
+
+    //Oldest first, one price per day seen
+    pub fn prices_for(&self, symbol: &str) -> Vec<&currency::USD> {
+        self.prices.get(symbol).map(|x| x.values().collect()).unwrap_or_default()
+    }
+}
+
+//A line per download, its hash then each symbol and price, all tab separated. A missing or unreadable cache just means
+//parsing the downloads again, as does a line that doesn't read back
+fn read_price_cache(cache_path: &Path) -> BTreeMap<String, Prices> {
+    let cache = fs::read_to_string(cache_path).unwrap_or_default();
+    cache.lines().filter_map(|line| {
+        let mut fields = line.split('\t');
+        let hash = fields.next().filter(|x| !x.is_empty())?.to_string();
+        let fields: Vec<&str> = fields.collect();
+        let prices = fields.chunks(2)
+            .map(|x| match x {
+                [symbol, price] => currency::USD::new(price).ok().map(|price| (symbol.to_string(), price)),
+                _ => None,
+            })
+            .collect::<Option<Prices>>()?;
+        Some((hash, prices))
+    }).collect()
+}
+
+fn write_price_cache(cache_path: &Path, prices_by_hash: &BTreeMap<String, Prices>) -> std::io::Result<()> {
+    let cache: String = prices_by_hash.iter()
+        .map(|(hash, prices)| prices.iter().fold(hash.clone(), |line, (symbol, price)| format!("{}\t{}\t{}", line, symbol, price)) + "\n")
+        .collect();
+    fs::write(cache_path, cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::ScrapePredicates;
+    use crate::fetcher::FetchedPage;
+    use crate::archive::RetentionPolicy;
+    use chrono::{DateTime, Utc};
+    use std::fs;
+
+    fn page(fetched_at: &str, body: String) -> FetchedPage {
+        FetchedPage {
+            url: "https://www.biopharmcatalyst.com/calendars/fda-calendar".to_string(),
+            status: 200,
+            headers: Vec::new(),
+            fetched_at: DateTime::parse_from_rfc3339(fetched_at).unwrap().with_timezone(&Utc),
+            body,
+        }
+    }
+
+    #[test]
+    fn history_from_archive() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+        let calendar = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+        archive.store(&page("2019-03-01T12:00:00Z", calendar.replace("$1.26", "$0.90"))).unwrap();
+        archive.store(&page("2019-04-20T12:00:00Z", calendar.replace("$1.26", "$1.10"))).unwrap();
+        archive.store(&page("2019-04-20T18:00:00Z", calendar.replace("$1.26", "$1.20"))).unwrap();
+        archive.store(&page("2019-04-30T12:00:00Z", calendar.replace("$1.26", "1.26"))).unwrap();
+        archive.store(&page("2019-05-01T12:00:00Z", calendar)).unwrap();
+
+        let history = PriceHistory::from_archive(&archive, NaiveDate::from_ymd(2019, 5, 1)).unwrap();
+        //The March download is too old, the April 30th one doesn't parse, and only the later of the two April 20th downloads counts
+        let btx: Vec<String> = history.prices_for("BTX").iter().map(|x| x.to_string()).collect();
+        assert_eq!(btx, vec!["$1.20", "$1.26"]);
+        assert!(history.prices_for("NOPE").is_empty());
+
+        //The three that parsed are cached, the March one is out of range
+        let cache = fs::read_to_string(archive_dir.path().join(PRICE_CACHE_FILE_NAME)).unwrap();
+        assert_eq!(cache.lines().count(), 3, "{}", cache);
+        assert!(cache.lines().all(|x| x.contains("\tGWPH\t")), "{}", cache);
+    }
+
+    #[test]
+    fn cached_prices_not_reparsed() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive = PageArchive::new(archive_dir.path(), RetentionPolicy::new());
+        let calendar = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+        let stored = archive.store(&page("2019-04-30T12:00:00Z", calendar.replace("$1.26", "$1.10"))).unwrap();
+        PriceHistory::from_archive(&archive, NaiveDate::from_ymd(2019, 5, 1)).unwrap();
+
+        //A download that's gone can still be read from the cache
+        fs::remove_file(archive_dir.path().join(format!("{}.html.gz", stored.entry.hash))).unwrap();
+        archive.store(&page("2019-05-01T12:00:00Z", calendar)).unwrap();
+        let history = PriceHistory::from_archive(&archive, NaiveDate::from_ymd(2019, 5, 1)).unwrap();
+        let btx: Vec<String> = history.prices_for("BTX").iter().map(|x| x.to_string()).collect();
+        assert_eq!(btx, vec!["$1.10", "$1.26"]);
+
+        let cache = read_price_cache(&archive_dir.path().join(PRICE_CACHE_FILE_NAME));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache[&stored.entry.hash].len(), 3);
+    }
+
+    #[test]
+    fn record_parsed_rows() {
+        let catalysts = fda_scraper::parse_rows(std::path::Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let mut history = PriceHistory::new();
+        history.record(NaiveDate::from_ymd(2019, 5, 1), &catalysts);
+        assert_eq!(history.prices_for("BTX"), vec![&currency::USD::new("$1.26").unwrap()]);
+    }
+}
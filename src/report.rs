@@ -0,0 +1,156 @@
+use askama::Template;
+use std::collections::BTreeMap;
+use super::fda_scraper::{ScrapedCatalysts, Grouping, RowGroup, CompanySummary};
+use super::watchlist::{Watchlist, Holding};
+use super::theme::Theme;
+use super::price_history::PriceHistory;
+use super::sparkline;
+
+//How the scraped rows are presented: holdings shown ahead of everything else, how rows are grouped, the theme and each
+//symbol's sparkline. The rows are the same however they're presented, so they're borrowed rather than kept here
+#[derive(Debug, Template)]
+#[template(path = "scraped_catalysts_email_body.html")]
+pub struct Report<'a> {
+    catalysts: &'a ScrapedCatalysts,
+    //Watchlisted symbols with a catalyst coming up
+    holdings: Vec<Holding>,
+    grouping: Grouping,
+    theme: Theme,
+    //PNGs of each symbol's recent prices, by symbol
+    sparklines: BTreeMap<String, Vec<u8>>,
+}
+
+//The same report for mail clients that only show plain text
+#[derive(Template)]
+#[template(path = "scraped_catalysts_email_body.txt")]
+struct PlainTextReport<'a> {
+    report: &'a Report<'a>,
+}
+
+impl<'a> Report<'a> {
+    //The original layout, phase then date groups in the default theme
+    pub fn new(catalysts: &'a ScrapedCatalysts) -> Report<'a> {
+        Report { catalysts, holdings: Vec::new(), grouping: Grouping::ByPhaseThenDate, theme: Theme::default(), sparklines: BTreeMap::new() }
+    }
+
+    pub fn set_holdings(mut self, watchlist: &Watchlist) -> Self {
+        self.holdings = watchlist.holdings(self.catalysts);
+        self
+    }
+
+    pub fn set_grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    pub fn set_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    //Symbols with less than two days of history go without a sparkline
+    pub fn set_price_history(mut self, history: &PriceHistory) -> Self {
+        let mut sparklines = BTreeMap::new();
+        for a_row in self.catalysts.rows() {
+            if let Some(png) = sparkline::render(&history.prices_for(&a_row.symbol)) {
+                sparklines.insert(a_row.symbol.clone(), png);
+            }
+        }
+        self.sparklines = sparklines;
+        self
+    }
+
+    pub fn catalysts(&self) -> &'a ScrapedCatalysts {
+        self.catalysts
+    }
+
+    pub fn holdings(&self) -> &[Holding] {
+        &self.holdings
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    //The Content-ID a report refers to the symbol's sparkline by, as data URIs are stripped by Gmail
+    pub fn sparkline_cid(&self, symbol: &str) -> Option<String> {
+        if self.sparklines.contains_key(symbol) { Option::Some(format!("sparkline-{}", symbol)) } else { Option::None }
+    }
+
+    //Each sparkline's Content-ID and PNG, to send alongside the report
+    pub fn sparklines(&self) -> Vec<(String, &[u8])> {
+        self.sparklines.iter().map(|(symbol, png)| (format!("sparkline-{}", symbol), png.as_slice())).collect()
+    }
+
+    //The report's layout, for the template and anything else presenting the rows
+    pub fn groups(&self) -> Vec<RowGroup<'a>> {
+        self.catalysts.groups(self.grouping)
+    }
+
+    pub fn clustered_companies(&self) -> Vec<CompanySummary<'a>> {
+        self.catalysts.clustered_companies()
+    }
+
+    pub fn summary_line(&self) -> String {
+        self.catalysts.summary_line()
+    }
+
+    pub fn render_text(&self) -> Result<String, askama::Error> {
+        PlainTextReport { report: self }.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use chrono::NaiveDate;
+    use std::fs;
+    use std::path::Path;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn holdings_rendered_first() {
+        let scraped = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let report = Report::new(&scraped).set_holdings(&Watchlist::parse("BTX,1000,$1.50").unwrap());
+
+        let rendered = report.render().unwrap();
+        let holdings_at = rendered.find("Your holdings with upcoming catalysts").unwrap();
+        assert!(holdings_at < rendered.find("Upcoming Catalysts</h2>").unwrap());
+        assert!(rendered.contains("<td>-$240.00</td>"));
+    }
+
+    #[test]
+    fn plain_text_report() {
+        let scraped = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html"), ScrapePredicates::new()).unwrap();
+        let report = Report::new(&scraped).set_holdings(&Watchlist::parse("BTX,1000,$1.50").unwrap());
+        let expected = fs::read_to_string("test-resources/fda_calendar_clustered.txt").unwrap();
+        assert_eq!(report.render_text().unwrap(), expected);
+    }
+
+    #[test]
+    fn sparklines_from_price_history() {
+        let calendar = fs::read_to_string("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html").unwrap();
+        let scraped = fda_scraper::parse_html(&calendar, &ScrapePredicates::new()).unwrap();
+        let mut history = PriceHistory::new();
+        history.record(date("2019-04-30"), &scraped);
+        //A second day's prices for BTX only
+        let btx_only = ScrapePredicates::new().set_price_limit(crate::currency::USD::new("$5").unwrap());
+        history.record(date("2019-05-01"), &fda_scraper::parse_html(&calendar.replace("$1.26", "$1.40"), &btx_only).unwrap());
+        let report = Report::new(&scraped).set_price_history(&history);
+
+        assert_eq!(report.sparkline_cid("BTX"), Some("sparkline-BTX".to_string()));
+        assert_eq!(report.sparkline_cid("GWPH"), None);
+        let sparklines = report.sparklines();
+        assert_eq!(sparklines.len(), 1);
+        assert_eq!(sparklines[0].0, "sparkline-BTX");
+        assert!(sparklines[0].1.starts_with(b"\x89PNG"));
+
+        let rendered = report.render().unwrap();
+        assert!(rendered.contains(r#"<img src="cid:sparkline-BTX" width="80" height="20""#));
+        assert_eq!(rendered.matches("<img").count(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+use html5ever::tokenizer::{Tokenizer, TokenizerOpts, TokenSink, TokenSinkResult, Token, Tag, TagKind, BufferQueue};
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tendril::StrTendril;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+//Pulls the markup of each calendar row (tr.js-tr.js-drug) out of the page as soon as the row closes,
+//so the document tree is never built. Memory is only bounded if the input is, do_scraping reads a body it already holds
+pub struct RowStream<R: Read> {
+    input: R,
+    tokenizer: Tokenizer<RowCollector>,
+    queue: BufferQueue,
+    undecoded: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> RowStream<R> {
+    pub fn new(input: R) -> RowStream<R> {
+        RowStream {
+            input,
+            tokenizer: Tokenizer::new(RowCollector::new(), TokenizerOpts::default()),
+            queue: BufferQueue::new(),
+            undecoded: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let read = match self.input.read(&mut chunk) {
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if read == 0 {
+            if !self.undecoded.is_empty() {
+                let remainder = String::from_utf8_lossy(&self.undecoded).into_owned();
+                self.undecoded.clear();
+                self.queue.push_back(StrTendril::from_slice(&remainder));
+            }
+            //The sink never hands back a script, so feeding always runs to completion
+            let _ = self.tokenizer.feed(&mut self.queue);
+            self.tokenizer.end();
+            self.finished = true;
+            return Ok(());
+        }
+
+        self.undecoded.extend_from_slice(&chunk[..read]);
+        let decoded = take_decoded(&mut self.undecoded);
+        self.queue.push_back(StrTendril::from_slice(&decoded));
+        let _ = self.tokenizer.feed(&mut self.queue);
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for RowStream<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if let Some(row) = self.tokenizer.sink.completed_rows.pop_front() {
+                return Some(Ok(row));
+            }
+            if self.finished {
+                return None;
+            }
+            if let Err(e) = self.read_chunk() {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+//Decodes as much of the buffer as is complete UTF-8, leaving a multi-byte character split across reads for the next one
+fn take_decoded(undecoded: &mut Vec<u8>) -> String {
+    let complete = match std::str::from_utf8(undecoded) {
+        Ok(_) => undecoded.len(),
+        Err(e) => match e.error_len() {
+            None => e.valid_up_to(),
+            Some(_) => undecoded.len(), //genuinely invalid bytes, these get replaced below
+        }
+    };
+    let rest = undecoded.split_off(complete);
+    let decoded = String::from_utf8_lossy(undecoded).into_owned();
+    *undecoded = rest;
+    decoded
+}
+
+struct RowCollector {
+    current_row: Option<String>,
+    open_rows: usize,
+    open_tables: usize,
+    completed_rows: VecDeque<String>,
+}
+
+impl RowCollector {
+    fn new() -> RowCollector {
+        RowCollector { current_row: None, open_rows: 0, open_tables: 0, completed_rows: VecDeque::new() }
+    }
+
+    fn finish_row(&mut self) {
+        if let Some(row) = self.current_row.take() {
+            self.completed_rows.push_back(row);
+        }
+        self.open_rows = 0;
+        self.open_tables = 0;
+    }
+
+    fn process_tag(&mut self, tag: &Tag) {
+        let name: &str = &tag.name;
+        let in_row = self.current_row.is_some();
+        let in_nested_table = self.open_tables > 0;
+
+        match (tag.kind, name) {
+            //rows don't nest without a table in between, so a new row or the end of the table closes the current one
+            (TagKind::StartTag, "tr") if in_row && !in_nested_table => self.finish_row(),
+            (TagKind::EndTag, "tbody") | (TagKind::EndTag, "table") if in_row && !in_nested_table => self.finish_row(),
+            _ => (),
+        }
+
+        if self.current_row.is_none() && tag.kind == TagKind::StartTag && name == "tr" && is_calendar_row(tag) {
+            self.current_row = Some(String::new());
+        }
+
+        if let Some(row) = self.current_row.as_mut() {
+            write_tag(row, tag);
+            match (tag.kind, name) {
+                (TagKind::StartTag, "table") => self.open_tables += 1,
+                (TagKind::EndTag, "table") => self.open_tables = self.open_tables.saturating_sub(1),
+                (TagKind::StartTag, "tr") if !tag.self_closing => self.open_rows += 1,
+                (TagKind::EndTag, "tr") => {
+                    self.open_rows = self.open_rows.saturating_sub(1);
+                    if self.open_rows == 0 {
+                        self.finish_row();
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+impl TokenSink for RowCollector {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => {
+                self.process_tag(&tag);
+                if tag.kind == TagKind::StartTag {
+                    //Without a tree builder to do it, the sink has to switch the tokenizer into raw text for these
+                    match &*tag.name {
+                        "script" => return TokenSinkResult::RawData(RawKind::ScriptData),
+                        "style" | "xmp" | "iframe" | "noembed" | "noframes" => return TokenSinkResult::RawData(RawKind::Rawtext),
+                        "title" | "textarea" => return TokenSinkResult::RawData(RawKind::Rcdata),
+                        _ => (),
+                    }
+                }
+            },
+            Token::CharacterTokens(text) => {
+                if let Some(row) = self.current_row.as_mut() {
+                    push_escaped(row, &text, false);
+                }
+            },
+            Token::EOFToken => self.finish_row(),
+            _ => (),
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+fn is_calendar_row(tag: &Tag) -> bool {
+    tag.attrs.iter()
+        .find(|x| &*x.name.local == "class")
+        .map_or(false, |class| {
+            let classes: Vec<&str> = class.value.split_whitespace().collect();
+            classes.contains(&"js-tr") && classes.contains(&"js-drug")
+        })
+}
+
+//The tokenizer has already decoded entities, so the row markup has to be escaped again to be parsed a second time
+fn write_tag(row: &mut String, tag: &Tag) {
+    row.push('<');
+    if tag.kind == TagKind::EndTag {
+        row.push('/');
+    }
+    row.push_str(&tag.name);
+    for attr in &tag.attrs {
+        row.push(' ');
+        row.push_str(&attr.name.local);
+        row.push_str("=\"");
+        push_escaped(row, &attr.value, true);
+        row.push('"');
+    }
+    if tag.self_closing {
+        row.push_str(" /");
+    }
+    row.push('>');
+}
+
+fn push_escaped(row: &mut String, text: &str, in_attribute: bool) {
+    for x in text.chars() {
+        match x {
+            '&' => row.push_str("&amp;"),
+            '<' => row.push_str("&lt;"),
+            '>' => row.push_str("&gt;"),
+            '"' if in_attribute => row.push_str("&quot;"),
+            '\u{a0}' => row.push_str("&nbsp;"),
+            _ => row.push(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::OneByteReader;
+
+    fn rows(html: &str) -> Vec<String> {
+        RowStream::new(html.as_bytes()).map(|x| x.unwrap()).collect()
+    }
+
+    #[test]
+    fn only_calendar_rows() {
+        let html = r#"<table><tr><th>Ticker</th></tr><tr class="js-tr js-drug"><td>BTX</td></tr><tr class="other"><td>x</td></tr></table>"#;
+        assert_eq!(rows(html), vec![r#"<tr class="js-tr js-drug"><td>BTX</td></tr>"#.to_string()]);
+    }
+
+    #[test]
+    fn nested_tables_and_unclosed_rows() {
+        let html = r#"<table><tr class="js-tr js-drug"><td><table><tr><td>inner</td></tr></table></td><tr class="js-tr js-drug"><td>second</td></table>"#;
+        assert_eq!(rows(html), vec![
+            r#"<tr class="js-tr js-drug"><td><table><tr><td>inner</td></tr></table></td>"#.to_string(),
+            r#"<tr class="js-tr js-drug"><td>second</td>"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn entities_are_escaped_again() {
+        let html = r#"<table><tr class="js-tr js-drug"><td title="a &quot;b&quot;">R&amp;D &lt;1&gt;&nbsp;x</td></tr></table>"#;
+        assert_eq!(rows(html), vec![r#"<tr class="js-tr js-drug"><td title="a &quot;b&quot;">R&amp;D &lt;1&gt;&nbsp;x</td></tr>"#.to_string()]);
+    }
+
+    #[test]
+    fn script_contents_are_not_rows() {
+        let html = r#"<script>var row = '<tr class="js-tr js-drug"></tr>';</script><table><tr class="js-tr js-drug"><td>BTX</td></tr></table>"#;
+        assert_eq!(rows(html), vec![r#"<tr class="js-tr js-drug"><td>BTX</td></tr>"#.to_string()]);
+    }
+
+    #[test]
+    fn split_reads() {
+        let html = "<table><tr class=\"js-tr js-drug\"><td>Sjögren’s</td></tr></table>";
+        let actual: Vec<String> = RowStream::new(OneByteReader(html.as_bytes())).map(|x| x.unwrap()).collect();
+        assert_eq!(actual, vec!["<tr class=\"js-tr js-drug\"><td>Sjögren’s</td></tr>".to_string()]);
+    }
+}
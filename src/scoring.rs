@@ -0,0 +1,154 @@
+use chrono::NaiveDate;
+use std::fmt;
+use super::currency;
+use super::catalyst_note::EventType;
+use super::fda_scraper::ParsedRow;
+
+//Ratios like the short ratio and relative volume are shown to two decimal places, kept as hundredths so rows stay Eq
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub struct Ratio {
+    hundredths: u64,
+}
+
+impl Ratio {
+    pub fn new(ratio: &str) -> Option<Ratio> {
+        let ratio = ratio.trim().replace(',', "");
+        let mut split = ratio.splitn(2, '.');
+        let whole: u64 = split.next()?.parse().ok()?;
+        let fraction = match split.next() {
+            Some(fraction) if fraction.len() <= 2 => format!("{:0<2}", fraction).parse::<u64>().ok()?,
+            Some(_) => return None,
+            None => 0
+        };
+        Some(Ratio { hundredths: whole.checked_mul(100)?.checked_add(fraction)? })
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.hundredths as f64 / 100.0
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:02}", self.hundredths / 100, self.hundredths % 100)
+    }
+}
+
+//The calendar's hidden columns, any of which can be blank
+#[derive(Debug, Eq, PartialEq, Default)]
+pub struct MarketMetrics {
+    pub market_cap: Option<currency::USD>,
+    pub short_ratio: Option<Ratio>,
+    pub relative_volume: Option<Ratio>,
+}
+
+//Higher scores are riskier, and more worth a look, than lower ones.
+//The phase is the calendar's stage label, e.g. "phase3", since a row only keeps the display text
+pub trait Scorer {
+    fn score(&self, phase: &str, row: &ParsedRow, today: NaiveDate) -> u32;
+}
+
+//Scores out of 100: phase 25, days to the catalyst 20, market cap 20, note 15, short ratio 10 and relative volume 10
+pub struct DefaultScorer;
+
+impl DefaultScorer {
+    fn phase_points(phase: &str) -> u32 {
+        match phase {
+            "nda" | "bla" | "pdufa" | "phase3" | "phase2.5" => 25,
+            "phase2" | "phase1.5" => 15,
+            "phase1" => 8,
+            _ => 5
+        }
+    }
+
+    fn days_points(row: &ParsedRow, today: NaiveDate) -> u32 {
+        match row.catalyst_date.start().map(|x| x.signed_duration_since(today).num_days()) {
+            Some(days) if days <= 1 => 20,
+            Some(days) if days <= 7 => 15,
+            Some(days) if days <= 30 => 8,
+            Some(_) => 4,
+            None => 2 //TBD
+        }
+    }
+
+    //Smaller companies move further on a single catalyst
+    fn market_cap_points(metrics: &MarketMetrics) -> u32 {
+        let market_cap = match &metrics.market_cap {
+            Some(market_cap) => market_cap,
+            None => return 10
+        };
+        if *market_cap < currency::USD::new("$100000000").unwrap() {
+            20
+        } else if *market_cap < currency::USD::new("$500000000").unwrap() {
+            14
+        } else if *market_cap < currency::USD::new("$2000000000").unwrap() {
+            8
+        } else {
+            3
+        }
+    }
+
+    //Thresholds are in hundredths
+    fn ratio_points(ratio: Option<Ratio>, thresholds: [(u64, u32); 3]) -> u32 {
+        ratio.and_then(|ratio| thresholds.iter().find(|x| ratio.hundredths >= x.0).map(|x| x.1)).unwrap_or(0)
+    }
+
+    //A note that already reports an outcome is about an event that's been and gone
+    fn note_points(row: &ParsedRow) -> u32 {
+        let points = row.note_classification.event_types.iter()
+            .map(|x| match x {
+                EventType::Pdufa | EventType::AdCom => 15,
+                EventType::ToplineData => 12,
+                EventType::ConferencePresentation => 5,
+                EventType::EnrollmentComplete => 3,
+            })
+            .max()
+            .unwrap_or(0);
+        if row.note_classification.outcome.is_some() { points / 2 } else { points }
+    }
+}
+
+impl Scorer for DefaultScorer {
+    fn score(&self, phase: &str, row: &ParsedRow, today: NaiveDate) -> u32 {
+        DefaultScorer::phase_points(phase)
+            + DefaultScorer::days_points(row, today)
+            + DefaultScorer::market_cap_points(&row.metrics)
+            + DefaultScorer::note_points(row)
+            + DefaultScorer::ratio_points(row.metrics.short_ratio, [(1000, 10), (500, 6), (200, 3)])
+            + DefaultScorer::ratio_points(row.metrics.relative_volume, [(300, 10), (150, 6), (100, 3)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use std::path::Path;
+
+    #[test]
+    fn parse_ratio() {
+        assert_eq!(Ratio::new("12.47").unwrap().to_string(), "12.47");
+        assert_eq!(Ratio::new(" 1.5 ").unwrap().to_string(), "1.50");
+        assert_eq!(Ratio::new("3").unwrap().to_string(), "3.00");
+        assert_eq!(Ratio::new("1,204.10").unwrap().to_string(), "1204.10");
+        assert_eq!(Ratio::new(""), None);
+        assert_eq!(Ratio::new("1.234"), None);
+        assert_eq!(Ratio::new("n/a"), None);
+        assert_eq!(Ratio::new("184467440737095516.16"), None);
+        assert_eq!(Ratio::new("184467440737095517"), None);
+    }
+
+    #[test]
+    fn default_scores() {
+        let catalysts = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let today = NaiveDate::from_ymd(2019, 5, 1);
+        let score_of = |symbol: &str, phase: &str| DefaultScorer.score(phase, catalysts.rows().find(|x| x.symbol == symbol).unwrap(), today);
+
+        //phase 1/2, tomorrow, $188M, topline data, short ratio 12.47, relative volume 1.04
+        assert_eq!(score_of("BTX", "phase1.5"), 15 + 20 + 14 + 12 + 10 + 3);
+        //phase 3, in 2 days, $72M, topline data already met, no short ratio, relative volume 0.54
+        assert_eq!(score_of("EYEN", "phase3"), 25 + 15 + 20 + 6);
+        //phase 3, in 2 days, $5.3B, conference presentation, no short ratio, relative volume 0.83
+        assert_eq!(score_of("GWPH", "phase3"), 25 + 15 + 3 + 5);
+    }
+}
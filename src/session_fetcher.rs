@@ -0,0 +1,417 @@
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE};
+use reqwest::Url;
+use scraper::Html;
+use regex::Regex;
+use lazy_static::lazy_static;
+use chrono::{DateTime, Utc, Duration};
+use log::info;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use super::fetcher::{Fetcher, FetchedPage, FetchError, host_of};
+use super::fda_scraper::build_selector_for;
+use super::polite_fetcher::{PoliteFetcher, redirect_location};
+
+const MAX_REDIRECTS: usize = 5;
+
+//Where and as whom to log in, and where the session's cookies are kept between runs
+#[derive(Clone)]
+pub struct SessionConfig {
+    login_url: String,
+    email: String,
+    password: String,
+    cookie_jar_path: PathBuf,
+    //The saved session is used, but a new one is only kept for the run, for a dry run
+    jar_read_only: bool,
+}
+
+impl SessionConfig {
+    pub fn new(login_url: &str, email: &str, password: &str, cookie_jar_path: &Path) -> SessionConfig {
+        SessionConfig { login_url: login_url.to_owned(), email: email.to_owned(), password: password.to_owned(), cookie_jar_path: cookie_jar_path.to_owned(), jar_read_only: false }
+    }
+
+    pub fn set_jar_read_only(mut self) -> Self {
+        self.jar_read_only = true;
+        self
+    }
+}
+
+//Fetches as a logged in user through a PoliteFetcher, logging in again whenever a response shows the session has lapsed.
+//Redirects are followed here so cookies set along the way aren't lost, and cookies only go to the login url's host
+pub struct SessionFetcher {
+    inner: PoliteFetcher,
+    config: SessionConfig,
+    session_host: String,
+    cookie_jar: Mutex<CookieJar>,
+}
+
+impl SessionFetcher {
+    pub fn new(inner: PoliteFetcher, config: SessionConfig) -> Result<SessionFetcher, FetchError> {
+        let session_host = host_of(&config.login_url);
+        if session_host.is_empty() {
+            return Err(FetchError::InvalidUrl(config.login_url.clone()));
+        }
+        let cookie_jar = CookieJar::load(&config.cookie_jar_path)?;
+        Ok(SessionFetcher { inner, config, session_host, cookie_jar: Mutex::new(cookie_jar) })
+    }
+
+    //Sends with the session's cookies and keeps any the response sets, for the login url's host only
+    fn send(&self, url: &Url, form: Option<&[(&str, &str)]>) -> Result<FetchedPage, FetchError> {
+        let in_session = host_of(url.as_str()) == self.session_host;
+        let mut headers = HeaderMap::new();
+        if in_session {
+            let cookie_header = self.cookie_jar.lock().unwrap().cookie_header(Utc::now());
+            if let Some(cookie_header) = cookie_header.and_then(|x| HeaderValue::from_str(&x).ok()) {
+                headers.insert(COOKIE, cookie_header);
+            }
+        }
+        let page = self.inner.send(url.as_str(), headers, form)?;
+
+        if in_session {
+            let mut cookie_jar = self.cookie_jar.lock().unwrap();
+            let set_cookies = page.headers.iter().filter(|x| x.0.eq_ignore_ascii_case("set-cookie")).map(|x| x.1.as_str());
+            if cookie_jar.absorb(set_cookies, Utc::now()) && !self.config.jar_read_only {
+                cookie_jar.save(&self.config.cookie_jar_path)?;
+            }
+        }
+        Ok(page)
+    }
+
+    fn is_login_page(&self, url: &Url) -> bool {
+        Url::parse(&self.config.login_url).map_or(false, |login_url| login_url.path() == url.path())
+    }
+
+    //None when the site shows us as logged out
+    fn fetch_as_member(&self, url: &str) -> Result<Option<FetchedPage>, FetchError> {
+        let mut current_url = Url::parse(url).map_err(|_| FetchError::InvalidUrl(url.to_owned()))?;
+        for _ in 0..MAX_REDIRECTS {
+            let page = self.send(&current_url, None)?;
+
+            if let Some(location) = redirect_location(&page)? {
+                if self.is_login_page(&location) {
+                    return Ok(None);
+                }
+                current_url = location;
+                continue;
+            }
+            if page.status == 401 {
+                return Ok(None);
+            }
+            if page.status >= 400 {
+                return Err(FetchError::ErrorStatus(current_url.into_string(), page.status));
+            }
+            if shows_login_form(&page.body) {
+                return Ok(None);
+            }
+
+            let headers = page.headers.into_iter().filter(|x| !x.0.eq_ignore_ascii_case("set-cookie")).collect();
+            return Ok(Some(FetchedPage { url: url.to_owned(), headers, ..page }));
+        }
+        Err(FetchError::TooManyRedirects(url.to_owned()))
+    }
+
+    //Loads the login form for its CSRF token, then posts the credentials with it
+    fn log_in(&self) -> Result<(), FetchError> {
+        let login_failed = || FetchError::LoginFailed(self.config.login_url.clone());
+        let login_url = Url::parse(&self.config.login_url).map_err(|_| FetchError::InvalidUrl(self.config.login_url.clone()))?;
+
+        let login_page = self.send(&login_url, None)?;
+        if login_page.status >= 400 {
+            return Err(FetchError::ErrorStatus(self.config.login_url.clone(), login_page.status));
+        }
+        let token_selector = build_selector_for("input[name=_token]").map_err(|_| login_failed())?;
+        let csrf_token = Html::parse_document(&login_page.body)
+            .select(&token_selector).next()
+            .and_then(|x| x.value().attr("value").map(str::to_owned))
+            .unwrap_or_default();
+
+        let form = [("_token", csrf_token.as_str()), ("email", self.config.email.as_str()), ("password", self.config.password.as_str())];
+        let response = self.send(&login_url, Some(&form))?;
+
+        //A successful login redirects away from the form, a failed one redirects back to it or shows it again
+        match redirect_location(&response)? {
+            Some(location) if !self.is_login_page(&location) => (),
+            _ => return Err(login_failed()),
+        }
+        info!("Logged in as {}", self.config.email);
+        Ok(())
+    }
+}
+
+impl Fetcher for SessionFetcher {
+    fn fetch(&self, url: &str) -> Result<FetchedPage, FetchError> {
+        if let Some(page) = self.fetch_as_member(url)? {
+            return Ok(page);
+        }
+        info!("Logged out fetching {}, logging in", url);
+        self.log_in()?;
+        self.fetch_as_member(url)?.ok_or_else(|| FetchError::LoggedOut(url.to_owned()))
+    }
+}
+
+lazy_static! {
+    static ref PASSWORD_INPUT: Regex = Regex::new(r#"(?i)<input[^>]*\btype\s*=\s*["']?password\b"#).unwrap();
+}
+
+//Member pages never ask for a password, so one that does is the login form served in their place.
+//Searched for rather than parsed so a large page isn't built into a tree just for this
+fn shows_login_form(body: &str) -> bool {
+    PASSWORD_INPUT.is_match(body)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Cookie {
+    value: String,
+    //None for cookies that last as long as the session
+    expires: Option<DateTime<Utc>>,
+}
+
+//Cookies by name for the one site we log in to, saved as name, value and expiry separated by tabs
+#[derive(Debug, Eq, PartialEq)]
+struct CookieJar {
+    cookies: BTreeMap<String, Cookie>,
+}
+
+impl CookieJar {
+    fn new() -> CookieJar {
+        CookieJar { cookies: BTreeMap::new() }
+    }
+
+    //A jar that isn't there yet is empty, it's written on the first login
+    fn load(cookie_jar_path: &Path) -> Result<CookieJar, FetchError> {
+        let saved = match fs::read_to_string(cookie_jar_path) {
+            Ok(saved) => saved,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(CookieJar::new()),
+            Err(e) => return Err(FetchError::CookieJarIoError(cookie_jar_path.to_owned(), e)),
+        };
+        let cookies = saved.lines()
+            .filter_map(|a_line| {
+                let mut fields = a_line.splitn(3, '\t');
+                let name = fields.next()?.to_owned();
+                let value = fields.next()?.to_owned();
+                let expires = fields.next().and_then(|x| DateTime::parse_from_rfc3339(x).ok()).map(|x| x.with_timezone(&Utc));
+                Some((name, Cookie { value, expires }))
+            })
+            .collect();
+        Ok(CookieJar { cookies })
+    }
+
+    fn save(&self, cookie_jar_path: &Path) -> Result<(), FetchError> {
+        let saved: String = self.cookies.iter()
+            .map(|(name, cookie)| format!("{}\t{}\t{}\n", name, cookie.value, cookie.expires.map(|x| x.to_rfc3339()).unwrap_or_default()))
+            .collect();
+        write_private(cookie_jar_path, &saved).map_err(|x| FetchError::CookieJarIoError(cookie_jar_path.to_owned(), x))
+    }
+
+    fn cookie_header(&self, now: DateTime<Utc>) -> Option<String> {
+        let live: Vec<String> = self.cookies.iter()
+            .filter(|(_, cookie)| cookie.expires.map_or(true, |x| x > now))
+            .map(|(name, cookie)| format!("{}={}", name, cookie.value))
+            .collect();
+        if live.is_empty() { None } else { Some(live.join("; ")) }
+    }
+
+    //Takes each Set-Cookie header's value. Returns whether anything changed, an expiry in the past or a Max-Age of 0 removes the cookie
+    fn absorb<'a>(&mut self, set_cookies: impl Iterator<Item = &'a str>, now: DateTime<Utc>) -> bool {
+        let mut changed = false;
+        for a_set_cookie in set_cookies {
+            if let Some((name, cookie)) = parse_set_cookie(a_set_cookie, now) {
+                if cookie.expires.map_or(false, |x| x <= now) {
+                    changed |= self.cookies.remove(&name).is_some();
+                } else if self.cookies.get(&name) != Some(&cookie) {
+                    self.cookies.insert(name, cookie);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+//The session's cookies are as good as the password, so only the owner can read them
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        //mode only applies to a file being created, one left from before is narrowed too
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn parse_set_cookie(set_cookie: &str, now: DateTime<Utc>) -> Option<(String, Cookie)> {
+    let mut attributes = set_cookie.split(';');
+    let mut name_and_value = attributes.next()?.splitn(2, '=');
+    let name = name_and_value.next()?.trim().to_owned();
+    let value = name_and_value.next()?.trim().to_owned();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut expires = None;
+    let mut max_age = None;
+    for an_attribute in attributes {
+        let mut key_and_value = an_attribute.splitn(2, '=');
+        let key = key_and_value.next().unwrap_or("").trim().to_lowercase();
+        let attribute_value = key_and_value.next().unwrap_or("").trim();
+        match key.as_str() {
+            //Some servers write the date with dashes, e.g. Thu, 02-May-2019 14:00:00 GMT
+            "expires" => expires = DateTime::parse_from_rfc2822(&attribute_value.replace('-', " ")).ok().map(|x| x.with_timezone(&Utc)),
+            "max-age" => max_age = attribute_value.parse::<i64>().ok().map(|x| now + Duration::seconds(x)),
+            _ => (),
+        }
+    }
+    //Max-Age wins over Expires when both are given
+    Some((name, Cookie { value, expires: max_age.or(expires) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::tests::{serve, response};
+    use crate::polite_fetcher::CrawlPolicy;
+    use std::sync::Arc;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn set_cookie_expiry() {
+        let now = at("2019-05-01T12:00:00Z");
+        assert_eq!(parse_set_cookie("session=s1; path=/; HttpOnly", now), Some(("session".to_string(), Cookie { value: "s1".to_string(), expires: None })));
+        assert_eq!(parse_set_cookie("XSRF-TOKEN=abc; expires=Thu, 02-May-2019 14:00:00 GMT; Max-Age=7200; path=/", now),
+                   Some(("XSRF-TOKEN".to_string(), Cookie { value: "abc".to_string(), expires: Some(at("2019-05-01T14:00:00Z")) })));
+        assert_eq!(parse_set_cookie("remember=r1; Expires=Thu, 02 May 2019 14:00:00 GMT", now),
+                   Some(("remember".to_string(), Cookie { value: "r1".to_string(), expires: Some(at("2019-05-02T14:00:00Z")) })));
+        assert_eq!(parse_set_cookie("no value", now), None);
+    }
+
+    #[test]
+    fn cookie_jar_round_trip() {
+        let now = at("2019-05-01T12:00:00Z");
+        let set_cookies = ["session=s1; path=/", "XSRF-TOKEN=abc; Max-Age=60"];
+
+        let mut cookie_jar = CookieJar::new();
+        assert!(cookie_jar.absorb(set_cookies.iter().cloned(), now));
+        assert!(!cookie_jar.absorb(set_cookies.iter().cloned(), now));
+        assert_eq!(cookie_jar.cookie_header(now), Some("XSRF-TOKEN=abc; session=s1".to_string()));
+        assert_eq!(cookie_jar.cookie_header(at("2019-05-01T12:05:00Z")), Some("session=s1".to_string()));
+
+        let cookie_jar_dir = tempfile::tempdir().unwrap();
+        let cookie_jar_path = cookie_jar_dir.path().join("cookies.tsv");
+        cookie_jar.save(&cookie_jar_path).unwrap();
+        assert_eq!(CookieJar::load(&cookie_jar_path).unwrap(), cookie_jar);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&cookie_jar_path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        assert!(cookie_jar.absorb(vec!["session=deleted; Max-Age=0"].into_iter(), now));
+        assert_eq!(cookie_jar.cookie_header(now), Some("XSRF-TOKEN=abc".to_string()));
+    }
+
+    const LOGIN_FORM: &[u8] = b"<html><form method=\"POST\" action=\"/login\"><input type=\"hidden\" name=\"_token\" value=\"csrf1\"><input name=\"email\"><input type=\"password\" name=\"password\"></form></html>";
+
+    //Stands in for the site: the calendar needs the session cookie, which logging in with the right password and token sets
+    fn serve_site(connections: usize, requests: Arc<Mutex<Vec<String>>>) -> String {
+        let address = serve(connections, move |request| {
+            requests.lock().unwrap().push(request.lines().next().unwrap_or("").to_owned());
+            let logged_in = request.lines().any(|x| x.to_lowercase().starts_with("cookie:") && x.contains("session=s1"));
+            match request.lines().next().unwrap_or("") {
+                "GET /robots.txt HTTP/1.1" => response(404, &[], b""),
+                "GET /login HTTP/1.1" => response(200, &[("Set-Cookie", "XSRF-TOKEN=x1; path=/")], LOGIN_FORM),
+                "POST /login HTTP/1.1" if request.ends_with("_token=csrf1&email=me%40example.com&password=hunter2") =>
+                    response(302, &[("Location", "/dashboard"), ("Set-Cookie", "session=s1; path=/; HttpOnly")], b""),
+                "POST /login HTTP/1.1" => response(302, &[("Location", "/login")], b""),
+                "GET /calendars/fda-calendar HTTP/1.1" if logged_in =>
+                    response(200, &[], b"<html><td class=\"js-td--portfolio\" data-portfolio=\"[4821]\"></td></html>"),
+                _ => response(302, &[("Location", "/login")], b""),
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn session_fetcher(site: &str, password: &str, cookie_jar_path: &Path) -> SessionFetcher {
+        SessionFetcher::new(PoliteFetcher::new(CrawlPolicy::new("fda_calendar_scraper/0.1").set_rate_limit(100.0, 10)).unwrap(),
+                            SessionConfig::new(&format!("{}/login", site), "me@example.com", password, cookie_jar_path)).unwrap()
+    }
+
+    #[test]
+    fn logs_in_when_logged_out_and_keeps_the_session() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let site = serve_site(7, Arc::clone(&requests));
+        let cookie_jar_dir = tempfile::tempdir().unwrap();
+        let cookie_jar_path = cookie_jar_dir.path().join("cookies.tsv");
+        let calendar_url = format!("{}/calendars/fda-calendar", site);
+
+        let page = session_fetcher(&site, "hunter2", &cookie_jar_path).fetch(&calendar_url).unwrap();
+        assert!(page.body.contains("data-portfolio=\"[4821]\""));
+
+        //a later run picks the session back up from the jar without logging in again
+        session_fetcher(&site, "hunter2", &cookie_jar_path).fetch(&calendar_url).unwrap();
+
+        //each run reads robots.txt before anything else
+        assert_eq!(*requests.lock().unwrap(), vec![
+            "GET /robots.txt HTTP/1.1", "GET /calendars/fda-calendar HTTP/1.1", "GET /login HTTP/1.1", "POST /login HTTP/1.1",
+            "GET /calendars/fda-calendar HTTP/1.1", "GET /robots.txt HTTP/1.1", "GET /calendars/fda-calendar HTTP/1.1"]);
+    }
+
+    #[test]
+    fn read_only_jar_left_alone() {
+        let site = serve_site(5, Arc::new(Mutex::new(Vec::new())));
+        let cookie_jar_dir = tempfile::tempdir().unwrap();
+        let cookie_jar_path = cookie_jar_dir.path().join("cookies.tsv");
+        let config = SessionConfig::new(&format!("{}/login", site), "me@example.com", "hunter2", &cookie_jar_path).set_jar_read_only();
+        let fetcher = SessionFetcher::new(PoliteFetcher::new(CrawlPolicy::new("fda_calendar_scraper/0.1").set_rate_limit(100.0, 10)).unwrap(), config).unwrap();
+
+        assert!(fetcher.fetch(&format!("{}/calendars/fda-calendar", site)).unwrap().body.contains("data-portfolio"));
+        assert!(!cookie_jar_path.exists());
+    }
+
+    #[test]
+    fn cookies_stay_on_the_login_host() {
+        let site = serve_site(5, Arc::new(Mutex::new(Vec::new())));
+        let other_requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&other_requests);
+        let other_site = serve(2, move |request| {
+            seen.lock().unwrap().push(request.to_lowercase());
+            response(if request.starts_with("GET /robots.txt") { 404 } else { 200 }, &[("Set-Cookie", "tracker=t1")], b"<html>elsewhere</html>")
+        });
+        let cookie_jar_dir = tempfile::tempdir().unwrap();
+        let cookie_jar_path = cookie_jar_dir.path().join("cookies.tsv");
+        let fetcher = session_fetcher(&site, "hunter2", &cookie_jar_path);
+
+        fetcher.fetch(&format!("{}/calendars/fda-calendar", site)).unwrap();
+        assert_eq!(fetcher.fetch(&format!("http://{}/news", other_site)).unwrap().body, "<html>elsewhere</html>");
+
+        assert!(other_requests.lock().unwrap().iter().all(|x| !x.contains("\r\ncookie:")));
+        assert!(!fs::read_to_string(&cookie_jar_path).unwrap().contains("tracker"));
+    }
+
+    #[test]
+    fn wrong_password() {
+        let site = serve_site(4, Arc::new(Mutex::new(Vec::new())));
+        let cookie_jar_dir = tempfile::tempdir().unwrap();
+
+        match session_fetcher(&site, "wrong", &cookie_jar_dir.path().join("cookies.tsv")).fetch(&format!("{}/calendars/fda-calendar", site)) {
+            Err(FetchError::LoginFailed(_)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn login_form_in_place_of_the_page() {
+        assert!(shows_login_form(std::str::from_utf8(LOGIN_FORM).unwrap()));
+        assert!(!shows_login_form("<html><td class=\"js-td--portfolio\" data-portfolio=\"[4821]\"></td></html>"));
+    }
+}
@@ -0,0 +1,169 @@
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+use super::currency;
+
+pub const WIDTH: u32 = 80;
+pub const HEIGHT: u32 = 20;
+//Keeps the line's ends and the last price's dot inside the image
+const PADDING: u32 = 2;
+
+const RISING: [u8; 4] = [0x2e, 0x7d, 0x32, 0xff];
+const FALLING: [u8; 4] = [0xc6, 0x28, 0x28, 0xff];
+
+//A line of the prices, oldest on the left, as a PNG with a transparent background.
+//Green when the last price is at or above the first, red otherwise. None with fewer than two prices as there's no line to draw
+pub fn render(prices: &[&currency::USD]) -> Option<Vec<u8>> {
+    if prices.len() < 2 {
+        return None;
+    }
+    let cents: Vec<i128> = prices.iter().map(|x| x.signed_cents()).collect();
+    let low = *cents.iter().min()?;
+    let high = *cents.iter().max()?;
+    let color = if cents[cents.len() - 1] >= cents[0] { RISING } else { FALLING };
+
+    let plot_width = i128::from(WIDTH - 1 - 2 * PADDING);
+    let plot_height = i128::from(HEIGHT - 1 - 2 * PADDING);
+    let last_index = cents.len() as i128 - 1;
+    let points: Vec<(i128, i128)> = cents.iter().enumerate()
+        .map(|(index, price)| {
+            let x = index as i128 * plot_width / last_index;
+            //An unchanged price is drawn across the middle
+            let y = if high == low { plot_height / 2 } else { (high - price) * plot_height / (high - low) };
+            (x + i128::from(PADDING), y + i128::from(PADDING))
+        })
+        .collect();
+
+    let mut canvas = Canvas::new();
+    for pair in points.windows(2) {
+        canvas.line(pair[0], pair[1], color);
+    }
+    let (last_x, last_y) = points[points.len() - 1];
+    for x in last_x - 1..=last_x + 1 {
+        for y in last_y - 1..=last_y + 1 {
+            canvas.set(x, y, color);
+        }
+    }
+    Some(canvas.encode_png())
+}
+
+struct Canvas {
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Canvas {
+    fn new() -> Canvas {
+        Canvas { pixels: vec![[0, 0, 0, 0]; (WIDTH * HEIGHT) as usize] }
+    }
+
+    fn set(&mut self, x: i128, y: i128, color: [u8; 4]) {
+        if x >= 0 && y >= 0 && x < i128::from(WIDTH) && y < i128::from(HEIGHT) {
+            self.pixels[(y * i128::from(WIDTH) + x) as usize] = color;
+        }
+    }
+
+    //Fills each column between the two points from the previous column's height to its own, so steep moves stay joined up
+    fn line(&mut self, from: (i128, i128), to: (i128, i128), color: [u8; 4]) {
+        let (from_x, from_y) = from;
+        let (to_x, to_y) = to;
+        let span = (to_x - from_x).max(1);
+        let mut previous_y = from_y;
+        for x in from_x..=to_x {
+            let y = from_y + (to_y - from_y) * (x - from_x) / span;
+            for filled_y in previous_y.min(y)..=previous_y.max(y) {
+                self.set(x, filled_y, color);
+            }
+            previous_y = y;
+        }
+    }
+
+    //8 bit RGBA, every scanline unfiltered
+    fn encode_png(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&WIDTH.to_be_bytes());
+        header.extend_from_slice(&HEIGHT.to_be_bytes());
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        for scanline in self.pixels.chunks(WIDTH as usize) {
+            let mut filtered = vec![0];
+            filtered.extend(scanline.iter().flatten());
+            encoder.write_all(&filtered).expect("Writes to a Vec don't fail");
+        }
+        let image_data = encoder.finish().expect("Writes to a Vec don't fail");
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &image_data);
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    fn prices(prices: &[&str]) -> Vec<currency::USD> {
+        prices.iter().map(|x| currency::USD::new(x).unwrap()).collect()
+    }
+
+    //Pulls the pixels back out of the single IDAT chunk, dropping each scanline's filter byte
+    fn decode(png: &[u8]) -> Vec<[u8; 4]> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 80, 0, 0, 0, 20]);
+        let idat_length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut raw = Vec::new();
+        ZlibDecoder::new(&png[41..41 + idat_length]).read_to_end(&mut raw).unwrap();
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+        raw.chunks(WIDTH as usize * 4 + 1)
+            .flat_map(|scanline| scanline[1..].chunks(4).map(|x| [x[0], x[1], x[2], x[3]]).collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn pixel(pixels: &[[u8; 4]], x: u32, y: u32) -> [u8; 4] {
+        pixels[(y * WIDTH + x) as usize]
+    }
+
+    #[test]
+    fn rising_and_falling() {
+        let rising = prices(&["$1.00", "$1.50", "$2.00"]);
+        let pixels = decode(&render(&rising.iter().collect::<Vec<_>>()).unwrap());
+        //Lowest price bottom left, highest top right
+        assert_eq!(pixel(&pixels, PADDING, HEIGHT - 1 - PADDING), RISING);
+        assert_eq!(pixel(&pixels, WIDTH - 1 - PADDING, PADDING), RISING);
+        assert_eq!(pixel(&pixels, WIDTH - 1 - PADDING, HEIGHT - 1 - PADDING), [0, 0, 0, 0]);
+
+        let falling = prices(&["$2.00", "$1.00"]);
+        let pixels = decode(&render(&falling.iter().collect::<Vec<_>>()).unwrap());
+        assert_eq!(pixel(&pixels, PADDING, PADDING), FALLING);
+        assert_eq!(pixel(&pixels, WIDTH - 1 - PADDING, HEIGHT - 1 - PADDING), FALLING);
+    }
+
+    #[test]
+    fn flat_and_too_short() {
+        let flat = prices(&["$1.00", "$1.00"]);
+        let pixels = decode(&render(&flat.iter().collect::<Vec<_>>()).unwrap());
+        let middle = PADDING + (HEIGHT - 1 - 2 * PADDING) / 2;
+        assert!((PADDING..WIDTH - PADDING).all(|x| pixel(&pixels, x, middle) == RISING));
+
+        let single = prices(&["$1.00"]);
+        assert_eq!(render(&single.iter().collect::<Vec<_>>()), None);
+        assert_eq!(render(&[]), None);
+    }
+}
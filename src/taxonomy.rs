@@ -0,0 +1,160 @@
+use regex::Regex;
+use lazy_static::lazy_static;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum TaxonomyError {
+    IoError(PathBuf, io::Error),
+    MalformedLine(usize, String),
+}
+
+impl fmt::Display for TaxonomyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TaxonomyError::IoError(ref path, ref e) => write!(f, "Taxonomy {:?}: {}", path, e),
+            TaxonomyError::MalformedLine(line_number, ref line) => write!(f, "Malformed taxonomy line {}: {:?}", line_number, line),
+        }
+    }
+}
+
+impl error::Error for TaxonomyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            TaxonomyError::IoError(_, ref e) => Some(e),
+            TaxonomyError::MalformedLine(_, _) => None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref BUILT_IN: Taxonomy = Taxonomy::parse(include_str!("../resources/therapeutic_areas.txt")).unwrap();
+}
+
+//Maps free text drug indications to therapeutic areas, see resources/therapeutic_areas.txt for the format
+#[derive(Debug, Clone)]
+pub struct Taxonomy {
+    //In file order, which is the order a row's areas are listed in
+    areas: Vec<(String, Regex)>,
+}
+
+impl Taxonomy {
+    pub fn built_in() -> &'static Taxonomy {
+        &BUILT_IN
+    }
+
+    pub fn load(taxonomy_path: &Path) -> Result<Taxonomy, TaxonomyError> {
+        fs::read_to_string(taxonomy_path)
+            .map_err(|x| TaxonomyError::IoError(taxonomy_path.to_owned(), x))
+            .and_then(|contents| Taxonomy::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Result<Taxonomy, TaxonomyError> {
+        let mut areas: Vec<(String, Vec<String>)> = Vec::new();
+        for (index, a_line) in contents.lines().enumerate() {
+            let a_line = a_line.split('#').next().unwrap_or("").trim();
+            if a_line.is_empty() {
+                continue;
+            }
+
+            let malformed = || TaxonomyError::MalformedLine(index + 1, a_line.to_owned());
+            let mut split = a_line.splitn(2, '=');
+            let area = split.next().unwrap_or("").trim();
+            let keywords: Vec<String> = split.next().ok_or_else(malformed)?
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(keyword_pattern)
+                .collect();
+            if area.is_empty() || keywords.is_empty() {
+                return Err(malformed());
+            }
+
+            //An area given on more than one line gets all of their keywords
+            match areas.iter_mut().find(|x| x.0.eq_ignore_ascii_case(area)) {
+                Some(existing) => existing.1.extend(keywords),
+                None => areas.push((area.to_lowercase(), keywords)),
+            }
+        }
+
+        let areas = areas.into_iter()
+            .map(|(area, keywords)| {
+                let rule = Regex::new(&keywords.join("|")).expect("Keywords are escaped so always form a valid pattern");
+                (area, rule)
+            })
+            .collect();
+        Ok(Taxonomy { areas })
+    }
+
+    //Every area the indication mentions, empty if it's not covered by the taxonomy
+    pub fn areas_for(&self, indication: &str) -> Vec<String> {
+        self.areas.iter()
+            .filter(|(_, rule)| rule.is_match(indication))
+            .map(|(area, _)| area.clone())
+            .collect()
+    }
+}
+
+//Acronyms are matched case sensitively so "als" or "sma" in a word like "small" or "also" don't count.
+//Word boundaries only go where the keyword itself starts or ends in a word, so "(AMD)" or "C. diff." can still match
+fn keyword_pattern(keyword: &str) -> String {
+    let (stem, is_prefix) = match keyword.strip_suffix('*') {
+        Some(stem) => (stem, true),
+        None => (keyword, false)
+    };
+    let is_word_character = |x: char| x.is_alphanumeric() || x == '_';
+    let starts_in_word = stem.chars().next().map_or(false, is_word_character);
+    let ends_in_word = !is_prefix && stem.chars().last().map_or(false, is_word_character);
+    let is_acronym = stem.chars().any(char::is_alphabetic) && !stem.chars().any(char::is_lowercase);
+    format!(r"(?{}:{}{}{})", if is_acronym { "-i" } else { "i" }, if starts_in_word { r"\b" } else { "" },
+            regex::escape(stem), if ends_in_word { r"\b" } else { "" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_areas() {
+        let taxonomy = Taxonomy::built_in();
+        assert_eq!(taxonomy.areas_for("Dry age-related macular degeneration (AMD)"), vec!["ophthalmology".to_string()]);
+        assert_eq!(taxonomy.areas_for("Dravet Syndrome"), vec!["neurology".to_string(), "rare disease".to_string()]);
+        assert_eq!(taxonomy.areas_for("Mydriasis - pupil dilation"), vec!["ophthalmology".to_string()]);
+        assert_eq!(taxonomy.areas_for("Relapsed/refractory multiple myeloma"), vec!["oncology".to_string()]);
+        assert_eq!(taxonomy.areas_for("Hair loss"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keywords_and_aliases() {
+        let taxonomy = Taxonomy::parse("# comment\nneurology = epilep*, ALS\n\nNeurology = Parkinson's disease\nrare disease = ALS").unwrap();
+        assert_eq!(taxonomy.areas_for("Epileptic encephalopathy"), vec!["neurology".to_string()]);
+        assert_eq!(taxonomy.areas_for("Early parkinson's disease"), vec!["neurology".to_string()]);
+        assert_eq!(taxonomy.areas_for("ALS"), vec!["neurology".to_string(), "rare disease".to_string()]);
+        //acronyms only match as written, and keywords without a * only match whole words
+        assert_eq!(taxonomy.areas_for("Small cell lung cancer, also als"), Vec::<String>::new());
+        assert_eq!(taxonomy.areas_for("Parkinson's diseases"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keywords_with_punctuation() {
+        let taxonomy = Taxonomy::parse("ophthalmology = (AMD)\ninfectious disease = C. diff.").unwrap();
+        assert_eq!(taxonomy.areas_for("Dry (AMD)"), vec!["ophthalmology".to_string()]);
+        assert_eq!(taxonomy.areas_for("Recurrent C. diff. infection"), vec!["infectious disease".to_string()]);
+        assert_eq!(taxonomy.areas_for("Dry AMD"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn malformed_taxonomy() {
+        match Taxonomy::parse("neurology = epilepsy\noncology") {
+            Err(TaxonomyError::MalformedLine(2, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+        match Taxonomy::parse("= epilepsy") {
+            Err(TaxonomyError::MalformedLine(1, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use std::io;
+use std::io::Read;
+
+//Hands out a byte at a time, to split every tag and multi-byte character across reads
+pub struct OneByteReader<'a>(pub &'a [u8]);
+
+impl<'a> Read for OneByteReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok(1)
+    }
+}
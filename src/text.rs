@@ -0,0 +1,41 @@
+use scraper::ElementRef;
+use unicode_normalization::UnicodeNormalization;
+
+//Every text node under the element, in document order, normalized. Text split up by nested markup,
+//like "<b>Phase 3</b> data", comes back whole rather than cut off at the first node
+pub fn element_text(an_element_ref: &ElementRef) -> String {
+    normalize(&an_element_ref.text().collect::<String>())
+}
+
+//Scraped text as it's shown and compared: compatibility characters like non-breaking spaces and ligatures are
+//folded (NFKC), zero width characters are dropped and runs of whitespace become a single space, with none at either end.
+//Entities are left alone, the HTML parser has already decoded them
+pub fn normalize(raw: &str) -> String {
+    let folded: String = raw.nfkc()
+        .filter(|x| !matches!(x, '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{FEFF}' | '\u{AD}'))
+        .collect();
+    folded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::{Html, Selector};
+
+    #[test]
+    fn normalize_text() {
+        assert_eq!(normalize("OpRegen "), "OpRegen");
+        assert_eq!(normalize("Updated data due  May 2,\n\t2019"), "Updated data due May 2, 2019");
+        assert_eq!(normalize("-0.02\u{A0}\u{A0}-1.56%"), "-0.02 -1.56%");
+        assert_eq!(normalize("AT&amp;T &lt;b&gt;"), "AT&amp;T &lt;b&gt;");
+        assert_eq!(normalize("Ep\u{AD}idio\u{200B}lex \u{FB01}le \u{FF21}"), "Epidiolex file A");
+        assert_eq!(normalize("Cafe\u{301}"), "Caf\u{E9}");
+    }
+
+    #[test]
+    fn nested_text() {
+        let fragment = Html::parse_fragment(r#"<div class="catalyst-note">Phase <b>3</b> data  due <a href="x">May&nbsp;2</a>. </div>"#);
+        let note = fragment.select(&Selector::parse("div").unwrap()).next().unwrap();
+        assert_eq!(element_text(&note), "Phase 3 data due May 2.");
+    }
+}
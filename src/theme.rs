@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ThemeError {
+    IoError(PathBuf, io::Error),
+    MalformedLine(usize, String),
+    UnknownColumn(usize, String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThemeError::IoError(ref path, ref e) => write!(f, "Theme {:?}: {}", path, e),
+            ThemeError::MalformedLine(line_number, ref line) => write!(f, "Malformed theme line {}: {:?}", line_number, line),
+            ThemeError::UnknownColumn(line_number, ref column) => write!(f, "Unknown column on theme line {}: {:?}", line_number, column),
+        }
+    }
+}
+
+impl error::Error for ThemeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ThemeError::IoError(_, ref e) => Some(e),
+            ThemeError::MalformedLine(_, _) => None,
+            ThemeError::UnknownColumn(_, _) => None,
+        }
+    }
+}
+
+//The columns of a row's first line in the report, shown in this order when chosen
+pub const COLUMNS: &[&str] = &["price", "symbol", "drug", "date", "phase"];
+
+//Presentation choices for the report, read from "key = value" lines:
+//  font = Helvetica, Arial, sans-serif
+//  color.phase3 = gold        (price color for a stage label, color.default for any other stage)
+//  columns = symbol, price, date
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Theme {
+    pub font_family: String,
+    phase_colors: BTreeMap<String, String>,
+    default_color: String,
+    columns: Vec<String>,
+}
+
+impl Default for Theme {
+    //The report as it looked before themes, earlier stages are riskier so their prices stand out more
+    fn default() -> Theme {
+        let mut phase_colors = BTreeMap::new();
+        phase_colors.insert("phase1".to_string(), "crimson".to_string());
+        phase_colors.insert("phase1.5".to_string(), "crimson".to_string());
+        phase_colors.insert("phase2".to_string(), "darkorange".to_string());
+        phase_colors.insert("phase3".to_string(), "gold".to_string());
+        Theme {
+            font_family: "sans-serif".to_string(),
+            phase_colors,
+            default_color: "black".to_string(),
+            columns: COLUMNS.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn load(theme_path: &Path) -> Result<Theme, ThemeError> {
+        fs::read_to_string(theme_path)
+            .map_err(|x| ThemeError::IoError(theme_path.to_owned(), x))
+            .and_then(|contents| Theme::parse(&contents))
+    }
+
+    //Anything not given keeps its default
+    pub fn parse(contents: &str) -> Result<Theme, ThemeError> {
+        let mut theme = Theme::default();
+        for (index, a_line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            //Colors can start with #, so only whole lines are comments
+            let a_line = a_line.trim();
+            if a_line.is_empty() || a_line.starts_with('#') {
+                continue;
+            }
+
+            let mut split = a_line.splitn(2, '=');
+            let key = split.next().unwrap_or("").trim();
+            let value = match split.next().map(str::trim) {
+                Some(value) if !value.is_empty() => value,
+                _ => return Err(ThemeError::MalformedLine(line_number, a_line.to_owned()))
+            };
+
+            match key {
+                "font" => theme.font_family = value.to_string(),
+                "color.default" => theme.default_color = value.to_string(),
+                "columns" => {
+                    theme.columns = Vec::new();
+                    for column in value.split(',').map(|x| x.trim().to_lowercase()) {
+                        if !COLUMNS.contains(&column.as_str()) {
+                            return Err(ThemeError::UnknownColumn(line_number, column));
+                        }
+                        theme.columns.push(column);
+                    }
+                },
+                _ if key.starts_with("color.") => {
+                    theme.phase_colors.insert(key["color.".len()..].to_string(), value.to_string());
+                },
+                _ => return Err(ThemeError::MalformedLine(line_number, a_line.to_owned()))
+            }
+        }
+        Ok(theme)
+    }
+
+    pub fn phase_color(&self, phase: &str) -> &str {
+        self.phase_colors.get(phase).unwrap_or(&self.default_color)
+    }
+
+    pub fn shows(&self, column: &str) -> bool {
+        self.columns.iter().any(|x| x == column)
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_theme() {
+        let theme = Theme::parse("# house style\nfont = Georgia, serif\ncolor.phase3 = #0a0\ncolor.default = gray\ncolumns = Symbol, price, date").unwrap();
+        assert_eq!(theme.font_family, "Georgia, serif");
+        assert_eq!(theme.phase_color("phase3"), "#0a0");
+        assert_eq!(theme.phase_color("phase1"), "crimson");
+        assert_eq!(theme.phase_color("approved"), "gray");
+        assert_eq!(theme.columns(), &["symbol".to_string(), "price".to_string(), "date".to_string()]);
+        assert!(!theme.shows("drug"));
+    }
+
+    #[test]
+    fn malformed_theme() {
+        match Theme::parse("font = serif\ncolumns = price, volume") {
+            Err(ThemeError::UnknownColumn(2, ref column)) if column == "volume" => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+        match Theme::parse("font") {
+            Err(ThemeError::MalformedLine(1, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+        match Theme::parse("background = blue") {
+            Err(ThemeError::MalformedLine(1, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+}
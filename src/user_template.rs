@@ -0,0 +1,273 @@
+use handlebars::{Handlebars, handlebars_helper};
+use serde::Serialize;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::fda_scraper::GroupedRow;
+use super::report::Report;
+
+#[derive(Debug)]
+pub enum UserTemplateError {
+    IoError(PathBuf, io::Error),
+    //Boxed as handlebars' errors are large enough to bloat every Result carrying them
+    TemplateError(Box<handlebars::TemplateError>),
+    RenderError(Box<handlebars::RenderError>),
+}
+
+impl From<handlebars::TemplateError> for UserTemplateError {
+    fn from(e: handlebars::TemplateError) -> Self {
+        UserTemplateError::TemplateError(Box::new(e))
+    }
+}
+
+impl From<handlebars::RenderError> for UserTemplateError {
+    fn from(e: handlebars::RenderError) -> Self {
+        UserTemplateError::RenderError(Box::new(e))
+    }
+}
+
+impl fmt::Display for UserTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UserTemplateError::IoError(ref path, ref e) => write!(f, "Template {:?}: {}", path, e),
+            UserTemplateError::TemplateError(ref e) => std::fmt::Display::fmt(&e, f),
+            UserTemplateError::RenderError(ref e) => std::fmt::Display::fmt(&e, f),
+        }
+    }
+}
+
+impl error::Error for UserTemplateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            UserTemplateError::IoError(_, ref e) => Some(e),
+            UserTemplateError::TemplateError(ref e) => Some(e.as_ref()),
+            UserTemplateError::RenderError(ref e) => Some(e.as_ref()),
+        }
+    }
+}
+
+//What a user template is rendered with, see resources/example_report.hbs for a template using all of it.
+//Everything is already formatted as text, and optional values are left out when missing so {{#if}} can test for them
+#[derive(Debug, Serialize)]
+pub struct ReportContext<'a> {
+    //e.g. "3 catalysts, next PDUFA GWPH 2019-05-03"
+    pub summary: String,
+    pub theme: ThemeContext<'a>,
+    pub holdings: Vec<HoldingContext>,
+    pub clustered_companies: Vec<CompanyContext>,
+    //In the report's grouping, a single unlabelled group when it isn't grouped
+    pub groups: Vec<GroupContext>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThemeContext<'a> {
+    pub font_family: &'a str,
+    //Some of "price", "symbol", "drug", "date" and "phase", in the order chosen
+    pub columns: &'a [String],
+    pub column_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldingContext {
+    pub symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<u64>,
+    pub price: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub market_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unrealized_pl: Option<String>,
+    //"drug (date)", soonest first
+    pub catalysts: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompanyContext {
+    pub symbol: String,
+    pub url: String,
+    pub catalyst_count: usize,
+    pub nearest_date: String,
+    pub highest_phase: String,
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub rows: Vec<RowContext>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowContext {
+    pub symbol: String,
+    pub url: String,
+    pub price: String,
+    //From the theme's colors for the row's stage
+    pub price_color: String,
+    pub drug_name: String,
+    pub drug_indication: String,
+    pub therapeutic_areas: Vec<String>,
+    pub catalyst_date: String,
+    //Display text, e.g. "Phase 1/2"
+    pub phase: String,
+    //The calendar's stage label, e.g. "phase1.5"
+    pub phase_label: String,
+    pub catalyst_note: String,
+    pub note_events: Vec<String>,
+    pub in_portfolio: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
+    //The img src of the row's price history, a cid: url for the image sent with the report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparkline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub company: Option<CompanyProfileContext>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompanyProfileContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cash_runway: Option<String>,
+    pub pipeline_programs: usize,
+}
+
+impl<'a> ReportContext<'a> {
+    pub fn new(report: &'a Report) -> ReportContext<'a> {
+        let theme = report.theme();
+        ReportContext {
+            summary: report.summary_line(),
+            theme: ThemeContext { font_family: &theme.font_family, columns: theme.columns(), column_count: theme.columns().len() },
+            holdings: report.holdings().iter()
+                .map(|x| HoldingContext {
+                    symbol: x.symbol.clone(),
+                    shares: x.position.shares,
+                    price: x.price.to_string(),
+                    market_value: x.market_value.as_ref().map(|x| x.to_string()),
+                    unrealized_pl: x.unrealized_pl.as_ref().map(|x| x.to_string()),
+                    catalysts: x.catalysts.iter().map(|(date, drug)| format!("{} ({})", drug, date)).collect(),
+                })
+                .collect(),
+            clustered_companies: report.clustered_companies().into_iter()
+                .map(|x| CompanyContext {
+                    symbol: x.symbol.to_string(),
+                    url: x.url.to_string(),
+                    catalyst_count: x.catalyst_count,
+                    nearest_date: x.nearest_date.to_string(),
+                    highest_phase: x.highest_phase.to_string(),
+                    notes: x.notes,
+                })
+                .collect(),
+            groups: report.groups().into_iter()
+                .map(|x| GroupContext {
+                    label: x.label,
+                    rows: x.rows.iter().map(|x| RowContext::new(x, report)).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RowContext {
+    fn new(grouped_row: &GroupedRow, report: &Report) -> RowContext {
+        let row = grouped_row.row;
+        RowContext {
+            symbol: row.symbol.clone(),
+            url: row.url.clone(),
+            price: row.price.to_string(),
+            price_color: report.theme().phase_color(grouped_row.phase).to_string(),
+            drug_name: row.drug_name.clone(),
+            drug_indication: row.drug_indication.clone(),
+            therapeutic_areas: row.therapeutic_areas.clone(),
+            catalyst_date: row.catalyst_date.to_string(),
+            phase: row.phase.clone(),
+            phase_label: grouped_row.phase.to_string(),
+            catalyst_note: row.catalyst_note.clone(),
+            note_events: row.note_events.iter().map(|x| x.to_string()).collect(),
+            in_portfolio: row.in_portfolio,
+            score: row.score,
+            sparkline: report.sparkline_cid(&row.symbol).map(|x| format!("cid:{}", x)),
+            company: row.company.as_ref().map(|x| CompanyProfileContext {
+                cash: x.cash.as_ref().map(|x| x.to_string()),
+                cash_runway: x.cash_runway.map(|x| x.to_string()),
+                pipeline_programs: x.pipeline.len(),
+            }),
+        }
+    }
+}
+
+//A handlebars template loaded at runtime in place of the compiled in report
+pub struct UserTemplate {
+    registry: Handlebars,
+}
+
+const TEMPLATE_NAME: &str = "report";
+
+//The built in eq only compares numbers, columns are compared as strings
+handlebars_helper!(json_eq: |x: Json, y: Json| x == y);
+
+impl UserTemplate {
+    pub fn load(template_path: &Path) -> Result<UserTemplate, UserTemplateError> {
+        fs::read_to_string(template_path)
+            .map_err(|x| UserTemplateError::IoError(template_path.to_owned(), x))
+            .and_then(|source| UserTemplate::parse(&source))
+    }
+
+    pub fn parse(source: &str) -> Result<UserTemplate, UserTemplateError> {
+        let mut registry = Handlebars::new();
+        //Misspelt fields fail the render instead of quietly rendering as nothing
+        registry.set_strict_mode(true);
+        registry.register_helper("eq", Box::new(json_eq));
+        registry.register_template_string(TEMPLATE_NAME, source)?;
+        Ok(UserTemplate { registry })
+    }
+
+    pub fn render(&self, report: &Report) -> Result<String, UserTemplateError> {
+        Ok(self.registry.render(TEMPLATE_NAME, &ReportContext::new(report))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates, ScrapedCatalysts};
+    use crate::watchlist::Watchlist;
+    use crate::theme::Theme;
+
+    fn scraped() -> ScrapedCatalysts {
+        fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_clustered.html"), ScrapePredicates::new()).unwrap()
+    }
+
+    #[test]
+    fn render_example_template() {
+        let scraped = scraped();
+        let report = Report::new(&scraped)
+            .set_holdings(&Watchlist::parse("BTX,1000,$1.50").unwrap())
+            .set_theme(Theme::parse("font = Georgia\ncolor.phase3 = #0a0\ncolumns = symbol, price").unwrap());
+        let rendered = UserTemplate::load(Path::new("resources/example_report.hbs")).unwrap().render(&report).unwrap();
+
+        assert!(rendered.contains("<h1>4 catalysts, next BTX 2019-05-02</h1>"), "{}", rendered);
+        assert!(rendered.contains("font-family:Georgia"));
+        assert!(rendered.contains("BTX: 1000 shares, P/L -$240.00"));
+        assert!(rendered.contains("GWPH</a> has 2 catalysts"));
+        assert!(rendered.contains(r#"<td style="color:#0a0">$6.00</td>"#));
+        assert!(!rendered.contains("MicroStat</td>"));
+    }
+
+    #[test]
+    fn strict_templates() {
+        let template = UserTemplate::parse("{{#each groups}}{{#each rows}}{{symbl}}{{/each}}{{/each}}").unwrap();
+        match template.render(&Report::new(&scraped())) {
+            Err(UserTemplateError::RenderError(_)) => (),
+            x => panic!("Unexpected result {:?}", x.map(|_| ()))
+        }
+        match UserTemplate::parse("{{#each groups}}") {
+            Err(UserTemplateError::TemplateError(_)) => (),
+            x => panic!("Unexpected result {:?}", x.map(|_| ()))
+        }
+    }
+}
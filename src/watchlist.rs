@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::error;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::currency;
+use super::catalyst_date::CatalystDate;
+use super::fda_scraper::ScrapedCatalysts;
+
+#[derive(Debug)]
+pub enum WatchlistError {
+    IoError(PathBuf, io::Error),
+    MalformedLine(usize, String),
+    CurrencyParseError(usize, currency::USDParseError),
+}
+
+impl fmt::Display for WatchlistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchlistError::IoError(ref path, ref e) => write!(f, "Watchlist {:?}: {}", path, e),
+            WatchlistError::MalformedLine(line_number, ref line) => write!(f, "Malformed watchlist line {}: {:?}", line_number, line),
+            WatchlistError::CurrencyParseError(line_number, ref e) => write!(f, "Watchlist line {}: {}", line_number, e),
+        }
+    }
+}
+
+impl error::Error for WatchlistError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            WatchlistError::IoError(_, ref e) => Some(e),
+            WatchlistError::MalformedLine(_, _) => None,
+            WatchlistError::CurrencyParseError(_, ref e) => Some(e),
+        }
+    }
+}
+
+//A symbol that's only being watched has no shares or cost basis
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Position {
+    pub shares: Option<u64>,
+    //Per share
+    pub cost_basis: Option<currency::USD>,
+}
+
+//One symbol per line as SYMBOL[,shares[,cost basis per share]], e.g. "BTX,1000,$1.50". Blank lines and # comments are skipped
+#[derive(Debug, Eq, PartialEq)]
+pub struct Watchlist {
+    positions: BTreeMap<String, Position>,
+}
+
+impl Watchlist {
+    pub fn load(watchlist_path: &Path) -> Result<Watchlist, WatchlistError> {
+        fs::read_to_string(watchlist_path)
+            .map_err(|x| WatchlistError::IoError(watchlist_path.to_owned(), x))
+            .and_then(|contents| Watchlist::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Result<Watchlist, WatchlistError> {
+        let mut positions = BTreeMap::new();
+        for (index, a_line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let a_line = a_line.split('#').next().unwrap_or("").trim();
+            if a_line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = a_line.split(',').map(str::trim).collect();
+            let malformed = || WatchlistError::MalformedLine(line_number, a_line.to_owned());
+            if fields.len() > 3 || fields[0].is_empty() {
+                return Err(malformed());
+            }
+            let shares = match fields.get(1) {
+                Some(shares) => Some(shares.parse().map_err(|_| malformed())?),
+                None => None
+            };
+            let cost_basis = match fields.get(2) {
+                Some(cost_basis) => Some(currency::USD::new(cost_basis).map_err(|x| WatchlistError::CurrencyParseError(line_number, x))?),
+                None => None
+            };
+            positions.insert(fields[0].to_uppercase(), Position { shares, cost_basis });
+        }
+        Ok(Watchlist { positions })
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        self.positions.keys().cloned().collect()
+    }
+
+    //Holdings for every watchlisted symbol with a catalyst in the scrape, in symbol order
+    pub fn holdings(&self, catalysts: &ScrapedCatalysts) -> Vec<Holding> {
+        self.positions.iter()
+            .filter_map(|(symbol, position)| {
+                let mut rows: Vec<_> = catalysts.rows().filter(|x| &x.symbol == symbol).collect();
+                rows.sort_by_key(|x| x.catalyst_date);
+                let price = rows.first()?.price.clone();
+
+                //Left out rather than wrong for a position too large to value
+                let market_value = position.shares.and_then(|shares| &price * shares);
+                let unrealized_pl = match (&market_value, position.shares, &position.cost_basis) {
+                    (Some(market_value), Some(shares), Some(cost_basis)) => (cost_basis * shares).and_then(|cost| market_value - &cost),
+                    _ => None
+                };
+                Some(Holding {
+                    symbol: symbol.clone(),
+                    position: position.clone(),
+                    price,
+                    market_value,
+                    unrealized_pl,
+                    catalysts: rows.iter().map(|x| (x.catalyst_date, x.drug_name.clone())).collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Holding {
+    pub symbol: String,
+    pub position: Position,
+    pub price: currency::USD,
+    pub market_value: Option<currency::USD>,
+    pub unrealized_pl: Option<currency::USD>,
+    //(catalyst date, drug name), soonest first
+    pub catalysts: Vec<(CatalystDate, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fda_scraper::{self, ScrapePredicates};
+    use chrono::NaiveDate;
+
+    fn usd(amount: &str) -> currency::USD {
+        currency::USD::new(amount).unwrap()
+    }
+
+    #[test]
+    fn parse_watchlist() {
+        let watchlist = Watchlist::load(Path::new("test-resources/watchlist.csv")).unwrap();
+        assert_eq!(watchlist.symbols(), vec!["BTX".to_string(), "EYEN".to_string(), "GWPH".to_string()]);
+        assert_eq!(watchlist.positions["BTX"], Position { shares: Some(1000), cost_basis: Some(usd("$1.50")) });
+        assert_eq!(watchlist.positions["GWPH"], Position { shares: Some(10), cost_basis: None });
+        assert_eq!(watchlist.positions["EYEN"], Position { shares: None, cost_basis: None });
+    }
+
+    #[test]
+    fn malformed_watchlist() {
+        match Watchlist::parse("BTX,1000,$1.50\nGWPH,ten") {
+            Err(WatchlistError::MalformedLine(2, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+        match Watchlist::parse("BTX,1000,1.50") {
+            Err(WatchlistError::CurrencyParseError(1, _)) => (),
+            x => panic!("Unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn holdings_with_upcoming_catalysts() {
+        let watchlist = Watchlist::parse("btx,1000,$1.50\nGWPH,10\nEYEN\nABCD,5,$2.00").unwrap();
+        let catalysts = fda_scraper::parse_rows(Path::new("test-resources/fda_calendar_sample_files/fda_calendar_multiple_rows.html"), ScrapePredicates::new()).unwrap();
+        let may_2 = CatalystDate::exact(NaiveDate::from_ymd(2019, 5, 2));
+        let may_3 = CatalystDate::exact(NaiveDate::from_ymd(2019, 5, 3));
+
+        //ABCD has nothing coming up so isn't a holding with a catalyst
+        assert_eq!(watchlist.holdings(&catalysts), vec![
+            Holding {
+                symbol: "BTX".to_string(),
+                position: Position { shares: Some(1000), cost_basis: Some(usd("$1.50")) },
+                price: usd("$1.26"),
+                market_value: Some(usd("$1260")),
+                unrealized_pl: Some(usd("-$240")),
+                catalysts: vec![(may_2, "OpRegen".to_string())],
+            },
+            Holding {
+                symbol: "EYEN".to_string(),
+                position: Position { shares: None, cost_basis: None },
+                price: usd("$6.00"),
+                market_value: None,
+                unrealized_pl: None,
+                catalysts: vec![(may_3, "MicroStat".to_string())],
+            },
+            Holding {
+                symbol: "GWPH".to_string(),
+                position: Position { shares: Some(10), cost_basis: None },
+                price: usd("$173.16"),
+                market_value: Some(usd("$1731.60")),
+                unrealized_pl: None,
+                catalysts: vec![(may_3, "Epidiolex GWPCARE2".to_string())],
+            },
+        ]);
+    }
+}